@@ -1,14 +1,28 @@
 use clap::Parser;
 use std::path::PathBuf;
-use std::io::Write;
+use std::process::ExitCode;
 use rpassword::read_password;
+use zeroize::Zeroizing;
 use colored::*; // Para output bonito
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use qrfs_lib::device::BlockDevice;
-use qrfs_lib::crypto::CryptoEngine;
-use qrfs_lib::types::{SuperBlock, Inode, QRFS_MAGIC};
+use qrfs_lib::crypto::{CryptoEngine, VolumeHeader};
+use qrfs_lib::types::{SuperBlock, Inode, FileType, DirEntry, CHUNK_SIZE, SYMLINK_INLINE_MAX};
+use qrfs_lib::compress::{self, Compression};
+use qrfs_lib::xattr::{self, Xattr, XATTR_BLOCK_MAX, XATTR_INLINE_MAX, XATTR_NAME_MAX};
 use qrfs_lib::bitmap::Bitmap;
+use qrfs_lib::refcount::RefCounts;
+use qrfs_lib::dedup::ChunkIndex;
+use qrfs_lib::journal::Journal;
+use qrfs_lib::snapshot::{self, Snapshot};
+use qrfs_lib::scrub;
+use qrfs_lib::volume;
+
+mod repair;
+mod report;
+use repair::{Repair, LOST_FOUND};
+use report::{Finding, Format, Report, Severity, EXIT_CLEAN, EXIT_FAILED, EXIT_FIXED, EXIT_UNCORRECTED};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -16,130 +30,963 @@ struct Args {
     /// Carpeta donde están los QRs
     #[arg(value_name = "QR_FOLDER")]
     path: PathBuf,
+
+    /// Reparar lo que se pueda: reconstruye los bitmaps y los contadores de
+    /// referencias, limpia punteros fuera de rango, reengancha los inodos huérfanos
+    /// en /lost+found y corrige los contadores del superbloque (y el bloque 0
+    /// ilegible, si hay una copia .bak)
+    #[arg(short = 'y', long, visible_short_alias = 'r')]
+    repair: bool,
+
+    /// Mostrar lo que --repair corregiría, sin escribir nada
+    #[arg(long, conflicts_with = "repair")]
+    dry_run: bool,
+
+    /// Además, leer y descifrar cada bloque ocupado (lento: ver qrfs_scrub)
+    #[arg(long)]
+    scrub: bool,
+
+    /// Formato del reporte. Con json, stdout es solo el resumen final.
+    /// Código de salida: 0 sano, 1 reparado, 4 quedan errores o advertencias (o
+    /// correcciones pendientes, con --dry-run), 8 no se pudo revisar.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+/// Lo que encontró la revisión (la reparación parte de acá)
+struct Check {
+    inode_list: Vec<Inode>,
+    stored_bitmap: Bitmap,
+    inode_bitmap: Bitmap,
+    refs: RefCounts,
+    chunk_index: ChunkIndex,
+    snapshots: Vec<Snapshot>,
+    // Bloques que de verdad están en uso, y cuántos punteros tiene cada uno
+    calculated_used_blocks: HashSet<u64>,
+    block_refs: HashMap<u64, u64>,
+    dir_entries: BTreeMap<u64, Vec<DirEntry>>,
+    dirs_with_bad_entries: BTreeSet<u64>,
+    links: BTreeMap<u64, (u32, u32)>,
 }
 
-fn main() -> anyhow::Result<()> {
+fn main() -> ExitCode {
     let args = Args::parse();
-    println!("{}", "=== QRFS File System Check (fsck) ===".bold().blue());
+    let mut report = Report::new(args.format);
+    report.info("=== QRFS File System Check (fsck) ===".bold().blue());
 
+    let (code, remaining, failure) = match run(&args, &mut report) {
+        Ok((code, remaining)) => (code, remaining, None),
+        Err(e) => {
+            if args.format == Format::Text {
+                println!("{}", format!("[FAIL] {:#}", e).red());
+            }
+            (EXIT_FAILED, Vec::new(), Some(e))
+        }
+    };
+    report.finish(code, &remaining, failure.as_ref());
+    ExitCode::from(code)
+}
+
+/// Revisa (y repara) el volumen; devuelve el código de salida y los errores
+/// que quedaron sin corregir
+fn run(args: &Args, report: &mut Report) -> anyhow::Result<(u8, Vec<Finding>)> {
     // 1. Validar acceso al dispositivo
     if !args.path.exists() {
         anyhow::bail!("La carpeta no existe");
     }
     let device = BlockDevice::new(&args.path)?;
-    println!("[*] Dispositivo encontrado en {:?}", args.path);
-
-    // 2. Autenticación
-    print!("Passphrase: ");
-    std::io::stdout().flush()?;
-    let password = read_password()?;
-
-    // 3. Leer Bloque 0 (Superbloque)
-    println!("[*] Leyendo Superbloque...");
-    let block0 = device.read_block(0)?;
-    if block0.len() < 16 {
-        println!("{}", "[FAIL] Bloque 0 corrupto o ilegible".red());
-        return Ok(());
-    }
-    let (salt, encrypted_sb) = block0.split_at(16);
-    let mut salt_arr = [0u8; 16];
-    salt_arr.copy_from_slice(salt);
-
-    let crypto = CryptoEngine::new(&password, salt_arr);
-    
+    report.info(format!("[*] Dispositivo encontrado en {:?}", args.path));
+
+    // 2. Leer Bloque 0 (Cabecera + Superbloque)
+    report.info("[*] Leyendo Superbloque...");
+    let mut block0 = device.read_block(0);
+    if block0.as_ref().map_or(true, |b| VolumeHeader::parse(b).is_err()) {
+        // Con --keep-backups, qrfs_mount deja la versión anterior del bloque
+        if args.repair && device.restore_backup(0)? {
+            report.fix(Finding::new("Bloque 0 ilegible: restaurado desde qr_00000.png.bak").block(0), false);
+            block0 = device.read_block(0);
+        }
+    }
+    let block0 = block0.map_err(|e| anyhow::anyhow!("Bloque 0 ilegible: {}", e))?;
+    let (header, encrypted_sb) = VolumeHeader::parse(&block0)?;
+
+    // 3. Autenticación (solo si el volumen está cifrado)
+    let password = if header.needs_passphrase() {
+        report.prompt("Passphrase: ")?;
+        Zeroizing::new(read_password()?)
+    } else {
+        report.info("[*] Volumen sin cifrar (cifrado nulo)");
+        Zeroizing::new(String::new())
+    };
+
+    let crypto = CryptoEngine::from_header(&header, &password);
+    drop(password);
+
     // Intentar descifrar
-    let sb_bytes = match crypto.decrypt(encrypted_sb) {
-        Ok(b) => b,
-        Err(_) => {
-            println!("{}", "[FAIL] No se pudo descifrar el Superbloque. ¿Contraseña incorrecta?".red());
-            return Ok(());
+    let mut sb = volume::read_superblock(&crypto, encrypted_sb)?;
+    report.info("[OK] Firma QRFS válida (Magic Number correcto)".green());
+    report.info(format!("    > Total Blocks: {}", sb.total_blocks));
+    report.info(format!("    > Inodes: {}", sb.total_inodes));
+
+    // 3b. Un volumen que no se desmontó limpio puede tener una transacción
+    // confirmada en el journal sin aplicar: se rehace antes de revisar nada
+    // (como e2fsck, esto se hace siempre: sin el journal el volumen no es consistente)
+    if sb.journal_needs_recovery && args.dry_run {
+        report.fix(Finding::new("El volumen no se desmontó limpio: se rehará el journal (lo que sigue es el estado sin rehacer)"), true);
+    } else if sb.journal_needs_recovery {
+        report.info("[*] El volumen no se desmontó limpio: revisando el journal...");
+        let (journal, last) = Journal::recover(&sb, &device, &crypto)?;
+        match last {
+            Some(txn) => {
+                txn.apply(&device, &crypto)?;
+                report.fix(Finding::new(format!("Transacción {} rehecha ({} bloques)", journal.seq(), txn.len())), false);
+            }
+            None => report.info("    > Nada que rehacer (la última transacción no llegó a confirmarse)"),
+        }
+        sb.journal_seq = journal.seq();
+        sb.journal_head = journal.head();
+        sb.journal_needs_recovery = false;
+        volume::write_superblock(&device, &crypto, &sb)?;
+    }
+
+    // 4-7. Revisión
+    let check = check_volume(&device, &crypto, &sb, report)?;
+
+    // 6b. Scrub: que cada imagen ocupada exista, se lea y autentique.
+    // Ninguna reparación lo arregla: lo encontrado sigue pendiente después.
+    let mut unreadable = Vec::new();
+    if args.scrub {
+        report.info("[*] Leyendo todas las imágenes (scrub)...");
+        let blocks = scrub::allocated_blocks(&check.stored_bitmap, sb.total_blocks);
+        let owners = scrub::block_owners(&device, &crypto, &sb);
+        let jobs = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        for found in scrub::scrub(&device, &crypto, &sb, &blocks, &owners, jobs) {
+            let finding = Finding::new(format!("Bloque {} ({}) {}: {}", found.block, found.owner, found.status.label(), found.detail))
+                .block(found.block);
+            unreadable.push(finding.clone());
+            report.error(finding);
         }
+    }
+    let errors = report.count(Severity::Error);
+    let warnings = report.count(Severity::Warning);
+
+    // 8. Reparación: --repair escribe lo corregido, --dry-run solo muestra lo que haría
+    let mut repaired = 0;
+    if args.repair || args.dry_run {
+        report.info(format!("[*] {}...", if args.dry_run { "Simulando la reparación" } else { "Reparando" }));
+        repaired = repair_volume(&device, &crypto, sb, check, args.dry_run, report)?;
+    }
+    let fixes = report.count(Severity::Fixed) + report.count(Severity::Pending);
+
+    // 9. Después de reparar se revisa de nuevo (sin imprimir): lo que sigue mal
+    // es lo que la reparación no sabe corregir. Las advertencias también cuentan:
+    // un volumen con bloques perdidos o contadores viejos no está sano.
+    let unsound = |f: &Finding| matches!(f.severity, Severity::Error | Severity::Warning);
+    let remaining: Vec<Finding> = if args.repair && repaired > 0 {
+        let block0 = device.read_block(0)?;
+        let (_, encrypted_sb) = VolumeHeader::parse(&block0)?;
+        let mut recheck = Report::quiet();
+        check_volume(&device, &crypto, &volume::read_superblock(&crypto, encrypted_sb)?, &mut recheck)?;
+        recheck.findings.into_iter().filter(unsound).chain(unreadable).collect()
+    } else {
+        report.findings.iter().filter(|f| unsound(f)).cloned().collect()
     };
 
-    let sb: SuperBlock = bincode::deserialize(&sb_bytes)?;
-    
-    // Verificar Magic Number
-    if sb.magic == QRFS_MAGIC {
-        println!("{}", "[OK] Firma QRFS válida (Magic Number correcto)".green());
+    let code = if !remaining.is_empty() {
+        if args.dry_run {
+            report.info(format!("\n{} {} correcciones pendientes (ejecute con --repair para aplicarlas).", ">> DRY-RUN:".bold().magenta(), fixes));
+        } else if args.repair {
+            report.info(format!(
+                "\n{} Se aplicaron {} correcciones, pero quedan {} errores sin corregir:",
+                ">> PRECAUCIÓN:".bold().red(), fixes, remaining.len()
+            ));
+            for finding in &remaining {
+                report.info(format!("    {} {}", "[PENDIENTE]".red(), finding.message));
+            }
+        } else if errors > 0 {
+            report.info(format!("\n{} Se encontraron {} errores graves y {} advertencias.", ">> PRECAUCIÓN:".bold().red(), errors, warnings));
+        } else {
+            report.info(format!("\n{} Se encontraron {} advertencias (ejecute con --repair para corregirlas).", ">> ATENCIÓN:".bold().yellow(), warnings));
+        }
+        EXIT_UNCORRECTED
+    } else if fixes == 0 {
+        report.info(format!("\n{}", ">> EL SISTEMA DE ARCHIVOS ESTÁ SANO".bold().green()));
+        EXIT_CLEAN
+    } else if args.dry_run {
+        // Lo que --repair corregiría también es algo que está mal
+        report.info(format!("\n{} {} correcciones pendientes (ejecute con --repair para aplicarlas).", ">> DRY-RUN:".bold().magenta(), fixes));
+        EXIT_UNCORRECTED
     } else {
-        println!("{}", "[FAIL] Firma inválida. No es un sistema QRFS".red());
-        return Ok(());
+        report.info(format!("\n{} Se aplicaron {} correcciones ({} errores graves encontrados).", ">> REPARADO:".bold().cyan(), fixes, errors));
+        EXIT_FIXED
+    };
+    Ok((code, remaining))
+}
+
+/// Pasos 4 a 7: lee los metadatos y recalcula todo desde los inodos
+fn check_volume(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock, report: &mut Report) -> anyhow::Result<Check> {
+    // 4. Leer y Verificar Bitmap
+    report.info("[*] Verificando Mapa de Bits...");
+    if sb.bitmap_blocks < Bitmap::pages_for(sb.total_blocks) || sb.inode_bitmap_blocks < Bitmap::pages_for(sb.total_inodes) {
+        anyhow::bail!("La región del bitmap es más chica que el volumen");
     }
+    let stored_bitmap = volume::load_bitmap(device, crypto, sb)?;
+    report.info(format!("[OK] Bitmap descifrado y legible ({} páginas)", sb.bitmap_blocks).green());
 
-    println!("    > Total Blocks: {}", sb.total_blocks);
-    println!("    > Inodes: {}", sb.total_inodes);
+    let inode_bitmap = volume::load_inode_bitmap(device, crypto, sb, |block| block)?;
+    report.info("[OK] Bitmap de inodos descifrado y legible".green());
 
-    // 4. Leer y Verificar Bitmap
-    println!("[*] Verificando Mapa de Bits...");
-    let enc_bitmap = device.read_block(sb.bitmap_start)?;
-    let bitmap_bytes = crypto.decrypt(&enc_bitmap)?;
-    let stored_bitmap: Bitmap = bincode::deserialize(&bitmap_bytes)?;
-    println!("{}", "[OK] Bitmap descifrado y legible".green());
+    // 4b. Leer contadores de referencias e índice de dedup
+    let refs = volume::load_refcounts(device, crypto, sb)?;
+    let chunk_index = volume::load_chunk_index(device, crypto, sb)?;
+    if sb.dedup_index_blocks > 0 {
+        report.info(format!("    > Dedup activo: {} fragmentos indexados", chunk_index.entries().count()));
+    }
 
     // 5. Analizar Inodos y Recalcular Bitmap Real
-    println!("[*] Analizando Tabla de Inodos...");
-    let enc_inodes = device.read_block(sb.inode_table_start)?;
-    let inodes_bytes = crypto.decrypt(&enc_inodes)?;
-    let inode_list: Vec<Inode> = bincode::deserialize(&inodes_bytes)?;
+    report.info("[*] Analizando Tabla de Inodos...");
+    let inode_list = volume::load_inode_table(device, crypto, sb, |block| block)?;
 
     // Vamos a reconstruir qué bloques están REALMENTE en uso
     let mut calculated_used_blocks = HashSet::new();
-    
+
     // Agregamos bloques de metadatos que sabemos que existen
     calculated_used_blocks.insert(0); // Superbloque
-    calculated_used_blocks.insert(sb.bitmap_start); // Bitmap
-    calculated_used_blocks.insert(sb.inode_table_start); // Tabla inodos (simplificado a 1 bloque)
+    calculated_used_blocks.extend(sb.bitmap_start..sb.bitmap_start + sb.bitmap_blocks); // Bitmap
+    calculated_used_blocks.extend(sb.inode_bitmap_start..sb.inode_bitmap_start + sb.inode_bitmap_blocks); // Bitmap de inodos
+    calculated_used_blocks.extend(sb.journal_start..sb.journal_start + sb.journal_blocks); // Journal
+    calculated_used_blocks.extend(sb.snapshot_start..sb.snapshot_start + sb.snapshot_blocks); // Catálogo de snapshots
+    calculated_used_blocks.extend(sb.inode_table_start..sb.inode_table_start + sb.inode_table_blocks()); // Tabla inodos
+    calculated_used_blocks.extend(sb.refcount_start..sb.refcount_start + sb.refcount_blocks);
+    calculated_used_blocks.extend(sb.dedup_index_start..sb.dedup_index_start + sb.dedup_index_blocks);
+    let metadata_blocks = calculated_used_blocks.clone();
+
+    // Cuántos punteros de inodos apuntan a cada bloque de datos (dedup = más de uno)
+    let mut block_refs: HashMap<u64, u64> = HashMap::new();
+    // Qué inodos del volumen vivo reclaman cada bloque (y si es dato de un archivo regular)
+    let mut claims: BTreeMap<u64, Vec<(usize, bool)>> = BTreeMap::new();
 
     let mut valid_inodes_count = 0;
+    // Para el reporte de compresión: bloques sin comprimir vs. bloques usados
+    let (mut raw_blocks, mut used_blocks) = (0, 0);
 
     for (idx, inode) in inode_list.iter().enumerate() {
         // Si el inodo tiene modo 0, está "borrado" o vacío
         if inode.mode != 0 {
             valid_inodes_count += 1;
-            
+            raw_blocks += inode.raw_blocks();
+            used_blocks += inode.used_blocks();
+
+            for problem in symlink_problems(inode).into_iter().chain(size_problems(inode, sb.compression)) {
+                report.error(Finding::new(format!("Inodo {}: {}", idx, problem)).inode(idx as u64));
+            }
+
+            // Atributos extendidos (y su bloque desbordado, si tiene)
+            if inode.xattr_block >= sb.total_blocks {
+                report.error(Finding::new(format!("Inodo {} tiene bloque de xattrs fuera de rango: {}", idx, inode.xattr_block))
+                    .inode(idx as u64).block(inode.xattr_block));
+            } else {
+                if inode.xattr_block != 0 {
+                    calculated_used_blocks.insert(inode.xattr_block);
+                    *block_refs.entry(inode.xattr_block).or_insert(0) += 1;
+                    claims.entry(inode.xattr_block).or_default().push((idx, false));
+                }
+                match load_xattrs(device, crypto, inode) {
+                    Ok(xattrs) => {
+                        for problem in xattr_problems(inode, &xattrs) {
+                            report.error(Finding::new(format!("Inodo {}: {}", idx, problem)).inode(idx as u64));
+                        }
+                    }
+                    Err(e) => {
+                        report.error(Finding::new(format!("Inodo {}: xattrs ilegibles ({})", idx, e)).inode(idx as u64));
+                    }
+                }
+            }
+
             // Revisar sus bloques de datos
             for &block_id in inode.direct_blocks.iter() {
                 if block_id != 0 {
                     if block_id >= sb.total_blocks {
-                        println!("    {} Inodo {} apunta a bloque fuera de rango: {}", "[ERROR]".red(), idx, block_id);
+                        report.error(Finding::new(format!("Inodo {} apunta a bloque fuera de rango: {}", idx, block_id))
+                            .inode(idx as u64).block(block_id));
                     } else {
                         calculated_used_blocks.insert(block_id);
+                        *block_refs.entry(block_id).or_insert(0) += 1;
+                        claims.entry(block_id).or_default().push((idx, inode.file_type == FileType::File));
                     }
                 }
             }
         }
     }
 
-    println!("    > Inodos activos encontrados: {}", valid_inodes_count);
+    report.info(format!("    > Inodos activos encontrados: {}", valid_inodes_count));
+
+    // 5c. Snapshots: cada uno es una referencia más a los bloques que ve,
+    // y a las copias de la tabla de inodos o del bitmap de inodos que se le hicieron
+    let snapshots = snapshot::read_catalog(device, crypto, sb)?;
+    for snap in &snapshots {
+        report.info(format!("    > Snapshot '{}': {} bloques de metadatos copiados", snap.name, snap.moved.len()));
+        for &copy in snap.moved.values() {
+            if copy >= sb.total_blocks {
+                report.error(Finding::new(format!("Snapshot '{}' tiene una copia fuera de rango: {}", snap.name, copy)).block(copy));
+                continue;
+            }
+            calculated_used_blocks.insert(copy);
+            *block_refs.entry(copy).or_insert(0) += 1;
+        }
+        let snap_inodes = match volume::load_inode_table(device, crypto, sb, |block| snap.locate(block)) {
+            Ok(inodes) => inodes,
+            Err(e) => {
+                report.error(Finding::new(format!("Snapshot '{}': tabla de inodos ilegible ({})", snap.name, e)));
+                continue;
+            }
+        };
+        for inode in snap_inodes.iter().filter(|inode| inode.mode != 0) {
+            let blocks = inode.direct_blocks.iter().chain(std::iter::once(&inode.xattr_block));
+            for &block_id in blocks.filter(|&&b| b != 0 && b < sb.total_blocks) {
+                calculated_used_blocks.insert(block_id);
+                *block_refs.entry(block_id).or_insert(0) += 1;
+            }
+        }
+    }
+
+    // 5a. Bitmap de inodos vs. inodos realmente en uso (el 0 es el "nulo": siempre reservado)
+    let mut inodes_in_use = 1;
+    for (idx, inode) in inode_list.iter().enumerate() {
+        let in_use = inode.mode != 0;
+        if in_use { inodes_in_use += 1; }
+        if in_use && !inode_bitmap.get(idx) {
+            report.error(Finding::new(format!("Inodo {} está en uso pero marcado como LIBRE en el bitmap de inodos", idx)).inode(idx as u64));
+        } else if !in_use && idx != 0 && inode_bitmap.get(idx) {
+            report.warn(Finding::new(format!("Inodo {} marcado como ocupado pero está vacío (Huérfano)", idx)).inode(idx as u64));
+        }
+    }
+    report.info(format!("    > Inodos libres: {} de {}", sb.total_inodes.saturating_sub(inodes_in_use), sb.total_inodes));
+
+    // 5b. Contar enlaces: entradas de directorio que apuntan a cada inodo
+    report.info("[*] Verificando contadores de enlaces...");
+    let mut dir_entries: BTreeMap<u64, Vec<DirEntry>> = BTreeMap::new();
+    let mut dirs_with_bad_entries = BTreeSet::new();
+    for (idx, inode) in inode_list.iter().enumerate() {
+        if inode.mode == 0 || inode.file_type != FileType::Directory { continue; }
+        let entries: Vec<DirEntry> = match read_inode_data(device, crypto, inode) {
+            Ok(data) if data.is_empty() => Vec::new(),
+            Ok(data) => match bincode::deserialize(&data) {
+                Ok(entries) => entries,
+                Err(_) => {
+                    report.error(Finding::new(format!("Directorio {} con entradas ilegibles", idx)).inode(idx as u64));
+                    continue;
+                }
+            },
+            Err(e) => {
+                report.error(Finding::new(format!("Directorio {} ilegible: {}", idx, e)).inode(idx as u64));
+                continue;
+            }
+        };
+        for entry in &entries {
+            if inode_list.get(entry.inode_idx as usize).is_none_or(|child| child.mode == 0) {
+                report.error(Finding::new(format!(
+                    "Entrada '{}' del directorio {} apunta a inodo inexistente {}", entry.display_name(), idx, entry.inode_idx
+                )).inode(idx as u64));
+                dirs_with_bad_entries.insert(idx as u64);
+            }
+        }
+        dir_entries.insert(idx as u64, entries);
+    }
+    let links = expected_links(&inode_list, &dir_entries, sb.root_dir_inode);
+    for (&idx, &(refs_found, expected)) in &links {
+        if refs_found == 0 {
+            report.error(Finding::new(format!("Inodo {} no está en ningún directorio (huérfano)", idx)).inode(idx));
+        }
+        let nlink = inode_list[idx as usize].nlink;
+        if nlink != expected {
+            report.error(Finding::new(format!("Inodo {} tiene nlink {} pero se esperaban {}", idx, nlink, expected)).inode(idx));
+        }
+    }
+
+    // 5d. Árbol de directorios desde la raíz: nombres, ciclos, padres y alcance
+    report.info("[*] Recorriendo el árbol de directorios...");
+    let (reached, problems) = walk_tree(&inode_list, &dir_entries, sb.root_dir_inode);
+    for (dir, problem) in problems {
+        report.error(Finding::new(problem).inode(dir));
+    }
+    // (los que no tienen ninguna entrada ya se reportaron como huérfanos)
+    for (&idx, &(found, _)) in &links {
+        if found > 0 && !reached.contains(&idx) {
+            report.error(Finding::new(format!("Inodo {} inalcanzable desde la raíz", idx)).inode(idx));
+        }
+    }
+
+    // 5e. Bloques reclamados por más de un inodo: solo dedup comparte, y solo
+    // datos de archivos regulares (los snapshots no cuentan: no son inodos vivos).
+    // Ningún inodo puede reclamar un bloque de metadatos.
+    for (&block_id, owners) in &claims {
+        let inodes: Vec<usize> = owners.iter().map(|&(idx, _)| idx).collect();
+        if metadata_blocks.contains(&block_id) {
+            report.error(Finding::new(format!("Bloque de metadatos {} reclamado por los inodos {:?}", block_id, inodes))
+                .block(block_id).inode(inodes[0] as u64));
+        } else if owners.len() > 1 && !(sb.dedup_index_blocks > 0 && owners.iter().all(|&(_, shareable)| shareable)) {
+            report.error(Finding::new(format!("Bloque {} reclamado por los inodos {:?}", block_id, inodes))
+                .block(block_id).inode(inodes[0] as u64));
+        }
+    }
+    if used_blocks > 0 {
+        report.info(format!(
+            "    > Compresión: {:?} ({} QRs de datos, sin comprimir serían {}, ratio {:.2}x)",
+            sb.compression, used_blocks, raw_blocks, raw_blocks as f64 / used_blocks as f64
+        ));
+    }
 
     // 6. Comparación Final (Stored vs Calculated)
-    println!("[*] Buscando inconsistencias...");
-    let mut errors = 0;
+    report.info("[*] Buscando inconsistencias...");
 
     // Chequear Falsos Libres (El bitmap dice libre, pero un inodo lo usa) -> GRAVE
-    for &block_id in &calculated_used_blocks {
-        if !stored_bitmap.get(block_id as usize) {
-            println!("    {} Bloque {} está en uso por un archivo pero marcado como LIBRE en bitmap", "[CORRUPCIÓN]".red(), block_id);
-            errors += 1;
+    let mut falsely_free: Vec<u64> = calculated_used_blocks.iter().copied().filter(|&b| !stored_bitmap.get(b as usize)).collect();
+    falsely_free.sort_unstable();
+    for block_id in falsely_free {
+        report.error(Finding::new(format!("Bloque {} está en uso por un archivo pero marcado como LIBRE en bitmap", block_id)).block(block_id));
+    }
+
+    // Chequear contadores de referencias: N punteros => N-1 referencias extra
+    let mut counted: Vec<(u64, u64)> = block_refs.iter().map(|(&block, &count)| (block, count)).collect();
+    counted.sort_unstable();
+    for (block_id, count) in counted {
+        let stored = refs.shares(block_id) as u64;
+        if stored + 1 != count {
+            report.error(Finding::new(format!("Bloque {} tiene {} referencias pero su contador dice {}", block_id, count, stored + 1)).block(block_id));
+        }
+    }
+    for block_id in 0..sb.total_blocks {
+        if refs.shares(block_id) > 0 && !block_refs.contains_key(&block_id) {
+            report.warn(Finding::new(format!("Bloque {} sin referencias tiene contador de compartido", block_id)).block(block_id));
+        }
+    }
+
+    // Una entrada del índice que apunta a un bloque sin dueño haría que dedup
+    // comparta contenido equivocado
+    for entry in chunk_index.entries() {
+        if !block_refs.contains_key(&entry.block) {
+            report.error(Finding::new(format!("Índice de dedup apunta al bloque {} que no pertenece a ningún archivo", entry.block)).block(entry.block));
         }
     }
 
     // Chequear Falsos Ocupados (El bitmap dice ocupado, pero nadie lo usa) -> LEAK (Huérfano)
     // Recorremos todo el bitmap
     for i in 0..sb.total_blocks {
-        if stored_bitmap.get(i as usize) {
-            if !calculated_used_blocks.contains(&i) {
-                println!("    {} Bloque {} marcado como ocupado pero nadie lo usa (Huérfano)", "[WARN]".yellow(), i);
-                // Aquí podríamos ofrecer repararlo (fsck -r) poniendo el bit en 0
+        if stored_bitmap.get(i as usize) && !calculated_used_blocks.contains(&i) {
+            report.warn(Finding::new(format!("Bloque {} marcado como ocupado pero nadie lo usa (Huérfano)", i)).block(i));
+        }
+    }
+
+    // 7. Contadores de libres del superbloque vs. lo que dicen los bitmaps
+    // (qrfs_mount los guarda al sincronizar/desmontar: un corte los deja viejos)
+    let (free_blocks, free_inodes) = (stored_bitmap.free_count(), inode_bitmap.free_count());
+    report.info(format!("    > Libres: {} bloques, {} inodos", free_blocks, free_inodes));
+    if sb.free_blocks_count != free_blocks || sb.free_inodes_count != free_inodes {
+        report.warn(Finding::new(format!(
+            "El superbloque dice {} bloques y {} inodos libres (contados: {} y {})",
+            sb.free_blocks_count, sb.free_inodes_count, free_blocks, free_inodes
+        )).block(0));
+    }
+
+    Ok(Check {
+        inode_list, stored_bitmap, inode_bitmap, refs, chunk_index, snapshots,
+        calculated_used_blocks, block_refs, dir_entries, dirs_with_bad_entries, links,
+    })
+}
+
+/// Paso 8: corrige lo que encontró `check_volume` y devuelve cuántas correcciones hubo
+fn repair_volume(device: &BlockDevice, crypto: &CryptoEngine, sb: SuperBlock, check: Check, dry_run: bool, report: &mut Report) -> anyhow::Result<u32> {
+    correct(device, crypto, sb, check, dry_run, report)?.commit()
+}
+
+/// Pasos A a F de la reparación, en memoria: lo corregido queda en el `Repair`
+/// devuelto, que todavía no escribió nada
+fn correct<'a>(
+    device: &'a BlockDevice,
+    crypto: &'a CryptoEngine,
+    sb: SuperBlock,
+    check: Check,
+    dry_run: bool,
+    report: &'a mut Report,
+) -> anyhow::Result<Repair<'a>> {
+    let Check {
+        inode_list, stored_bitmap, inode_bitmap, refs, chunk_index, snapshots,
+        mut calculated_used_blocks, mut block_refs, mut dir_entries, dirs_with_bad_entries, links,
+    } = check;
+    let root = sb.root_dir_inode;
+    let total = sb.total_blocks;
+    let mut fix = Repair::new(
+        device, crypto, sb, inode_list, stored_bitmap, inode_bitmap, refs, chunk_index, snapshots, dry_run, report,
+    );
+
+    // A. Punteros a bloques fuera del volumen: el archivo se corta en el primero
+    // (la lectura no sigue después de un hueco, así que lo de más allá se suelta)
+    for idx in 0..fix.inodes.len() {
+        let mut inode = fix.inodes[idx].clone();
+        if inode.mode == 0 { continue; }
+        let mut cleared = 0;
+        if let Some(bad) = inode.direct_blocks.iter().position(|&b| b >= total) {
+            for block in inode.direct_blocks[bad..].iter_mut().filter(|b| **b != 0) {
+                if *block < total && let Some(count) = block_refs.get_mut(block) {
+                    *count -= 1;
+                    if *count == 0 {
+                        block_refs.remove(block);
+                        calculated_used_blocks.remove(block);
+                    }
+                }
+                *block = 0;
+                cleared += 1;
             }
+            inode.size = read_inode_data(device, crypto, &inode).map_or(0, |data| data.len() as u64);
+        }
+        if inode.xattr_block >= total {
+            inode.xattr_block = 0;
+            cleared += 1;
+        }
+        if cleared > 0 {
+            fix.fix(Finding::new(format!(
+                "Inodo {}: {} punteros borrados desde el primero fuera de rango (tamaño {})", idx, cleared, inode.size
+            )).inode(idx as u64));
+            fix.set_inode(idx as u64, inode);
         }
     }
 
-    if errors == 0 {
-        println!("\n{}", ">> EL SISTEMA DE ARCHIVOS ESTÁ SANO".bold().green());
-    } else {
-        println!("\n{} Se encontraron {} errores graves.", ">> PRECAUCIÓN:".bold().red(), errors);
+    // B. Bitmaps reconstruidos: ocupado es lo que usan los metadatos, los
+    // inodos y los snapshots; nada más
+    for block in 0..total {
+        let used = calculated_used_blocks.contains(&block);
+        if fix.bitmap.get(block as usize) != used {
+            fix.bitmap.set(block as usize, used);
+            fix.fix(Finding::new(if used {
+                format!("Bloque {} en uso marcado como ocupado", block)
+            } else {
+                format!("Bloque {} huérfano liberado", block)
+            }).block(block));
+        }
     }
+    for idx in 0..fix.inodes.len() {
+        let used = idx == 0 || fix.inodes[idx].mode != 0;
+        if fix.inode_bitmap.get(idx) != used {
+            fix.inode_bitmap.set(idx, used);
+            fix.fix(Finding::new(format!(
+                "Inodo {} marcado como {} en el bitmap de inodos", idx, if used { "ocupado" } else { "libre" }
+            )).inode(idx as u64));
+        }
+    }
+
+    // C. Contadores de referencias (N punteros => N-1 extra) e índice de dedup
+    for block in 0..total {
+        let shares = block_refs.get(&block).map_or(0, |&count| (count - 1).min(u16::MAX as u64) as u16);
+        if fix.refs.shares(block) != shares {
+            fix.fix(Finding::new(format!("Bloque {}: contador de compartido {} (decía {})", block, shares, fix.refs.shares(block))).block(block));
+            fix.refs.set_shares(block, shares);
+        }
+    }
+    let stale: Vec<u64> = fix.chunk_index.entries().map(|e| e.block).filter(|b| !block_refs.contains_key(b)).collect();
+    for block in stale {
+        fix.chunk_index.remove_block(block);
+        fix.fix(Finding::new(format!("Entrada del índice de dedup al bloque {} borrada", block)).block(block));
+    }
+
+    // D. Entradas de directorio que apuntan a inodos que no existen
+    for dir in dirs_with_bad_entries {
+        let Some(entries) = dir_entries.get_mut(&dir) else { continue };
+        entries.retain(|e| fix.inodes.get(e.inode_idx as usize).is_some_and(|child| child.mode != 0));
+        let entries = entries.clone();
+        fix.write_dir(dir, &entries)?;
+        fix.fix(Finding::new(format!("Directorio {}: entradas a inodos inexistentes borradas", dir)).inode(dir));
+    }
+
+    // E. Inodos que no están en ningún directorio: a /lost+found, como "#N"
+    let orphans: Vec<u64> = links.iter().filter(|(_, (found, _))| *found == 0).map(|(&idx, _)| idx).collect();
+    if !orphans.is_empty() {
+        match dir_entries.get(&root).cloned() {
+            None => fix.warn(Finding::new("La raíz es ilegible: los inodos huérfanos no se pueden reenganchar").inode(root)),
+            Some(mut root_entries) => {
+                let existing = root_entries.iter()
+                    .find(|e| e.name == LOST_FOUND && fix.inodes.get(e.inode_idx as usize).is_some_and(|i| i.file_type == FileType::Directory))
+                    .map(|e| e.inode_idx);
+                let lost_found = match existing {
+                    Some(idx) => idx,
+                    None => {
+                        let idx = fix.create_dir(0o700)?;
+                        root_entries.push(DirEntry { inode_idx: idx, name: LOST_FOUND.to_vec() });
+                        fix.write_dir(root, &root_entries)?;
+                        dir_entries.insert(root, root_entries);
+                        dir_entries.insert(idx, Vec::new());
+                        fix.fix(Finding::new("Creado /lost+found").inode(idx));
+                        idx
+                    }
+                };
+                let mut found_entries = dir_entries.get(&lost_found).cloned().unwrap_or_default();
+                for idx in orphans {
+                    let name = format!("#{}", idx);
+                    fix.fix(Finding::new(format!("Inodo {} reenganchado como /lost+found/{}", idx, name)).inode(idx));
+                    found_entries.push(DirEntry { inode_idx: idx, name: name.into_bytes() });
+                }
+                fix.write_dir(lost_found, &found_entries)?;
+                dir_entries.insert(lost_found, found_entries);
+            }
+        }
+    }
+
+    // F. nlink de cada inodo, con los directorios ya corregidos
+    for (idx, (_, expected)) in expected_links(&fix.inodes, &dir_entries, root) {
+        let mut inode = fix.inodes[idx as usize].clone();
+        if inode.nlink != expected {
+            fix.fix(Finding::new(format!("Inodo {}: nlink {} (decía {})", idx, expected, inode.nlink)).inode(idx));
+            inode.nlink = expected;
+            fix.set_inode(idx, inode);
+        }
+    }
+
+    // G. (en `Repair::commit`) Contadores del superbloque, y escribir todo junto
+    Ok(fix)
+}
+
+/// Reglas de un enlace simbólico: el tamaño es el largo del destino, y el destino
+/// vive dentro del inodo (si es corto) o en bloques de datos, nunca en ambos.
+/// Los demás tipos no pueden tener datos embebidos.
+fn symlink_problems(inode: &Inode) -> Vec<String> {
+    let mut problems = Vec::new();
+    let has_blocks = inode.direct_blocks.iter().any(|&b| b != 0);
+
+    if inode.file_type != FileType::Symlink {
+        if !inode.inline_data.is_empty() {
+            problems.push(format!("{:?} con datos embebidos", inode.file_type));
+        }
+        return problems;
+    }
+
+    if inode.size == 0 {
+        problems.push("symlink con destino vacío".to_string());
+    }
+    if !inode.inline_data.is_empty() {
+        if inode.inline_data.len() > SYMLINK_INLINE_MAX {
+            problems.push(format!("destino embebido de {} bytes (máximo {})", inode.inline_data.len(), SYMLINK_INLINE_MAX));
+        }
+        if inode.inline_data.len() as u64 != inode.size {
+            problems.push(format!("tamaño {} no coincide con destino de {} bytes", inode.size, inode.inline_data.len()));
+        }
+        if has_blocks {
+            problems.push("symlink con destino embebido y bloques de datos".to_string());
+        }
+    } else if inode.size > 0 && !has_blocks {
+        problems.push("symlink sin destino (ni embebido ni en bloques)".to_string());
+    }
+    problems
+}
+
+/// Para cada inodo en uso: cuántas entradas de directorio lo apuntan y qué nlink
+/// le corresponde (un directorio suma su "." y el ".." de cada subdirectorio)
+fn expected_links(inodes: &[Inode], dir_entries: &BTreeMap<u64, Vec<DirEntry>>, root: u64) -> BTreeMap<u64, (u32, u32)> {
+    let mut dir_refs: HashMap<u64, u32> = HashMap::new();
+    let mut subdirs: HashMap<u64, u32> = HashMap::new();
+    dir_refs.insert(root, 1); // La raíz no tiene padre: su ".." es ella misma
+    for (&dir, entries) in dir_entries {
+        for entry in entries {
+            if let Some(child) = inodes.get(entry.inode_idx as usize)
+                && child.mode != 0
+            {
+                *dir_refs.entry(entry.inode_idx).or_insert(0) += 1;
+                if child.file_type == FileType::Directory {
+                    *subdirs.entry(dir).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    inodes.iter().enumerate()
+        .filter(|(_, inode)| inode.mode != 0)
+        .map(|(idx, inode)| {
+            let idx = idx as u64;
+            let found = dir_refs.get(&idx).copied().unwrap_or(0);
+            let expected = match inode.file_type {
+                FileType::Directory => found + 1 + subdirs.get(&idx).copied().unwrap_or(0),
+                _ => found,
+            };
+            (idx, (found, expected))
+        })
+        .collect()
+}
+
+/// Tamaño vs. bloques asignados: los bloques van seguidos desde el primero (la
+/// lectura corta en el primer hueco), y son los que hacen falta para `size`:
+/// exactamente, sin compresión; a lo sumo esos y al menos uno, con compresión.
+/// Un directorio vacío puede conservar un bloque (mkfs se lo reserva a la raíz).
+fn size_problems(inode: &Inode, compression: Compression) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some(hole) = inode.direct_blocks.iter().position(|&b| b == 0)
+        && inode.direct_blocks[hole..].iter().any(|&b| b != 0)
+    {
+        problems.push(format!("bloques de datos con un hueco en el puntero {}", hole));
+    }
+    if !inode.inline_data.is_empty() { return problems; } // Vive dentro del inodo
+
+    let used = inode.used_blocks();
+    let needed = inode.size.div_ceil(CHUNK_SIZE as u64 - 1); // Un bloque en crudo guarda CHUNK_SIZE - 1 bytes
+    if inode.size == 0 && inode.file_type == FileType::Directory && used == 1 {
+        return problems;
+    }
+    if inode.size == 0 && used > 0 {
+        problems.push(format!("tamaño 0 con {} bloques de datos", used));
+    } else if inode.size > 0 && used == 0 {
+        problems.push(format!("tamaño {} sin bloques de datos", inode.size));
+    } else if compression == Compression::None && used != needed {
+        problems.push(format!("tamaño {} ocupa {} bloques pero tiene {}", inode.size, needed, used));
+    } else if used > needed {
+        problems.push(format!("tamaño {} ocupa a lo sumo {} bloques pero tiene {}", inode.size, needed, used));
+    }
+    problems
+}
+
+/// Recorre el árbol desde la raíz (en profundidad, con la ruta actual para ver
+/// ciclos). Cada directorio tiene nombres válidos y sin repetir, y cada
+/// subdirectorio un solo padre: si no, su ".." sería ambiguo.
+/// Devuelve los inodos alcanzados y los problemas encontrados (con el inodo afectado).
+fn walk_tree(inodes: &[Inode], dir_entries: &BTreeMap<u64, Vec<DirEntry>>, root: u64) -> (HashSet<u64>, Vec<(u64, String)>) {
+    let mut problems = Vec::new();
+    let live = |idx: u64| inodes.get(idx as usize).filter(|inode| inode.mode != 0);
+    if live(root).is_none_or(|inode| inode.file_type != FileType::Directory) {
+        problems.push((root, format!("La raíz (inodo {}) no es un directorio en uso", root)));
+        return (HashSet::new(), problems);
+    }
+
+    let empty = Vec::new();
+    let entries_of = |dir: u64| dir_entries.get(&dir).unwrap_or(&empty);
+    let check_names = |dir: u64, problems: &mut Vec<(u64, String)>| {
+        let mut seen = HashSet::new();
+        for entry in entries_of(dir) {
+            if DirEntry::check_name(&entry.name).is_err() || entry.name == b"." || entry.name == b".." {
+                problems.push((dir, format!("Nombre inválido '{}' en el directorio {}", entry.display_name(), dir)));
+            }
+            if !seen.insert(&entry.name) {
+                problems.push((dir, format!("Nombre '{}' repetido en el directorio {}", entry.display_name(), dir)));
+            }
+        }
+    };
+
+    let mut reached = HashSet::from([root]);
+    let mut parent_of: HashMap<u64, u64> = HashMap::new();
+    let mut on_path = HashSet::from([root]);
+    let mut stack = vec![(root, 0)]; // (directorio, próxima entrada a visitar)
+    check_names(root, &mut problems);
 
-    Ok(())
-}
\ No newline at end of file
+    while let Some(&(dir, pos)) = stack.last() {
+        let Some(entry) = entries_of(dir).get(pos) else {
+            on_path.remove(&dir);
+            stack.pop();
+            continue;
+        };
+        if let Some(top) = stack.last_mut() { top.1 += 1; }
+
+        let child = entry.inode_idx;
+        let Some(inode) = live(child) else { continue }; // Ya reportado en 5b
+        if inode.file_type != FileType::Directory {
+            reached.insert(child);
+            continue;
+        }
+        if on_path.contains(&child) {
+            problems.push((dir, format!("Ciclo: '{}' en el directorio {} apunta a su ancestro {}", entry.display_name(), dir, child)));
+        } else if let Some(&first) = parent_of.get(&child) {
+            problems.push((child, format!("El directorio {} está en {} y en {}: su '..' es ambiguo", child, first, dir)));
+        } else {
+            parent_of.insert(child, dir);
+            reached.insert(child);
+            on_path.insert(child);
+            stack.push((child, 0));
+            check_names(child, &mut problems);
+        }
+    }
+    (reached, problems)
+}
+
+/// Lee el contenido de un inodo (descifra y descomprime sus bloques)
+fn read_inode_data(device: &BlockDevice, crypto: &CryptoEngine, inode: &Inode) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for &block_id in inode.direct_blocks.iter() {
+        if block_id == 0 { break; }
+        let plain = crypto.decrypt(&device.read_block(block_id)?)?;
+        data.extend_from_slice(&compress::unpack_chunk(&plain)?);
+    }
+    data.truncate(inode.size as usize);
+    Ok(data)
+}
+
+/// Lista de xattrs de un inodo (del propio inodo o de su bloque desbordado)
+fn load_xattrs(device: &BlockDevice, crypto: &CryptoEngine, inode: &Inode) -> anyhow::Result<Vec<Xattr>> {
+    if inode.xattr_block == 0 {
+        return Ok(inode.xattrs.clone());
+    }
+    let plain = crypto.decrypt(&device.read_block(inode.xattr_block)?)?;
+    Ok(bincode::deserialize(&compress::unpack_chunk(&plain)?)?)
+}
+
+/// Límites de los xattrs: nombres válidos y únicos, y la lista en el lugar que le toca
+/// según su tamaño (embebida si es chica, en un bloque si no)
+fn xattr_problems(inode: &Inode, xattrs: &[Xattr]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let len = xattr::serialized_len(xattrs);
+
+    if inode.xattr_block != 0 && !inode.xattrs.is_empty() {
+        problems.push("xattrs embebidos y en bloque a la vez".to_string());
+    }
+    if inode.xattr_block == 0 && len > XATTR_INLINE_MAX {
+        problems.push(format!("xattrs embebidos de {} bytes (máximo {})", len, XATTR_INLINE_MAX));
+    }
+    if len > XATTR_BLOCK_MAX {
+        problems.push(format!("xattrs de {} bytes (máximo {})", len, XATTR_BLOCK_MAX));
+    }
+
+    let mut seen = HashSet::new();
+    for x in xattrs {
+        let name = String::from_utf8_lossy(&x.name);
+        if x.name.is_empty() || x.name.len() > XATTR_NAME_MAX {
+            problems.push(format!("xattr con nombre inválido '{}'", name));
+        }
+        if !seen.insert(&x.name) {
+            problems.push(format!("xattr '{}' repetido", name));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: u64 = 1;
+
+    fn dir() -> Inode {
+        Inode::new(FileType::Directory, 0o755)
+    }
+
+    fn file(size: u64, blocks: &[u64]) -> Inode {
+        let mut inode = Inode::new(FileType::File, 0o644);
+        inode.size = size;
+        inode.direct_blocks[..blocks.len()].copy_from_slice(blocks);
+        inode
+    }
+
+    fn entry(name: &[u8], inode_idx: u64) -> DirEntry {
+        DirEntry { name: name.to_vec(), inode_idx }
+    }
+
+    /// Inodo 0 libre (el "nulo") seguido de `inodes`
+    fn table(inodes: Vec<Inode>) -> Vec<Inode> {
+        std::iter::once(Inode::new(FileType::File, 0)).chain(inodes).collect()
+    }
+
+    fn problems_about(problems: &[(u64, String)], text: &str) -> Vec<u64> {
+        problems.iter().filter(|(_, p)| p.contains(text)).map(|&(inode, _)| inode).collect()
+    }
+
+    #[test]
+    fn test_walk_tree_finds_cycles_and_second_parents() {
+        // / -> a(2) -> b(3) -> "arriba" vuelve a a(2); / y a(2) tienen los dos a c(4)
+        let inodes = table(vec![dir(), dir(), dir(), dir()]);
+        let dirs = BTreeMap::from([
+            (1, vec![entry(b"a", 2), entry(b"c", 4)]),
+            (2, vec![entry(b"b", 3), entry(b"c", 4)]),
+            (3, vec![entry(b"arriba", 2)]),
+        ]);
+        let (reached, problems) = walk_tree(&inodes, &dirs, ROOT);
+        assert_eq!(reached, HashSet::from([1, 2, 3, 4]));
+        assert_eq!(problems_about(&problems, "Ciclo"), vec![3]);
+        assert_eq!(problems_about(&problems, "ambiguo"), vec![4]);
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[test]
+    fn test_walk_tree_checks_names() {
+        let inodes = table(vec![dir(), file(0, &[]), file(0, &[]), file(0, &[]), file(0, &[])]);
+        let dirs = BTreeMap::from([(1, vec![
+            entry(b"a", 2),
+            entry(b"a", 3),       // Repetido
+            entry(b"..", 4),      // Reservado
+            entry(b"x/y", 5),     // Con barra
+            entry(b"", 5),        // Vacío
+            entry(b"bien", 5),
+        ])]);
+        let (_, problems) = walk_tree(&inodes, &dirs, ROOT);
+        assert_eq!(problems_about(&problems, "repetido").len(), 1);
+        assert_eq!(problems_about(&problems, "inválido").len(), 3);
+        assert!(problems.iter().all(|&(dir, _)| dir == ROOT));
+    }
+
+    #[test]
+    fn test_walk_tree_reports_unreachable_inodes() {
+        // 2 y su archivo 3 no cuelgan de la raíz; 4 sí
+        let inodes = table(vec![dir(), dir(), file(0, &[]), file(0, &[])]);
+        let dirs = BTreeMap::from([(1, vec![entry(b"visible", 4)]), (2, vec![entry(b"perdido", 3)])]);
+        let (reached, problems) = walk_tree(&inodes, &dirs, ROOT);
+        assert_eq!(reached, HashSet::from([1, 4]));
+        assert!(problems.is_empty());
+
+        // Una raíz que no es un directorio no deja recorrer nada
+        let (reached, problems) = walk_tree(&table(vec![file(0, &[])]), &BTreeMap::new(), ROOT);
+        assert!(reached.is_empty());
+        assert_eq!(problems.len(), 1);
+    }
+
+    #[test]
+    fn test_expected_links() {
+        // / tiene el archivo 2 dos veces (enlace duro), el directorio 3 (con el
+        // subdirectorio 4) y una entrada a un inodo libre (6); 5 es huérfano
+        let inodes = table(vec![dir(), file(0, &[]), dir(), dir(), file(0, &[]), Inode::new(FileType::File, 0)]);
+        let dirs = BTreeMap::from([
+            (1, vec![entry(b"a", 2), entry(b"b", 2), entry(b"d", 3), entry(b"libre", 6)]),
+            (3, vec![entry(b"sub", 4)]),
+            (4, vec![]),
+        ]);
+        let links = expected_links(&inodes, &dirs, ROOT);
+        assert_eq!(links, BTreeMap::from([
+            (1, (1, 3)), // Su propio "..", su "." y el ".." de 3
+            (2, (2, 2)),
+            (3, (1, 3)),
+            (4, (1, 2)),
+            (5, (0, 0)),
+        ]));
+    }
+
+    #[test]
+    fn test_size_problems_without_compression() {
+        let chunk = CHUNK_SIZE as u64 - 1;
+        assert!(size_problems(&file(2 * chunk, &[10, 11]), Compression::None).is_empty());
+        assert!(size_problems(&file(chunk + 1, &[10, 11]), Compression::None).is_empty());
+        assert_eq!(size_problems(&file(2 * chunk, &[10]), Compression::None).len(), 1);
+        assert_eq!(size_problems(&file(chunk, &[10, 11]), Compression::None).len(), 1);
+        assert_eq!(size_problems(&file(0, &[10]), Compression::None).len(), 1);
+        assert_eq!(size_problems(&file(10, &[]), Compression::None).len(), 1);
+        assert!(size_problems(&file(0, &[]), Compression::None).is_empty());
+
+        // Hueco: la lectura corta en el puntero 1 y el bloque 12 nunca se lee
+        let holed = size_problems(&file(3 * chunk, &[10, 0, 12]), Compression::None);
+        assert!(holed.iter().any(|p| p.contains("hueco en el puntero 1")));
+
+        // La raíz recién formateada: vacía, con el bloque que le reservó mkfs
+        let mut root = dir();
+        root.direct_blocks[0] = 10;
+        assert!(size_problems(&root, Compression::None).is_empty());
+        root.direct_blocks[1] = 11;
+        assert_eq!(size_problems(&root, Compression::None).len(), 1);
+
+        // Un symlink corto vive en el inodo: no se miran sus bloques
+        let mut link = Inode::new(FileType::Symlink, 0o777);
+        link.inline_data = b"destino".to_vec();
+        link.size = 7;
+        assert!(size_problems(&link, Compression::None).is_empty());
+    }
+
+    #[test]
+    fn test_size_problems_with_compression() {
+        let chunk = CHUNK_SIZE as u64 - 1;
+        // Comprimido puede ocupar menos bloques, pero nunca más ni ninguno
+        assert!(size_problems(&file(3 * chunk, &[10]), Compression::Deflate).is_empty());
+        assert!(size_problems(&file(3 * chunk, &[10, 11, 12]), Compression::Deflate).is_empty());
+        assert_eq!(size_problems(&file(chunk, &[10, 11]), Compression::Deflate).len(), 1);
+        assert_eq!(size_problems(&file(chunk, &[]), Compression::Deflate).len(), 1);
+        assert_eq!(size_problems(&file(0, &[10]), Compression::Deflate).len(), 1);
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::BTreeSet;
+use std::time::SystemTime;
+
+use qrfs_lib::device::BlockDevice;
+use qrfs_lib::crypto::CryptoEngine;
+use qrfs_lib::types::{SuperBlock, Inode, FileType, DirEntry, CHUNK_SIZE, DIRECT_POINTERS, INODES_PER_BLOCK};
+use qrfs_lib::compress;
+use qrfs_lib::bitmap::Bitmap;
+use qrfs_lib::refcount::RefCounts;
+use qrfs_lib::dedup::ChunkIndex;
+use qrfs_lib::journal::Transaction;
+use qrfs_lib::snapshot::{self, Snapshot};
+use qrfs_lib::volume;
+
+use crate::report::{Finding, Report};
+
+// Dónde quedan los inodos que no están en ningún directorio
+pub const LOST_FOUND: &[u8] = b"lost+found";
+
+/// Estado del volumen mientras fsck lo repara.
+///
+/// Todos los cambios se hacen en memoria y se escriben juntos al final, en una
+/// transacción del journal (como una operación de qrfs_mount): si se corta a la
+/// mitad, el próximo fsck la rehace. Con `dry_run` se hacen igual, para poder
+/// contar lo que se corregiría, pero no se escribe nada.
+pub struct Repair<'a> {
+    device: &'a BlockDevice,
+    crypto: &'a CryptoEngine,
+    sb: SuperBlock,
+    pub inodes: Vec<Inode>,
+    pub bitmap: Bitmap,
+    pub inode_bitmap: Bitmap,
+    pub refs: RefCounts,
+    pub chunk_index: ChunkIndex,
+    snapshots: Vec<Snapshot>,
+    dirty_inodes: BTreeSet<u64>,
+    txn: Transaction, // Bloques de datos nuevos (directorios reescritos)
+    dry_run: bool,
+    fixes: u32,
+    report: &'a mut Report,
+}
+
+impl<'a> Repair<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &'a BlockDevice,
+        crypto: &'a CryptoEngine,
+        sb: SuperBlock,
+        inodes: Vec<Inode>,
+        bitmap: Bitmap,
+        inode_bitmap: Bitmap,
+        refs: RefCounts,
+        chunk_index: ChunkIndex,
+        snapshots: Vec<Snapshot>,
+        dry_run: bool,
+        report: &'a mut Report,
+    ) -> Self {
+        Self {
+            device, crypto, sb, inodes, bitmap, inode_bitmap, refs, chunk_index, snapshots,
+            dirty_inodes: BTreeSet::new(),
+            txn: Transaction::default(),
+            dry_run,
+            fixes: 0,
+            report,
+        }
+    }
+
+    /// Anota (e imprime) una corrección
+    pub fn fix(&mut self, finding: Finding) {
+        self.report.fix(finding, self.dry_run);
+        self.fixes += 1;
+    }
+
+    /// Algo que no se puede corregir
+    pub fn warn(&mut self, finding: Finding) {
+        self.report.warn(finding);
+    }
+
+    /// Reemplaza un inodo (se guarda su bloque de la tabla al final)
+    pub fn set_inode(&mut self, idx: u64, inode: Inode) {
+        self.inodes[idx as usize] = inode;
+        self.dirty_inodes.insert(idx);
+    }
+
+    /// Reescribe el contenido de un directorio en bloques nuevos.
+    /// Los viejos se sueltan (si un snapshot los comparte, siguen siendo suyos).
+    pub fn write_dir(&mut self, idx: u64, entries: &[DirEntry]) -> anyhow::Result<()> {
+        let data = bincode::serialize(entries)?;
+        let mut inode = self.inodes[idx as usize].clone();
+        for block in inode.direct_blocks.iter_mut().filter(|b| **b != 0) {
+            if self.refs.release(*block, &mut self.bitmap) {
+                self.chunk_index.remove_block(*block);
+            }
+            *block = 0;
+        }
+
+        let mut written = 0;
+        let mut ptr = 0;
+        while written < data.len() {
+            if ptr >= DIRECT_POINTERS {
+                anyhow::bail!("El directorio {} no entra en {} bloques", idx, DIRECT_POINTERS);
+            }
+            let (consumed, packed) = compress::pack_chunk(&data[written..], CHUNK_SIZE, self.sb.compression);
+            let block = self.bitmap.allocate()
+                .ok_or_else(|| anyhow::anyhow!("No queda espacio para reescribir el directorio {}", idx))?;
+            self.txn.write(block, packed);
+            inode.direct_blocks[ptr] = block;
+            written += consumed;
+            ptr += 1;
+        }
+
+        inode.size = data.len() as u64;
+        inode.modified_at = SystemTime::now();
+        inode.changed_at = inode.modified_at;
+        self.set_inode(idx, inode);
+        Ok(())
+    }
+
+    /// Crea un directorio vacío (sin enlazarlo en ningún lado) y devuelve su inodo
+    pub fn create_dir(&mut self, mode: u16) -> anyhow::Result<u64> {
+        let idx = self.inode_bitmap.allocate()
+            .ok_or_else(|| anyhow::anyhow!("La tabla de inodos está llena"))?;
+        self.set_inode(idx, Inode::new(FileType::Directory, mode));
+        Ok(idx)
+    }
+
+    /// Escribe todo lo corregido (salvo con `dry_run`) y devuelve cuántas correcciones hubo
+    pub fn commit(mut self) -> anyhow::Result<u32> {
+        // 1. Bloques de la tabla de inodos que cambiaron
+        let table_blocks: BTreeSet<u64> = self.dirty_inodes.iter().map(|&idx| idx / INODES_PER_BLOCK as u64).collect();
+        for table_block in table_blocks {
+            let first = table_block as usize * INODES_PER_BLOCK;
+            let block_inodes = &self.inodes[first..first + INODES_PER_BLOCK];
+            self.txn.write(self.sb.inode_table_start + table_block, bincode::serialize(block_inodes)?);
+        }
+        for page in self.inode_bitmap.take_dirty() {
+            self.txn.write(self.sb.inode_bitmap_start + page as u64, bincode::serialize(self.inode_bitmap.page(page))?);
+        }
+
+        // 2. Lo que los snapshots todavía ven en su lugar se copia antes de pisarlo
+        if !self.snapshots.is_empty() && snapshot::preserve(
+            &self.sb, &mut self.snapshots, &mut self.txn,
+            &mut self.bitmap, &mut self.refs, self.device, self.crypto,
+        )? {
+            for (block, bytes) in snapshot::catalog_pages(&self.sb, &self.snapshots)? {
+                self.txn.write(block, bytes);
+            }
+        }
+
+        // 3. Bitmap de bloques, contadores de referencias e índice de dedup
+        for page in self.bitmap.take_dirty() {
+            self.txn.write(self.sb.bitmap_start + page as u64, bincode::serialize(self.bitmap.page(page))?);
+        }
+        for page in self.refs.take_dirty() {
+            self.txn.write(self.sb.refcount_start + page as u64, bincode::serialize(self.refs.page(page))?);
+        }
+        if self.sb.dedup_index_blocks > 0 {
+            for bucket in self.chunk_index.take_dirty() {
+                self.txn.write(self.sb.dedup_index_start + bucket as u64, bincode::serialize(self.chunk_index.bucket(bucket))?);
+            }
+        }
+
+        // 4. Contadores de libres (los bitmaps ya están al día)
+        let (free_blocks, free_inodes) = (self.bitmap.free_count(), self.inode_bitmap.free_count());
+        if self.sb.free_blocks_count != free_blocks || self.sb.free_inodes_count != free_inodes {
+            self.fix(Finding::new(format!(
+                "Contadores del superbloque: {} bloques y {} inodos libres (decía {} y {})",
+                free_blocks, free_inodes, self.sb.free_blocks_count, self.sb.free_inodes_count
+            )).block(0));
+            self.sb.free_blocks_count = free_blocks;
+            self.sb.free_inodes_count = free_inodes;
+        }
+
+        if !self.dry_run && self.fixes > 0 {
+            // Protegido por el journal, igual que una operación de qrfs_mount
+            if let Some((parts, blocks)) = volume::commit(self.device, self.crypto, &mut self.sb, &self.txn)? {
+                self.report.warn(Finding::new(format!("La reparación ocupó {} bloques y el journal tiene {}: se escribió sin journal", parts, blocks)));
+            }
+        }
+        Ok(self.fixes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::fs;
+    use crate::{correct, expected_links, Check};
+
+    const TOTAL_BLOCKS: u64 = 64;
+    const DATA_START: u64 = 10; // Los bloques 0..10 son metadatos
+    const ROOT: u64 = 1;
+
+    fn superblock(total_inodes: u64) -> SuperBlock {
+        SuperBlock {
+            inode_table_start: 5,
+            bitmap_start: 1,
+            bitmap_blocks: 1,
+            inode_bitmap_start: 2,
+            inode_bitmap_blocks: 1,
+            journal_start: 3,
+            journal_blocks: 1,
+            snapshot_start: 4,
+            snapshot_blocks: 1,
+            root_dir_inode: ROOT,
+            refcount_start: 8,
+            refcount_blocks: 1,
+            dedup_index_start: 9,
+            dedup_index_blocks: 1,
+            ..SuperBlock::for_tests(TOTAL_BLOCKS, total_inodes)
+        }
+    }
+
+    /// Dispositivo (en una carpeta propia, que se borra al terminar aunque el
+    /// test falle), cifrado nulo y reporte sin salida
+    struct Fixture {
+        dir: &'static str,
+        device: BlockDevice,
+        crypto: CryptoEngine,
+        report: Report,
+    }
+
+    impl Fixture {
+        fn new(dir: &'static str) -> Self {
+            let _ = fs::remove_dir_all(dir);
+            Self {
+                dir,
+                device: BlockDevice::new(dir).unwrap(),
+                crypto: CryptoEngine::new_plaintext(),
+                report: Report::quiet(),
+            }
+        }
+
+        fn correct(&mut self, sb: SuperBlock, found: Check) -> Repair<'_> {
+            correct(&self.device, &self.crypto, sb, found, true, &mut self.report).unwrap()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.dir);
+        }
+    }
+
+    fn file(blocks: &[u64]) -> Inode {
+        let mut inode = Inode::new(FileType::File, 0o644);
+        inode.direct_blocks[..blocks.len()].copy_from_slice(blocks);
+        inode.size = blocks.len() as u64 * (CHUNK_SIZE as u64 - 1);
+        inode
+    }
+
+    fn dir(block: u64) -> Inode {
+        let mut inode = Inode::new(FileType::Directory, 0o755);
+        inode.direct_blocks[0] = block;
+        inode
+    }
+
+    fn entry(name: &str, inode_idx: u64) -> DirEntry {
+        DirEntry { name: name.as_bytes().to_vec(), inode_idx }
+    }
+
+    /// Lo que vería `check_volume` en un volumen con estos inodos y directorios,
+    /// con los bitmaps y contadores tal cual los calcula (es decir, sanos)
+    fn check(inodes: Vec<Inode>, dirs: Vec<(u64, Vec<DirEntry>)>) -> Check {
+        let mut calculated_used_blocks: HashSet<u64> = (0..DATA_START).collect();
+        let mut block_refs: HashMap<u64, u64> = HashMap::new();
+        for inode in inodes.iter().filter(|inode| inode.mode != 0) {
+            let blocks = inode.direct_blocks.iter().chain(std::iter::once(&inode.xattr_block));
+            for &block in blocks.filter(|&&b| b != 0 && b < TOTAL_BLOCKS) {
+                calculated_used_blocks.insert(block);
+                *block_refs.entry(block).or_insert(0) += 1;
+            }
+        }
+
+        let mut stored_bitmap = Bitmap::new(TOTAL_BLOCKS as usize);
+        for &block in &calculated_used_blocks {
+            stored_bitmap.set(block as usize, true);
+        }
+        let mut inode_bitmap = Bitmap::new(inodes.len());
+        for (idx, inode) in inodes.iter().enumerate() {
+            inode_bitmap.set(idx, idx == 0 || inode.mode != 0);
+        }
+        let mut refs = RefCounts::new(1);
+        for (&block, &count) in &block_refs {
+            refs.set_shares(block, (count - 1) as u16);
+        }
+        stored_bitmap.take_dirty();
+        inode_bitmap.take_dirty();
+        refs.take_dirty();
+
+        let dir_entries: BTreeMap<u64, Vec<DirEntry>> = dirs.into_iter().collect();
+        let links = expected_links(&inodes, &dir_entries, ROOT);
+        Check {
+            inode_list: inodes, stored_bitmap, inode_bitmap, refs,
+            chunk_index: ChunkIndex::new(1),
+            snapshots: Vec::new(),
+            calculated_used_blocks, block_refs, dir_entries,
+            dirs_with_bad_entries: BTreeSet::new(),
+            links,
+        }
+    }
+
+    /// Contenido de un directorio reescrito por la reparación (todavía en la transacción)
+    fn staged_dir(fix: &Repair, idx: u64) -> Vec<DirEntry> {
+        let inode = &fix.inodes[idx as usize];
+        let mut data = Vec::new();
+        for &block in inode.direct_blocks.iter().take_while(|&&b| b != 0) {
+            let staged = fix.txn.get(block).expect("el directorio tiene que estar en la transacción");
+            data.extend_from_slice(&compress::unpack_chunk(staged).unwrap());
+        }
+        bincode::deserialize(&data).unwrap()
+    }
+
+    fn names(entries: &[DirEntry]) -> Vec<(String, u64)> {
+        entries.iter().map(|e| (e.display_name().into_owned(), e.inode_idx)).collect()
+    }
+
+    #[test]
+    fn test_out_of_range_pointers_are_cleared() {
+        let mut fixture = Fixture::new("test_repair_range");
+
+        // El inodo 2 apunta fuera del volumen desde el primer puntero, y su bloque
+        // de xattrs también: el 11 (que queda después del primero malo) se suelta
+        let mut broken = file(&[TOTAL_BLOCKS + 5, 11]);
+        broken.xattr_block = TOTAL_BLOCKS + 9;
+        let inodes = vec![Inode::new(FileType::File, 0), dir(DATA_START), broken];
+        let dirs = vec![(ROOT, vec![entry("roto", 2)])];
+
+        let fix = fixture.correct(superblock(3), check(inodes, dirs));
+        assert_eq!(fix.inodes[2].direct_blocks, [0; DIRECT_POINTERS]);
+        assert_eq!(fix.inodes[2].xattr_block, 0);
+        assert_eq!(fix.inodes[2].size, 0);
+        assert!(fix.dirty_inodes.contains(&2));
+        assert!(!fix.bitmap.get(11)); // Sin dueño: liberado
+        assert!(fix.bitmap.get(DATA_START as usize));
+    }
+
+    #[test]
+    fn test_bitmaps_and_refcounts_are_rebuilt() {
+        let mut fixture = Fixture::new("test_repair_bitmaps");
+
+        // Los inodos 2 y 3 comparten el bloque 12 (dedup)
+        let inodes = vec![Inode::new(FileType::File, 0), dir(DATA_START), file(&[11, 12]), file(&[12]), Inode::new(FileType::File, 0)];
+        let dirs = vec![(ROOT, vec![entry("a", 2), entry("b", 3)])];
+        let mut found = check(inodes, dirs);
+
+        // Lo guardado en disco se desvió de lo calculado
+        found.stored_bitmap.set(11, false); // En uso pero marcado libre
+        found.stored_bitmap.set(40, true);  // Huérfano
+        found.inode_bitmap.set(3, false);   // Inodo en uso marcado libre
+        found.inode_bitmap.set(4, true);    // Inodo vacío marcado ocupado
+        found.refs.set_shares(12, 0);       // Perdió la cuenta del compartido
+        found.refs.set_shares(30, 2);       // Contador de un bloque sin dueño
+
+        let fix = fixture.correct(superblock(5), found);
+        assert!(fix.bitmap.get(11));
+        assert!(!fix.bitmap.get(40));
+        assert!((0..DATA_START as usize).all(|block| fix.bitmap.get(block)));
+        assert!(fix.inode_bitmap.get(0) && fix.inode_bitmap.get(3));
+        assert!(!fix.inode_bitmap.get(4));
+        assert_eq!(fix.refs.shares(12), 1);
+        assert_eq!(fix.refs.shares(30), 0);
+        assert_eq!(fix.refs.shares(11), 0);
+        assert_eq!(fix.fixes, 6);
+    }
+
+    #[test]
+    fn test_orphans_go_to_a_new_lost_found() {
+        let mut fixture = Fixture::new("test_repair_new_lost_found");
+
+        // 2 es un archivo y 3 un directorio (con un archivo adentro) que no están en ningún lado
+        let mut inodes = vec![Inode::new(FileType::File, 0), dir(DATA_START), file(&[11]), dir(12), file(&[13])];
+        inodes.extend((0..4).map(|_| Inode::new(FileType::File, 0)));
+        let dirs = vec![(ROOT, vec![]), (3, vec![entry("adentro", 4)])];
+
+        let fix = fixture.correct(superblock(9), check(inodes, dirs));
+        let root_entries = staged_dir(&fix, ROOT);
+        let lost_found = root_entries.iter().find(|e| e.name == LOST_FOUND).expect("tiene que crear /lost+found").inode_idx;
+        assert_eq!(names(&root_entries), vec![("lost+found".to_string(), lost_found)]);
+        assert_eq!(names(&staged_dir(&fix, lost_found)), vec![("#2".to_string(), 2), ("#3".to_string(), 3)]);
+
+        let lf = &fix.inodes[lost_found as usize];
+        assert_eq!((lf.file_type, lf.mode), (FileType::Directory, 0o700));
+        assert!(fix.inode_bitmap.get(lost_found as usize));
+        // La raíz tiene un subdirectorio y /lost+found otro; el archivo, un nombre
+        assert_eq!(fix.inodes[ROOT as usize].nlink, 3);
+        assert_eq!(lf.nlink, 3);
+        assert_eq!(fix.inodes[2].nlink, 1);
+        assert_eq!(fix.inodes[3].nlink, 2);
+        assert_eq!(fix.inodes[4].nlink, 1);
+    }
+
+    #[test]
+    fn test_orphans_go_to_the_existing_lost_found() {
+        let mut fixture = Fixture::new("test_repair_old_lost_found");
+
+        // /lost+found (inodo 2) ya existe y tiene algo de una reparación anterior
+        let mut inodes = vec![Inode::new(FileType::File, 0), dir(DATA_START), dir(11), file(&[12]), file(&[13])];
+        inodes[ROOT as usize].nlink = 3;
+        inodes[4].nlink = 5; // Además de huérfano, con el nlink mal
+        let dirs = vec![(ROOT, vec![entry("lost+found", 2)]), (2, vec![entry("#3", 3)])];
+
+        let fix = fixture.correct(superblock(5), check(inodes, dirs));
+        assert!(fix.txn.get(DATA_START).is_none()); // La raíz no se toca
+        assert_eq!(names(&staged_dir(&fix, 2)), vec![("#3".to_string(), 3), ("#4".to_string(), 4)]);
+        assert_eq!(fix.inodes[4].nlink, 1);
+        assert_eq!(fix.inodes[2].nlink, 2);
+        assert_eq!(fix.inodes[ROOT as usize].nlink, 3);
+        assert_eq!(fix.inode_bitmap.free_count(), 0);
+    }
+}
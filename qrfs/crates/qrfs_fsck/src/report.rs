@@ -0,0 +1,151 @@
+use colored::*;
+use serde::Serialize;
+
+// Códigos de salida, como los de e2fsck
+pub const EXIT_CLEAN: u8 = 0;
+pub const EXIT_FIXED: u8 = 1;
+pub const EXIT_UNCORRECTED: u8 = 4;
+pub const EXIT_FAILED: u8 = 8;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// Qué tan grave es un hallazgo
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// El volumen está inconsistente
+    Error,
+    /// Algo sobra o está desactualizado, pero no se pierde nada
+    Warning,
+    /// Corrección aplicada por --repair
+    Fixed,
+    /// Corrección que --repair aplicaría (--dry-run)
+    Pending,
+}
+
+impl Severity {
+    fn tag(self) -> ColoredString {
+        match self {
+            Severity::Error => "[CORRUPCIÓN]".red(),
+            Severity::Warning => "[WARN]".yellow(),
+            Severity::Fixed => "[FIX]".cyan(),
+            Severity::Pending => "[DRY-RUN]".magenta(),
+        }
+    }
+}
+
+/// Un problema (o una corrección), con el bloque y el inodo afectados si los hay
+#[derive(Serialize, Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    pub block: Option<u64>,
+    pub inode: Option<u64>,
+}
+
+impl Finding {
+    pub fn new(message: impl std::fmt::Display) -> Self {
+        Self { severity: Severity::Error, message: message.to_string(), block: None, inode: None }
+    }
+
+    pub fn block(mut self, block: u64) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    pub fn inode(mut self, inode: u64) -> Self {
+        self.inode = Some(inode);
+        self
+    }
+}
+
+/// Salida de fsck: en texto se imprime a medida que avanza la revisión; en JSON
+/// se juntan los hallazgos y se imprime todo al final (stdout queda solo para el reporte)
+pub struct Report {
+    format: Format,
+    quiet: bool,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn new(format: Format) -> Self {
+        Self { format, quiet: false, findings: Vec::new() }
+    }
+
+    /// Para volver a revisar después de reparar: junta los hallazgos sin imprimir nada
+    pub fn quiet() -> Self {
+        Self { format: Format::Text, quiet: true, findings: Vec::new() }
+    }
+
+    fn text(&self) -> bool {
+        self.format == Format::Text && !self.quiet
+    }
+
+    /// Línea de progreso (solo en texto)
+    pub fn info(&self, message: impl std::fmt::Display) {
+        if self.text() {
+            println!("{}", message);
+        }
+    }
+
+    pub fn error(&mut self, finding: Finding) {
+        self.push(Severity::Error, finding);
+    }
+
+    pub fn warn(&mut self, finding: Finding) {
+        self.push(Severity::Warning, finding);
+    }
+
+    /// Corrección aplicada (o, con `dry_run`, que se aplicaría)
+    pub fn fix(&mut self, finding: Finding, dry_run: bool) {
+        self.push(if dry_run { Severity::Pending } else { Severity::Fixed }, finding);
+    }
+
+    fn push(&mut self, severity: Severity, mut finding: Finding) {
+        finding.severity = severity;
+        if self.text() {
+            println!("    {} {}", severity.tag(), finding.message);
+        }
+        self.findings.push(finding);
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    /// Pide la passphrase: con JSON el pedido va a stderr
+    pub fn prompt(&self, message: &str) -> std::io::Result<()> {
+        use std::io::Write;
+        if self.format == Format::Json {
+            eprint!("{}", message);
+            std::io::stderr().flush()
+        } else {
+            print!("{}", message);
+            std::io::stdout().flush()
+        }
+    }
+
+    /// Imprime el reporte JSON (en texto ya está todo impreso).
+    /// `remaining` son los errores que quedaron después de reparar.
+    pub fn finish(&self, exit_code: u8, remaining: &[Finding], failure: Option<&anyhow::Error>) {
+        if self.format != Format::Json {
+            return;
+        }
+        let summary = serde_json::json!({
+            "exit_code": exit_code,
+            "error": failure.map(|e| format!("{:#}", e)),
+            "errors": self.count(Severity::Error),
+            "warnings": self.count(Severity::Warning),
+            "fixed": self.count(Severity::Fixed),
+            "pending": self.count(Severity::Pending),
+            "remaining_errors": remaining.len(),
+            "findings": self.findings,
+            "remaining": remaining,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap_or_default());
+    }
+}
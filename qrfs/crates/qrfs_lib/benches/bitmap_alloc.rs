@@ -0,0 +1,60 @@
+// Mide el asignador de `Bitmap` sobre mapas grandes.
+// Uso: cargo bench -p qrfs_lib --bench bitmap_alloc
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use qrfs_lib::bitmap::Bitmap;
+
+// 4M bloques: un volumen de ~4 GiB
+const TOTAL_BLOCKS: usize = 4 * 1024 * 1024;
+const ALLOCATIONS: usize = 10_000;
+
+/// Mapa casi lleno (95%) con los huecos repartidos, como un volumen muy usado
+fn fragmented_bitmap() -> Bitmap {
+    let mut bitmap = Bitmap::new(TOTAL_BLOCKS);
+    for i in 0..TOTAL_BLOCKS {
+        bitmap.set(i, i % 20 != 0);
+    }
+    bitmap
+}
+
+fn run(name: &str, mut f: impl FnMut(&mut Bitmap)) {
+    let mut bitmap = fragmented_bitmap();
+    let start = Instant::now();
+    f(&mut bitmap);
+    let elapsed = start.elapsed();
+    println!(
+        "{:<40} {:>10.2?} total, {:>8.0?} por bloque",
+        name, elapsed, elapsed / ALLOCATIONS as u32
+    );
+}
+
+fn main() {
+    println!("Bitmap de {} bloques, {} asignaciones", TOTAL_BLOCKS, ALLOCATIONS);
+
+    run("allocate (next-fit)", |b| {
+        for _ in 0..ALLOCATIONS { black_box(b.allocate()); }
+    });
+
+    run("allocate_near(0) (equivale a first-fit)", |b| {
+        for _ in 0..ALLOCATIONS { black_box(b.allocate_near(0)); }
+    });
+
+    run("allocate_near(mitad del volumen)", |b| {
+        let goal = (TOTAL_BLOCKS / 2) as u64;
+        for _ in 0..ALLOCATIONS { black_box(b.allocate_near(goal)); }
+    });
+
+    run("allocate_many (una sola llamada)", |b| {
+        black_box(b.allocate_many(ALLOCATIONS, 0));
+    });
+
+    // Peor caso: mapa lleno salvo el último bloque
+    let mut full = Bitmap::new(TOTAL_BLOCKS);
+    for i in 0..TOTAL_BLOCKS - 1 { full.set(i, true); }
+    let start = Instant::now();
+    black_box(full.allocate_near(0));
+    let elapsed: Duration = start.elapsed();
+    println!("{:<40} {:>10.2?}", "último libre de 4M (escaneo por palabras)", elapsed);
+}
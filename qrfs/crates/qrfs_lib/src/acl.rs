@@ -0,0 +1,298 @@
+use thiserror::Error;
+
+use crate::perm::{Caller, MAY_EXEC, MAY_READ, MAY_WRITE};
+use crate::types::Inode;
+
+// Nombres de los xattrs donde el kernel pasa las ACLs
+pub const ACL_ACCESS_XATTR: &[u8] = b"system.posix_acl_access";
+pub const ACL_DEFAULT_XATTR: &[u8] = b"system.posix_acl_default";
+
+// Formato binario de linux/posix_acl_xattr.h:
+// [versión u32] + N x [tag u16, permisos u16, id u32], todo little-endian
+const ACL_XATTR_VERSION: u32 = 2;
+const ENTRY_LEN: usize = 8;
+const UNDEFINED_ID: u32 = u32::MAX;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum AclError {
+    #[error("ACL con formato inválido")]
+    Malformed,
+    #[error("ACL incompleta o con entradas repetidas")]
+    Invalid,
+}
+
+/// Tipos de entrada (en el orden en que deben aparecer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AclTag {
+    UserObj,
+    User,
+    GroupObj,
+    Group,
+    Mask,
+    Other,
+}
+
+impl AclTag {
+    fn to_raw(self) -> u16 {
+        match self {
+            AclTag::UserObj => 0x01,
+            AclTag::User => 0x02,
+            AclTag::GroupObj => 0x04,
+            AclTag::Group => 0x08,
+            AclTag::Mask => 0x10,
+            AclTag::Other => 0x20,
+        }
+    }
+
+    fn from_raw(raw: u16) -> Option<Self> {
+        match raw {
+            0x01 => Some(AclTag::UserObj),
+            0x02 => Some(AclTag::User),
+            0x04 => Some(AclTag::GroupObj),
+            0x08 => Some(AclTag::Group),
+            0x10 => Some(AclTag::Mask),
+            0x20 => Some(AclTag::Other),
+            _ => None,
+        }
+    }
+
+    fn is_named(self) -> bool {
+        matches!(self, AclTag::User | AclTag::Group)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    pub perm: u16, // rwx en los 3 bits bajos
+    pub id: u32,   // uid/gid para User/Group, sin uso en el resto
+}
+
+/// Una ACL POSIX (la de acceso de un inodo, o la "default" de un directorio)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acl {
+    entries: Vec<AclEntry>, // Ordenadas por (tag, id)
+}
+
+impl Acl {
+    /// ACL mínima equivalente a los bits de un modo
+    pub fn from_mode(mode: u16) -> Self {
+        Self {
+            entries: vec![
+                AclEntry { tag: AclTag::UserObj, perm: (mode >> 6) & 0o7, id: UNDEFINED_ID },
+                AclEntry { tag: AclTag::GroupObj, perm: (mode >> 3) & 0o7, id: UNDEFINED_ID },
+                AclEntry { tag: AclTag::Other, perm: mode & 0o7, id: UNDEFINED_ID },
+            ],
+        }
+    }
+
+    /// Decodifica y valida el valor del xattr
+    pub fn from_xattr(value: &[u8]) -> Result<Self, AclError> {
+        if value.len() < 4 || !(value.len() - 4).is_multiple_of(ENTRY_LEN) {
+            return Err(AclError::Malformed);
+        }
+        let version = u32::from_le_bytes(value[..4].try_into().unwrap());
+        if version != ACL_XATTR_VERSION {
+            return Err(AclError::Malformed);
+        }
+
+        let mut entries = Vec::new();
+        for raw in value[4..].chunks_exact(ENTRY_LEN) {
+            let tag = AclTag::from_raw(u16::from_le_bytes([raw[0], raw[1]])).ok_or(AclError::Malformed)?;
+            let perm = u16::from_le_bytes([raw[2], raw[3]]);
+            if perm & !0o7 != 0 {
+                return Err(AclError::Malformed);
+            }
+            let id = if tag.is_named() { u32::from_le_bytes(raw[4..8].try_into().unwrap()) } else { UNDEFINED_ID };
+            entries.push(AclEntry { tag, perm, id });
+        }
+
+        let mut acl = Self { entries };
+        acl.validate()?;
+        acl.entries.sort_by_key(|e| (e.tag, e.id));
+        Ok(acl)
+    }
+
+    /// Codifica en el formato del xattr
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut out = ACL_XATTR_VERSION.to_le_bytes().to_vec();
+        for e in &self.entries {
+            out.extend_from_slice(&e.tag.to_raw().to_le_bytes());
+            out.extend_from_slice(&e.perm.to_le_bytes());
+            out.extend_from_slice(&e.id.to_le_bytes());
+        }
+        out
+    }
+
+    /// Reglas de acl(5): un USER_OBJ, un GROUP_OBJ y un OTHER; sin repetidos;
+    /// y si hay entradas con nombre, hace falta una MASK.
+    fn validate(&self) -> Result<(), AclError> {
+        let mut sorted = self.entries.clone();
+        sorted.sort_by_key(|e| (e.tag, e.id));
+        if sorted.windows(2).any(|w| w[0].tag == w[1].tag && (!w[0].tag.is_named() || w[0].id == w[1].id)) {
+            return Err(AclError::Invalid);
+        }
+
+        let count = |tag| sorted.iter().filter(|e| e.tag == tag).count();
+        let named = sorted.iter().any(|e| e.tag.is_named());
+        if count(AclTag::UserObj) != 1 || count(AclTag::GroupObj) != 1 || count(AclTag::Other) != 1 {
+            return Err(AclError::Invalid);
+        }
+        if named && count(AclTag::Mask) != 1 {
+            return Err(AclError::Invalid);
+        }
+        Ok(())
+    }
+
+    fn find(&self, tag: AclTag) -> Option<&AclEntry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+
+    fn find_mut(&mut self, tag: AclTag) -> Option<&mut AclEntry> {
+        self.entries.iter_mut().find(|e| e.tag == tag)
+    }
+
+    /// ¿Dice lo mismo que los bits del modo? (entonces no hace falta guardarla)
+    pub fn is_minimal(&self) -> bool {
+        self.entries.len() == 3
+    }
+
+    /// Bits rwx del modo que corresponden a esta ACL.
+    /// Con MASK, los bits de grupo del modo muestran la máscara (como en Linux).
+    pub fn mode_bits(&self) -> u16 {
+        let perm = |tag| self.find(tag).map(|e| e.perm).unwrap_or(0);
+        let group = self.find(AclTag::Mask).map(|e| e.perm).unwrap_or_else(|| perm(AclTag::GroupObj));
+        (perm(AclTag::UserObj) << 6) | (group << 3) | perm(AclTag::Other)
+    }
+
+    /// chmod sobre un archivo con ACL: los bits nuevos van a USER_OBJ,
+    /// MASK (o GROUP_OBJ si no hay máscara) y OTHER
+    pub fn apply_mode(&mut self, mode: u16) {
+        if let Some(e) = self.find_mut(AclTag::UserObj) { e.perm = (mode >> 6) & 0o7; }
+        let group_tag = if self.find(AclTag::Mask).is_some() { AclTag::Mask } else { AclTag::GroupObj };
+        if let Some(e) = self.find_mut(group_tag) { e.perm = (mode >> 3) & 0o7; }
+        if let Some(e) = self.find_mut(AclTag::Other) { e.perm = mode & 0o7; }
+    }
+
+    /// ACL de acceso que hereda algo creado en un directorio con esta ACL "default",
+    /// recortada por el modo pedido al crear (igual que hace el kernel)
+    pub fn inherit(&self, create_mode: u16) -> Self {
+        let mut acl = self.clone();
+        acl.apply_mode(self.mode_bits() & create_mode);
+        acl
+    }
+
+    /// Chequeo de acceso de acl(5). Root, igual que con los bits del modo,
+    /// lee y escribe todo y ejecuta si algún bit x está puesto.
+    /// Cuentan el grupo principal y los suplementarios de quien llama.
+    pub fn permits(&self, inode: &Inode, caller: &Caller, mask: i32) -> bool {
+        let want = (mask & (MAY_READ | MAY_WRITE | MAY_EXEC)) as u16;
+        if caller.is_root() {
+            return want & MAY_EXEC as u16 == 0 || self.mode_bits() & 0o111 != 0;
+        }
+
+        let acl_mask = self.find(AclTag::Mask).map(|e| e.perm).unwrap_or(0o7);
+        let grants = |perm: u16| perm & want == want;
+
+        // 1. Dueño
+        if caller.uid == inode.uid {
+            return self.find(AclTag::UserObj).is_some_and(|e| grants(e.perm));
+        }
+        // 2. Usuario con nombre
+        if let Some(e) = self.entries.iter().find(|e| e.tag == AclTag::User && e.id == caller.uid) {
+            return grants(e.perm & acl_mask);
+        }
+        // 3. Grupos: si alguno coincide, alguno tiene que alcanzar; si no, se deniega
+        let groups: Vec<&AclEntry> = self.entries.iter()
+            .filter(|e| match e.tag {
+                AclTag::GroupObj => caller.in_group(inode.gid),
+                AclTag::Group => caller.in_group(e.id),
+                _ => false,
+            })
+            .collect();
+        if !groups.is_empty() {
+            return groups.iter().any(|e| grants(e.perm & acl_mask));
+        }
+        // 4. Otros
+        self.find(AclTag::Other).is_some_and(|e| grants(e.perm))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FileType;
+
+    const ALICE: Caller = Caller { uid: 1000, gid: 1000, groups: Vec::new() };
+    const BOB: Caller = Caller { uid: 1001, gid: 1001, groups: Vec::new() };
+    const CAROL: Caller = Caller { uid: 1002, gid: 2000, groups: Vec::new() };
+    const EVE: Caller = Caller { uid: 1003, gid: 1003, groups: Vec::new() };
+
+    fn entry(tag: AclTag, perm: u16, id: u32) -> AclEntry {
+        AclEntry { tag, perm, id }
+    }
+
+    /// user::rw- user:bob:rw- group::r-- group:2000:rw- mask::rw- other::---
+    fn shared_acl() -> Acl {
+        Acl {
+            entries: vec![
+                entry(AclTag::UserObj, 0o6, UNDEFINED_ID),
+                entry(AclTag::User, 0o6, 1001),
+                entry(AclTag::GroupObj, 0o4, UNDEFINED_ID),
+                entry(AclTag::Group, 0o6, 2000),
+                entry(AclTag::Mask, 0o6, UNDEFINED_ID),
+                entry(AclTag::Other, 0o0, UNDEFINED_ID),
+            ],
+        }
+    }
+
+    fn alices_file() -> Inode {
+        let mut inode = Inode::new(FileType::File, 0o660);
+        inode.uid = 1000;
+        inode.gid = 1000;
+        inode
+    }
+
+    #[test]
+    fn test_xattr_round_trip_and_validation() {
+        let acl = shared_acl();
+        assert_eq!(Acl::from_xattr(&acl.to_xattr()).unwrap(), acl);
+
+        // Sin máscara no puede haber entradas con nombre
+        let mut no_mask = acl.clone();
+        no_mask.entries.retain(|e| e.tag != AclTag::Mask);
+        assert_eq!(Acl::from_xattr(&no_mask.to_xattr()), Err(AclError::Invalid));
+
+        assert_eq!(Acl::from_xattr(&[1, 0, 0, 0]), Err(AclError::Malformed));
+        assert_eq!(Acl::from_xattr(&[2, 0, 0, 0, 1]), Err(AclError::Malformed));
+        assert!(Acl::from_mode(0o640).is_minimal());
+    }
+
+    #[test]
+    fn test_permission_check() {
+        let acl = shared_acl();
+        let file = alices_file();
+        assert!(acl.permits(&file, &ALICE, MAY_READ | MAY_WRITE));
+        assert!(acl.permits(&file, &BOB, MAY_WRITE));   // Usuario con nombre
+        assert!(acl.permits(&file, &CAROL, MAY_WRITE)); // Grupo con nombre
+        assert!(!acl.permits(&file, &EVE, MAY_READ));   // Otros: nada
+
+        // La máscara limita a las entradas con nombre, no al dueño
+        let mut masked = acl.clone();
+        masked.apply_mode(0o640);
+        assert_eq!(masked.mode_bits(), 0o640);
+        assert!(!masked.permits(&file, &BOB, MAY_WRITE));
+        assert!(masked.permits(&file, &BOB, MAY_READ));
+        assert!(masked.permits(&file, &ALICE, MAY_WRITE));
+    }
+
+    #[test]
+    fn test_inherit_from_default() {
+        let default = shared_acl();
+        // Un archivo creado con 0644: la máscara queda en r-- y el dueño en rw-
+        let inherited = default.inherit(0o644);
+        assert_eq!(inherited.mode_bits(), 0o640);
+        assert!(!inherited.is_minimal());
+        assert!(!inherited.permits(&alices_file(), &BOB, MAY_WRITE));
+    }
+}
@@ -1,32 +1,129 @@
-use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// Bytes del mapa por página en disco (6400 bloques por QR; serializada y cifrada cabe en un bloque)
+pub const BITMAP_BYTES_PER_BLOCK: usize = 800;
+
+/// Mapa de bits de bloques (o de inodos).
+/// En disco es una región de páginas consecutivas de `BITMAP_BYTES_PER_BLOCK` bytes,
+/// y solo se reescriben las páginas sucias (igual que `RefCounts`).
+#[derive(Debug, Clone)]
 pub struct Bitmap {
-    pub bits: Vec<u8>,
+    bits: Vec<u8>,
     pub size: usize, // Cantidad total de bloques que rastreamos
+    used: usize,     // Bits en 1, mantenido en cada cambio (free_count sin recorrer el mapa)
+    hint: usize,     // Dónde sigue buscando `allocate` (next-fit); no se guarda en disco
+    dirty: BTreeSet<usize>,
 }
 
 impl Bitmap {
     /// Crea un mapa nuevo con todos los bloques marcados como LIBRES (0).
     pub fn new(total_blocks: usize) -> Self {
         // Necesitamos 1 byte por cada 8 bloques.
-        // (total_blocks + 7) / 8 es una forma de hacer ceil div.
-        let byte_len = (total_blocks + 7) / 8;
+        let byte_len = total_blocks.div_ceil(8);
         Self {
             bits: vec![0; byte_len],
             size: total_blocks,
+            used: 0,
+            hint: 0,
+            dirty: BTreeSet::new(),
         }
     }
 
-    /// Busca el primer bloque libre, lo marca como ocupado y devuelve su índice.
+    /// Cuántas páginas (bloques en disco) hacen falta para `total_bits` bits
+    pub fn pages_for(total_bits: u64) -> u64 {
+        total_bits.div_ceil(8).div_ceil(BITMAP_BYTES_PER_BLOCK as u64).max(1)
+    }
+
+    pub fn page_count(&self) -> usize {
+        Self::pages_for(self.size as u64) as usize
+    }
+
+    /// Carga una página leída de disco
+    pub fn load_page(&mut self, page: usize, bytes: &[u8]) {
+        let start = (page * BITMAP_BYTES_PER_BLOCK).min(self.bits.len());
+        let len = bytes.len().min(BITMAP_BYTES_PER_BLOCK).min(self.bits.len() - start);
+        let range = start..start + len;
+
+        self.used -= popcount(&self.bits[range.clone()]);
+        self.bits[range.clone()].copy_from_slice(&bytes[..len]);
+        // Bits más allá del final del mapa no cuentan (ni deberían estar en 1)
+        if range.end == self.bits.len() && !self.size.is_multiple_of(8) {
+            self.bits[range.end - 1] &= (1 << (self.size % 8)) - 1;
+        }
+        self.used += popcount(&self.bits[range]);
+    }
+
+    /// Contenido de una página (para serializar y guardar). La última puede ser más corta.
+    pub fn page(&self, page: usize) -> &[u8] {
+        let start = (page * BITMAP_BYTES_PER_BLOCK).min(self.bits.len());
+        let end = (start + BITMAP_BYTES_PER_BLOCK).min(self.bits.len());
+        &self.bits[start..end]
+    }
+
+    /// Devuelve (y olvida) las páginas modificadas desde la última sincronización
+    pub fn take_dirty(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.dirty).into_iter().collect()
+    }
+
+    /// Busca un bloque libre a partir de donde terminó la asignación anterior
+    /// (next-fit), lo marca como ocupado y devuelve su índice.
     pub fn allocate(&mut self) -> Option<u64> {
-        for i in 0..self.size {
-            if !self.get(i) {
-                self.set(i, true);
-                return Some(i as u64);
+        self.allocate_near(self.hint as u64)
+    }
+
+    /// Como `allocate`, pero buscando desde `goal` (ej. el bloque siguiente al
+    /// último del archivo, para que sus bloques queden juntos). Da la vuelta al final.
+    pub fn allocate_near(&mut self, goal: u64) -> Option<u64> {
+        if self.used >= self.size { return None; } // Disco lleno
+        let goal = (goal as usize).min(self.size);
+        let found = self.find_free(goal, self.size).or_else(|| self.find_free(0, goal))?;
+        self.set(found, true);
+        self.hint = found + 1;
+        Some(found as u64)
+    }
+
+    /// Reserva `count` bloques de una vez, lo más seguidos posible a partir de `goal`.
+    /// Todo o nada: si no hay suficientes libres no toca el mapa.
+    pub fn allocate_many(&mut self, count: usize, goal: u64) -> Option<Vec<u64>> {
+        if self.free_count() < count as u64 { return None; }
+        let mut blocks = Vec::with_capacity(count);
+        let mut next = goal;
+        for _ in 0..count {
+            let block = self.allocate_near(next)?;
+            blocks.push(block);
+            next = block + 1;
+        }
+        Some(blocks)
+    }
+
+    /// Reserva el primer bloque libre por debajo de `limit` (para mudar bloques
+    /// antes de achicar el volumen). No toca la pista de `allocate`.
+    pub fn allocate_below(&mut self, limit: u64) -> Option<u64> {
+        let found = self.find_free(0, (limit as usize).min(self.size))?;
+        self.set(found, true);
+        Some(found as u64)
+    }
+
+    /// Primer bit en 0 dentro de [from, to). Salta de a 64 bits las zonas llenas.
+    fn find_free(&self, from: usize, to: usize) -> Option<usize> {
+        let mut i = from;
+        while i < to {
+            if i.is_multiple_of(64) && i + 64 <= to {
+                let word = self.word(i / 64);
+                if word == u64::MAX { i += 64; continue; }
+                return Some(i + (!word).trailing_zeros() as usize);
             }
+            if !self.get(i) { return Some(i); }
+            i += 1;
         }
-        None // Disco lleno
+        None
+    }
+
+    /// 64 bits del mapa como un entero (el bit j es el bloque 64*w + j)
+    fn word(&self, w: usize) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.bits[w * 8..w * 8 + 8]);
+        u64::from_le_bytes(bytes)
     }
 
     /// Marca un bloque específico (ej. los del sistema) como ocupado/libre.
@@ -35,12 +132,21 @@ impl Bitmap {
         
         let byte_idx = index / 8;
         let bit_idx = index % 8;
+        if self.get(index) == value { return; }
         
         if value {
             self.bits[byte_idx] |= 1 << bit_idx; // Poner bit en 1
+            self.used += 1;
         } else {
             self.bits[byte_idx] &= !(1 << bit_idx); // Poner bit en 0
+            self.used -= 1;
         }
+        self.dirty.insert(byte_idx / BITMAP_BYTES_PER_BLOCK);
+    }
+
+    /// Cantidad de bloques libres
+    pub fn free_count(&self) -> u64 {
+        (self.size - self.used) as u64
     }
 
     /// Verifica si un bloque está ocupado.
@@ -57,10 +163,13 @@ impl Bitmap {
         if new_total_blocks > self.size {
             // --- CRECIMIENTO ---
             // Calcular nuevos bytes necesarios
-            let new_byte_len = (new_total_blocks + 7) / 8;
+            let new_byte_len = new_total_blocks.div_ceil(8);
             // Extender con ceros (libre)
+            let old_pages = self.page_count();
             self.bits.resize(new_byte_len, 0);
             self.size = new_total_blocks;
+            // La última página vieja puede haber crecido, y las nuevas hay que escribirlas
+            self.dirty.extend(old_pages.saturating_sub(1)..self.page_count());
         } else if new_total_blocks < self.size {
             // --- REDUCCIÓN ---
             // Verificar que no estemos cortando datos
@@ -70,23 +179,29 @@ impl Bitmap {
                 }
             }
             // Si es seguro, cortamos el vector
-            let new_byte_len = (new_total_blocks + 7) / 8;
+            let new_byte_len = new_total_blocks.div_ceil(8);
             self.bits.truncate(new_byte_len);
             
             // Limpiar los bits sobrantes del último byte (para que no queden 'sucios')
-            if new_total_blocks % 8 != 0 {
+            if !new_total_blocks.is_multiple_of(8) {
                 let last_idx = self.bits.len() - 1;
                 let mask = (1 << (new_total_blocks % 8)) - 1;
                 self.bits[last_idx] &= mask;
             }
             
             self.size = new_total_blocks;
+            self.dirty.insert(self.page_count() - 1);
         }
         Ok(())
     }
 }
 
+fn popcount(bytes: &[u8]) -> usize {
+    bytes.iter().map(|b| b.count_ones() as usize).sum()
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -111,5 +226,78 @@ mod tests {
         // Debería encontrar el 1 (el 0 está ocupado)
         let second = bitmap.allocate().unwrap();
         assert_eq!(second, 1);
+        assert_eq!(bitmap.free_count(), 13);
+
+        // Next-fit: un bloque liberado atrás no se reusa hasta dar la vuelta
+        bitmap.set(0, false);
+        assert_eq!(bitmap.allocate(), Some(2));
+
+        // Lleno: allocate() ya no encuentra nada
+        while bitmap.allocate().is_some() {}
+        assert_eq!(bitmap.free_count(), 0);
+        assert_eq!(bitmap.allocate(), None);
+    }
+
+    #[test]
+    fn test_allocate_near_and_wrap() {
+        let mut bitmap = Bitmap::new(200);
+        for i in 0..200 { bitmap.set(i, i % 3 != 0); } // Libres: múltiplos de 3
+
+        assert_eq!(bitmap.allocate_near(100), Some(102));
+        assert_eq!(bitmap.allocate_near(199), Some(0)); // Da la vuelta
+        // Palabras de 64 bits llenas se saltan enteras
+        for i in 64..192 { bitmap.set(i, true); }
+        assert_eq!(bitmap.allocate_near(64), Some(192));
+    }
+
+    #[test]
+    fn test_allocate_many() {
+        let mut bitmap = Bitmap::new(300);
+        bitmap.set(12, true);
+        assert_eq!(bitmap.allocate_many(4, 10), Some(vec![10, 11, 13, 14]));
+
+        // Todo o nada
+        let free = bitmap.free_count();
+        assert_eq!(bitmap.allocate_many(free as usize + 1, 0), None);
+        assert_eq!(bitmap.free_count(), free);
+        assert_eq!(bitmap.allocate_many(free as usize, 0).map(|b| b.len()), Some(free as usize));
+        assert_eq!(bitmap.allocate(), None);
+    }
+
+    #[test]
+    fn test_allocate_below() {
+        let mut bitmap = Bitmap::new(300);
+        for i in 0..10 { bitmap.set(i, true); }
+        bitmap.set(11, true);
+        assert_eq!(bitmap.allocate_below(12), Some(10));
+        assert_eq!(bitmap.allocate_below(12), None);
+        assert_eq!(bitmap.allocate_below(1000), Some(12));
+        assert_eq!(bitmap.allocate(), Some(13));
+    }
+
+    #[test]
+    fn test_pages_round_trip() {
+        let total = BITMAP_BYTES_PER_BLOCK * 8 + 100; // Dos páginas, la segunda corta
+        let mut bitmap = Bitmap::new(total);
+        assert_eq!(bitmap.page_count(), 2);
+
+        bitmap.set(total - 1, true);
+        assert_eq!(bitmap.take_dirty(), vec![1]); // Solo se ensució la segunda
+
+        let mut loaded = Bitmap::new(total);
+        for page in 0..bitmap.page_count() {
+            loaded.load_page(page, bitmap.page(page));
+        }
+        assert!(loaded.get(total - 1));
+        assert_eq!(loaded.free_count(), total as u64 - 1);
+
+        // Volver a cargar la misma página no cuenta dos veces
+        loaded.load_page(1, bitmap.page(1));
+        assert_eq!(loaded.free_count(), total as u64 - 1);
+
+        // Crecer agrega páginas sucias para escribir
+        loaded.take_dirty();
+        loaded.resize(total + BITMAP_BYTES_PER_BLOCK * 8).unwrap();
+        assert_eq!(loaded.take_dirty(), vec![1, 2]);
     }
 }
\ No newline at end of file
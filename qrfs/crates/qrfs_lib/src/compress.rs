@@ -0,0 +1,175 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::types::BLOCK_SIZE;
+
+// Formato de un bloque de datos (ANTES de cifrar): [FLAG (1 byte)] [CONTENIDO]
+// El flag indica si el contenido se guardó tal cual o comprimido.
+const FLAG_RAW: u8 = 0;
+const FLAG_DEFLATE: u8 = 1;
+
+// Máximo de bytes que puede entregar un bloque al descomprimirse.
+// Protege contra "bombas" de compresión y acota el trabajo de pack_chunk.
+const MAX_UNPACKED: usize = BLOCK_SIZE * 16;
+
+#[derive(Error, Debug)]
+pub enum CompressError {
+    #[error("Bloque con formato desconocido (flag {0})")]
+    UnknownFlag(u8),
+    #[error("Bloque comprimido corrupto")]
+    Corrupt,
+}
+
+/// Algoritmo de compresión del volumen (se guarda en el superbloque).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "deflate" => Ok(Compression::Deflate),
+            _ => Err(format!("Compresión desconocida '{}' (use none o deflate)", s)),
+        }
+    }
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+    // Escribir en un Vec en memoria no puede fallar
+    encoder.write_all(data).expect("escritura en memoria");
+    encoder.finish().expect("escritura en memoria")
+}
+
+/// Empaqueta el prefijo más largo de `data` que cabe en `capacity` bytes.
+/// Con compresión activa busca (búsqueda binaria) cuántos bytes de texto plano
+/// caben comprimidos; si los datos no comprimen, el bloque se guarda en crudo.
+/// Devuelve (bytes de `data` consumidos, bloque empaquetado).
+pub fn pack_chunk(data: &[u8], capacity: usize, compression: Compression) -> (usize, Vec<u8>) {
+    let raw_len = data.len().min(capacity - 1);
+    let raw_block = || {
+        let mut block = Vec::with_capacity(raw_len + 1);
+        block.push(FLAG_RAW);
+        block.extend_from_slice(&data[..raw_len]);
+        (raw_len, block)
+    };
+
+    if compression == Compression::None || data.is_empty() {
+        return raw_block();
+    }
+
+    // Intento rápido: ¿cabe todo lo que queda?
+    let max_len = data.len().min(MAX_UNPACKED);
+    let all = deflate(&data[..max_len]);
+    if all.len() < capacity {
+        return (max_len, [&[FLAG_DEFLATE][..], &all].concat());
+    }
+
+    // Si ni siquiera lo que cabría en crudo comprime, no vale la pena buscar
+    let at_raw = deflate(&data[..raw_len]);
+    if at_raw.len() >= capacity || raw_len == max_len {
+        return raw_block();
+    }
+
+    // Búsqueda binaria: `lo` siempre cabe comprimido, `hi` nunca
+    let (mut lo, mut hi) = (raw_len, max_len);
+    let mut best = at_raw;
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        let candidate = deflate(&data[..mid]);
+        if candidate.len() < capacity {
+            lo = mid;
+            best = candidate;
+        } else {
+            hi = mid;
+        }
+    }
+
+    if lo == raw_len {
+        // La compresión no ganó espacio: guardamos en crudo
+        return raw_block();
+    }
+    (lo, [&[FLAG_DEFLATE][..], &best].concat())
+}
+
+/// Recupera el texto plano de un bloque empaquetado con `pack_chunk`.
+pub fn unpack_chunk(block: &[u8]) -> Result<Vec<u8>, CompressError> {
+    let (&flag, content) = block.split_first().ok_or(CompressError::Corrupt)?;
+    match flag {
+        FLAG_RAW => Ok(content.to_vec()),
+        FLAG_DEFLATE => {
+            let mut out = Vec::new();
+            DeflateDecoder::new(content)
+                .take(MAX_UNPACKED as u64 + 1)
+                .read_to_end(&mut out)
+                .map_err(|_| CompressError::Corrupt)?;
+            if out.len() > MAX_UNPACKED {
+                return Err(CompressError::Corrupt);
+            }
+            Ok(out)
+        }
+        other => Err(CompressError::UnknownFlag(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, thread_rng};
+
+    const CAPACITY: usize = 900;
+
+    /// Empaqueta `data` completo en bloques y devuelve los bloques
+    fn pack_all(data: &[u8], compression: Compression) -> Vec<Vec<u8>> {
+        let mut blocks = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let (used, block) = pack_chunk(&data[pos..], CAPACITY, compression);
+            assert!(block.len() <= CAPACITY);
+            pos += used;
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_text_uses_fewer_blocks() {
+        let text = "fn main() { println!(\"Hola QRFS\"); }\n".repeat(200);
+
+        let plain = pack_all(text.as_bytes(), Compression::None);
+        let packed = pack_all(text.as_bytes(), Compression::Deflate);
+        assert!(packed.len() < plain.len());
+
+        // Al desempaquetar y concatenar volvemos al original
+        let restored: Vec<u8> = packed.iter()
+            .flat_map(|b| unpack_chunk(b).unwrap())
+            .collect();
+        assert_eq!(restored, text.as_bytes());
+    }
+
+    #[test]
+    fn test_incompressible_data_is_stored_raw() {
+        let mut data = vec![0u8; 3000];
+        thread_rng().fill(&mut data[..]);
+
+        let (used, block) = pack_chunk(&data, CAPACITY, Compression::Deflate);
+        assert_eq!(block[0], FLAG_RAW);
+        assert_eq!(used, CAPACITY - 1);
+        assert_eq!(unpack_chunk(&block).unwrap(), &data[..used]);
+    }
+
+    #[test]
+    fn test_unknown_flag() {
+        assert!(matches!(unpack_chunk(&[7, 1, 2, 3]), Err(CompressError::UnknownFlag(7))));
+        assert!(unpack_chunk(&[]).is_err());
+    }
+}
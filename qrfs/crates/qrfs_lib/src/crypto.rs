@@ -3,15 +3,23 @@ use aes_gcm::{
     Aes256Gcm, Nonce // Or `Key`
 };
 use pbkdf2::pbkdf2;
-use hmac::Hmac;
+use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use rand::{Rng, thread_rng};
 use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::dedup::ChunkHash;
 
 // Constantes de seguridad
-const SALT_LEN: usize = 16;
+pub const SALT_LEN: usize = 16;
 const KEY_LEN: usize = 32; // AES-256 necesita 32 bytes
 const ITERATIONS: u32 = 100_000; // Estándar de seguridad decente
+const DEDUP_KEY_LABEL: &[u8] = b"qrfs-dedup"; // Para derivar la llave de los hashes de dedup
+
+// Firma y versión del formato en la cabecera del bloque 0
+const HEADER_MAGIC: &[u8; 4] = b"QRFS";
+pub const FORMAT_VERSION: u8 = 1;
 
 #[derive(Error, Debug)]
 pub enum CryptoError {
@@ -19,11 +27,98 @@ pub enum CryptoError {
     EncryptionError,
     #[error("Datos corruptos o contraseña incorrecta")]
     DecryptionError,
+    #[error("Cabecera del volumen inválida o algoritmo desconocido")]
+    InvalidHeader,
+    #[error("El bloque 0 no tiene la firma de QRFS: el volumen es de un formato anterior (o no es QRFS) y hay que reformatearlo")]
+    UnknownLayout,
+    #[error("El volumen usa el formato v{found} y esta versión de QRFS solo lee v{FORMAT_VERSION}: actualice QRFS o reformatee el volumen")]
+    UnsupportedVersion { found: u8 },
+}
+
+/// Algoritmo con el que se protege el volumen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherKind {
+    /// Cifrado nulo: los bloques se guardan en claro (depuración, archivos públicos)
+    Null,
+    /// AES-256-GCM con clave derivada por PBKDF2 (valor por defecto)
+    Aes256Gcm,
+}
+
+impl CipherKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            CipherKind::Null => 0,
+            CipherKind::Aes256Gcm => 1,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(CipherKind::Null),
+            1 => Some(CipherKind::Aes256Gcm),
+            _ => None,
+        }
+    }
+}
+
+/// Cabecera EN CLARO al inicio del bloque 0:
+/// [MAGIC "QRFS" (4 bytes) | VERSIÓN (1 byte) | CIPHER (1 byte) | SALT (16 bytes)]
+/// Se lee antes que nada para saber si hay que pedir passphrase.
+#[derive(Debug, Clone, Copy)]
+pub struct VolumeHeader {
+    pub cipher: CipherKind,
+    pub salt: [u8; SALT_LEN],
+}
+
+impl VolumeHeader {
+    pub const LEN: usize = HEADER_MAGIC.len() + 2 + SALT_LEN;
+
+    /// Separa la cabecera del resto del bloque 0 (el superbloque, cifrado o no).
+    /// Los volúmenes sin firma (formato anterior) o de otra versión se rechazan
+    /// con un error que dice qué hacer, en vez de fallar al descifrar.
+    pub fn parse(block0: &[u8]) -> Result<(Self, &[u8]), CryptoError> {
+        if block0.len() < Self::LEN || !block0.starts_with(HEADER_MAGIC) {
+            return Err(CryptoError::UnknownLayout);
+        }
+        let (version, rest) = (block0[HEADER_MAGIC.len()], &block0[HEADER_MAGIC.len() + 1..]);
+        if version != FORMAT_VERSION {
+            return Err(CryptoError::UnsupportedVersion { found: version });
+        }
+        let cipher = CipherKind::from_byte(rest[0]).ok_or(CryptoError::InvalidHeader)?;
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&rest[1..1 + SALT_LEN]);
+        Ok((Self { cipher, salt }, &block0[Self::LEN..]))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(Self::LEN);
+        out.extend_from_slice(HEADER_MAGIC);
+        out.push(FORMAT_VERSION);
+        out.push(self.cipher.to_byte());
+        out.extend_from_slice(&self.salt);
+        out
+    }
+
+    /// ¿Hace falta pedir la passphrase para abrir este volumen?
+    pub fn needs_passphrase(&self) -> bool {
+        self.cipher != CipherKind::Null
+    }
+}
+
+/// Implementación concreta del cifrado
+enum Backend {
+    Aes(Box<Aes256Gcm>),
+    /// Identidad: encrypt/decrypt devuelven los mismos bytes
+    Identity,
 }
 
-/// Estructura que maneja la sesión criptográfica
+/// Estructura que maneja la sesión criptográfica.
+/// El cifrador vive en el heap (Box) para que su dirección no cambie y se pueda
+/// bloquear en RAM con `lock_in_memory`. Al soltarlo, las round keys se borran
+/// (features `zeroize` de aes/ghash).
 pub struct CryptoEngine {
-    cipher: Aes256Gcm,
+    backend: Backend,
+    mac_key: Zeroizing<[u8; KEY_LEN]>, // Llave HMAC para los hashes de deduplicación
     pub salt: [u8; SALT_LEN],
 }
 
@@ -38,7 +133,8 @@ impl CryptoEngine {
 
     /// Reconstruye el motor con un Salt existente (para mount)
     pub fn new(password: &str, salt: [u8; SALT_LEN]) -> Self {
-        let mut key = [0u8; KEY_LEN];
+        // La clave derivada se borra sola al salir de esta función
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
         
         // Derivar clave usando PBKDF2 (Password-Based Key Derivation Function 2)
         // Esto hace que sea lento para un atacante adivinar la contraseña
@@ -46,23 +142,91 @@ impl CryptoEngine {
             password.as_bytes(),
             &salt,
             ITERATIONS,
-            &mut key
+            key.as_mut()
         ).expect("HMAC can be initialized with any key length");
 
-        let cipher = Aes256Gcm::new(&key.into());
+        let cipher = Box::new(Aes256Gcm::new(key.as_ref().into()));
+
+        // Llave independiente para los hashes de dedup: HMAC(clave, etiqueta)
+        let mut kdf = <Hmac<Sha256> as Mac>::new_from_slice(key.as_ref())
+            .expect("HMAC can be initialized with any key length");
+        kdf.update(DEDUP_KEY_LABEL);
+        let mac_key = Zeroizing::new(kdf.finalize().into_bytes().into());
         
-        Self { cipher, salt }
+        Self { backend: Backend::Aes(cipher), mac_key, salt }
+    }
+
+    /// Motor sin cifrado (volúmenes creados con `--no-encryption`)
+    pub fn new_plaintext() -> Self {
+        // Sin secreto que proteger: los hashes usan una llave fija
+        Self {
+            backend: Backend::Identity,
+            mac_key: Zeroizing::new([0u8; KEY_LEN]),
+            salt: [0u8; SALT_LEN],
+        }
+    }
+
+    /// Construye el motor que corresponde a la cabecera del bloque 0.
+    /// En volúmenes sin cifrar la passphrase se ignora.
+    pub fn from_header(header: &VolumeHeader, password: &str) -> Self {
+        match header.cipher {
+            CipherKind::Null => Self::new_plaintext(),
+            CipherKind::Aes256Gcm => Self::new(password, header.salt),
+        }
+    }
+
+    pub fn cipher_kind(&self) -> CipherKind {
+        match self.backend {
+            Backend::Aes(_) => CipherKind::Aes256Gcm,
+            Backend::Identity => CipherKind::Null,
+        }
+    }
+
+    /// Cabecera que hay que escribir al inicio del bloque 0
+    pub fn header(&self) -> VolumeHeader {
+        VolumeHeader { cipher: self.cipher_kind(), salt: self.salt }
+    }
+
+    /// Bloquea en RAM las páginas donde vive el cifrador (mlock), para que la
+    /// clave expandida nunca termine en el swap.
+    pub fn lock_in_memory(&self) -> std::io::Result<()> {
+        let cipher = match &self.backend {
+            Backend::Aes(cipher) => cipher,
+            Backend::Identity => return Ok(()), // No hay clave que proteger
+        };
+        let ptr = &**cipher as *const Aes256Gcm as *const libc::c_void;
+        let len = std::mem::size_of::<Aes256Gcm>();
+        // SAFETY: el rango apunta a memoria válida y propia mientras `self` viva.
+        let ret = unsafe { libc::mlock(ptr, len) };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Hash con llave (HMAC-SHA256) de un fragmento en claro, para deduplicar
+    /// sin que el índice revele el contenido.
+    pub fn chunk_hash(&self, data: &[u8]) -> ChunkHash {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.mac_key.as_ref())
+            .expect("HMAC can be initialized with any key length");
+        mac.update(data);
+        mac.finalize().into_bytes().into()
     }
 
     /// Cifra datos. Retorna: [NONCE (12 bytes) | TEXTO CIFRADO | TAG (16 bytes)]
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = match &self.backend {
+            Backend::Aes(cipher) => cipher,
+            Backend::Identity => return Ok(data.to_vec()),
+        };
+
         // Generar un Nonce (Number used once) aleatorio para cada bloque
         let mut nonce_bytes = [0u8; 12];
         thread_rng().fill(&mut nonce_bytes);
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         // Cifrar
-        let ciphertext = self.cipher.encrypt(nonce, data)
+        let ciphertext = cipher.encrypt(nonce, data)
             .map_err(|_| CryptoError::EncryptionError)?;
 
         // Empaquetar todo junto: Nonce + Ciphertext
@@ -74,7 +238,13 @@ impl CryptoEngine {
     }
 
     /// Descifra datos. Espera formato: [NONCE | TEXTO CIFRADO]
-    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    /// El texto plano se devuelve envuelto en `Zeroizing` para que se borre al soltarlo.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+        let cipher = match &self.backend {
+            Backend::Aes(cipher) => cipher,
+            Backend::Identity => return Ok(Zeroizing::new(data.to_vec())),
+        };
+
         if data.len() < 12 {
             return Err(CryptoError::DecryptionError);
         }
@@ -84,10 +254,10 @@ impl CryptoEngine {
         let nonce = Nonce::from_slice(nonce_bytes);
 
         // Descifrar
-        let plaintext = self.cipher.decrypt(nonce, ciphertext)
+        let plaintext = cipher.decrypt(nonce, ciphertext)
             .map_err(|_| CryptoError::DecryptionError)?;
 
-        Ok(plaintext)
+        Ok(Zeroizing::new(plaintext))
     }
 }
 
@@ -117,7 +287,7 @@ mod tests {
         let decrypted = engine_mount.decrypt(&encrypted).expect("Fallo al descifrar");
 
         // 5. Verificar
-        assert_eq!(data.to_vec(), decrypted);
+        assert_eq!(data.to_vec(), *decrypted);
     }
 
     #[test]
@@ -134,4 +304,63 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_chunk_hash_depends_on_key() {
+        let engine = CryptoEngine::new_with_random_salt("clave");
+        let same = CryptoEngine::new("clave", engine.salt);
+        let other = CryptoEngine::new("otra", engine.salt);
+
+        let data = b"config.toml repetido";
+        assert_eq!(engine.chunk_hash(data), same.chunk_hash(data));
+        assert_ne!(engine.chunk_hash(data), other.chunk_hash(data));
+        assert_ne!(engine.chunk_hash(data), engine.chunk_hash(b"otro contenido"));
+    }
+
+    #[test]
+    fn test_plaintext_volume_header() {
+        let engine = CryptoEngine::new_plaintext();
+        let data = b"Superbloque publico";
+
+        // El cifrado nulo no altera los datos
+        let stored = engine.encrypt(data).unwrap();
+        assert_eq!(data.to_vec(), stored);
+
+        // La cabecera del bloque 0 indica que no hay que pedir passphrase
+        let mut block0 = engine.header().to_bytes();
+        block0.extend_from_slice(&stored);
+        let (header, payload) = VolumeHeader::parse(&block0).unwrap();
+        assert_eq!(header.cipher, CipherKind::Null);
+        assert!(!header.needs_passphrase());
+
+        let reopened = CryptoEngine::from_header(&header, "");
+        assert_eq!(data.to_vec(), *reopened.decrypt(payload).unwrap());
+    }
+
+    #[test]
+    fn test_header_rejects_other_layouts() {
+        let engine = CryptoEngine::new_plaintext();
+        let mut block0 = engine.header().to_bytes();
+        block0.extend_from_slice(b"superbloque");
+
+        // Formato anterior: [SALT | superbloque], sin firma
+        let mut legacy = engine.salt.to_vec();
+        legacy.extend_from_slice(b"superbloque");
+        assert!(matches!(VolumeHeader::parse(&legacy), Err(CryptoError::UnknownLayout)));
+
+        // Una versión futura del formato
+        let mut future = block0.clone();
+        future[HEADER_MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            VolumeHeader::parse(&future),
+            Err(CryptoError::UnsupportedVersion { found }) if found == FORMAT_VERSION + 1
+        ));
+
+        // Firma y versión correctas pero algoritmo desconocido
+        let mut unknown_cipher = block0.clone();
+        unknown_cipher[HEADER_MAGIC.len() + 1] = 0xff;
+        assert!(matches!(VolumeHeader::parse(&unknown_cipher), Err(CryptoError::InvalidHeader)));
+
+        assert!(VolumeHeader::parse(&block0).is_ok());
+    }
 }
\ No newline at end of file
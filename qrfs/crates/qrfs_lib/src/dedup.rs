@@ -0,0 +1,151 @@
+use std::collections::{BTreeSet, HashMap};
+use serde::{Serialize, Deserialize};
+
+// Entradas por cubeta: 20 * 40 bytes + 8 del largo del Vec, cabe cifrado en un QR
+pub const ENTRIES_PER_BUCKET: usize = 20;
+
+// Una cubeta del índice por cada N bloques del volumen
+const BLOCKS_PER_BUCKET: u64 = 32;
+
+/// Hash con llave (HMAC-SHA256) del texto plano de un fragmento.
+/// Con llave para que el índice no revele el contenido.
+pub type ChunkHash = [u8; 32];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub hash: ChunkHash,
+    pub block: u64,
+}
+
+/// Índice de fragmentos para deduplicación: hash -> bloque que ya guarda ese contenido.
+/// En disco es una tabla hash de cubetas de tamaño fijo (una cubeta = un bloque).
+/// Es "best effort": si una cubeta se llena, ese fragmento simplemente no se deduplica.
+#[derive(Debug, Clone)]
+pub struct ChunkIndex {
+    buckets: Vec<Vec<IndexEntry>>,
+    by_block: HashMap<u64, ChunkHash>, // Índice inverso, para borrar al liberar un bloque
+    dirty: BTreeSet<usize>,
+}
+
+impl ChunkIndex {
+    pub fn new(buckets: usize) -> Self {
+        Self {
+            buckets: vec![Vec::new(); buckets],
+            by_block: HashMap::new(),
+            dirty: BTreeSet::new(),
+        }
+    }
+
+    /// Cubetas que reserva mkfs para un volumen de `total_blocks` bloques
+    pub fn buckets_for(total_blocks: u64) -> u64 {
+        total_blocks.div_ceil(BLOCKS_PER_BUCKET).max(1)
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.buckets.len()
+    }
+
+    fn bucket_of(&self, hash: &ChunkHash) -> usize {
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&hash[..8]);
+        (u64::from_le_bytes(prefix) % self.buckets.len() as u64) as usize
+    }
+
+    /// Carga una cubeta leída de disco
+    pub fn load_bucket(&mut self, bucket: usize, entries: Vec<IndexEntry>) {
+        for entry in &entries {
+            self.by_block.insert(entry.block, entry.hash);
+        }
+        self.buckets[bucket] = entries;
+    }
+
+    /// Contenido de una cubeta (para serializar y guardar)
+    pub fn bucket(&self, bucket: usize) -> &Vec<IndexEntry> {
+        &self.buckets[bucket]
+    }
+
+    /// Todas las entradas del índice (para fsck)
+    pub fn entries(&self) -> impl Iterator<Item = &IndexEntry> {
+        self.buckets.iter().flatten()
+    }
+
+    /// ¿Qué bloque guarda ya este contenido?
+    pub fn lookup(&self, hash: &ChunkHash) -> Option<u64> {
+        self.buckets[self.bucket_of(hash)]
+            .iter()
+            .find(|e| &e.hash == hash)
+            .map(|e| e.block)
+    }
+
+    /// Registra que `block` guarda el fragmento `hash`.
+    /// Retorna false si la cubeta está llena (el fragmento no queda indexado).
+    pub fn insert(&mut self, hash: ChunkHash, block: u64) -> bool {
+        // Un bloque guarda un solo contenido: si cambió, la entrada vieja sobra
+        self.remove_block(block);
+
+        let idx = self.bucket_of(&hash);
+        let bucket = &mut self.buckets[idx];
+        if bucket.iter().any(|e| e.hash == hash) { return false; }
+        if bucket.len() >= ENTRIES_PER_BUCKET { return false; }
+
+        bucket.push(IndexEntry { hash, block });
+        self.by_block.insert(block, hash);
+        self.dirty.insert(idx);
+        true
+    }
+
+    /// Olvida el contenido de un bloque (se liberó o se va a sobrescribir)
+    pub fn remove_block(&mut self, block: u64) {
+        if let Some(hash) = self.by_block.remove(&block) {
+            let idx = self.bucket_of(&hash);
+            self.buckets[idx].retain(|e| e.block != block);
+            self.dirty.insert(idx);
+        }
+    }
+
+    /// Devuelve (y olvida) las cubetas modificadas desde la última sincronización
+    pub fn take_dirty(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.dirty).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(n: u8) -> ChunkHash {
+        let mut h = [0u8; 32];
+        h[0] = n;
+        h[31] = n;
+        h
+    }
+
+    #[test]
+    fn test_lookup_and_remove() {
+        let mut index = ChunkIndex::new(4);
+        assert!(index.insert(hash(1), 10));
+        assert_eq!(index.lookup(&hash(1)), Some(10));
+        assert_eq!(index.lookup(&hash(2)), None);
+
+        // Sobrescribir el bloque 10 con otro contenido reemplaza la entrada
+        assert!(index.insert(hash(2), 10));
+        assert_eq!(index.lookup(&hash(1)), None);
+        assert_eq!(index.lookup(&hash(2)), Some(10));
+
+        index.remove_block(10);
+        assert_eq!(index.lookup(&hash(2)), None);
+        assert!(!index.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn test_full_bucket_is_best_effort() {
+        // Con una sola cubeta, todo cae en ella
+        let mut index = ChunkIndex::new(1);
+        for i in 0..ENTRIES_PER_BUCKET {
+            assert!(index.insert(hash(i as u8), i as u64));
+        }
+        assert!(!index.insert(hash(200), 999));
+        assert_eq!(index.lookup(&hash(200)), None);
+        assert_eq!(index.entries().count(), ENTRIES_PER_BUCKET);
+    }
+}
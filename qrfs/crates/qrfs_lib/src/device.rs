@@ -1,6 +1,7 @@
-use std::fs;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
-use image::{Luma, imageops}; // <--- Agregamos imageops
+use image::{ImageFormat, Luma, imageops}; // <--- Agregamos imageops
 use image::imageops::FilterType; // <--- Agregamos FilterType
 use qrcode::{QrCode, Version, EcLevel};
 use rqrr::PreparedImage;
@@ -27,6 +28,7 @@ pub enum DeviceError {
 
 pub struct BlockDevice {
     root_path: PathBuf,
+    keep_backups: bool, // Guardar la versión anterior de cada bloque como `.bak`
 }
 
 impl BlockDevice {
@@ -35,13 +37,36 @@ impl BlockDevice {
         if !root_path.exists() {
             fs::create_dir_all(&root_path)?;
         }
-        Ok(Self { root_path })
+        Ok(Self { root_path, keep_backups: false })
+    }
+
+    /// Al reescribir un bloque, conservar la generación anterior como
+    /// `qr_XXXXX.png.bak` (ver `restore_backup`). Duplica el espacio usado.
+    pub fn set_keep_backups(&mut self, keep: bool) {
+        self.keep_backups = keep;
     }
 
     fn get_path(&self, block_id: u64) -> PathBuf {
         self.root_path.join(format!("qr_{:05}.png", block_id))
     }
 
+    fn backup_path(&self, block_id: u64) -> PathBuf {
+        self.root_path.join(format!("qr_{:05}.png.bak", block_id))
+    }
+
+    fn temp_path(&self, block_id: u64) -> PathBuf {
+        self.root_path.join(format!("qr_{:05}.png.tmp", block_id))
+    }
+
+    /// Reemplaza `path` por `tmp` sin que nunca se vea a medio escribir:
+    /// rename es atómico dentro de un mismo directorio, y después se sincroniza
+    /// el directorio para que el cambio de nombre también quede en disco.
+    fn replace_with(&self, tmp: &Path, path: &Path) -> Result<(), DeviceError> {
+        fs::rename(tmp, path)?;
+        File::open(&self.root_path)?.sync_all()?;
+        Ok(())
+    }
+
     /// ESCRIBIR: Bytes -> Base64 -> QR -> Imagen (177x177 aprox)
     pub fn write_block(&self, block_id: u64, data: &[u8]) -> Result<(), DeviceError> {
         if data.len() > BLOCK_SIZE {
@@ -59,10 +84,49 @@ impl BlockDevice {
             .quiet_zone(true) // Asegura el borde blanco vital para la lectura
             .build();
 
+        // 1. Escribir en un temporal del mismo directorio y asegurarlo en disco
+        // (un corte o un disco lleno acá deja el bloque anterior intacto)
         let path = self.get_path(block_id);
-        image.save(path)?;
+        let tmp = self.temp_path(block_id);
+        let saved = File::create(&tmp).map_err(DeviceError::from).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            image.write_to(&mut writer, ImageFormat::Png)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            Ok(())
+        });
+        if let Err(e) = saved {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
 
-        Ok(())
+        // 2. Guardar la generación anterior, si se pidió. Un enlace duro no copia
+        // nada y el original sigue en su lugar hasta el rename.
+        if self.keep_backups && path.exists() {
+            let backup = self.backup_path(block_id);
+            remove_if_exists(&backup)?;
+            if fs::hard_link(&path, &backup).is_err() {
+                fs::copy(&path, &backup)?;
+            }
+        }
+
+        // 3. Reemplazar de una vez
+        self.replace_with(&tmp, &path)
+    }
+
+    /// Vuelve un bloque a su generación anterior (el `.bak` que deja `write_block`
+    /// con `set_keep_backups`). Retorna false si no hay copia de respaldo.
+    pub fn restore_backup(&self, block_id: u64) -> Result<bool, DeviceError> {
+        let backup = self.backup_path(block_id);
+        if !backup.exists() {
+            return Ok(false);
+        }
+        // Se copia (y no se mueve) para poder volver a restaurarla si hace falta
+        let tmp = self.temp_path(block_id);
+        fs::copy(&backup, &tmp)?;
+        File::open(&tmp)?.sync_all()?;
+        self.replace_with(&tmp, &self.get_path(block_id))?;
+        Ok(true)
     }
 
     /// LEER: Imagen -> Upscale (Zoom Entero) -> Detectar QR -> Bytes
@@ -105,16 +169,32 @@ impl BlockDevice {
         Err(DeviceError::QrDecodingFailed)
     }
 
+    /// ¿Existe la imagen del bloque? (`read_block` no distingue: una que falta se lee como ceros)
+    pub fn has_block(&self, block_id: u64) -> bool {
+        self.get_path(block_id).exists()
+    }
+
+    /// Números de bloque de todas las imágenes `qr_XXXXX.png` de la carpeta, en orden
+    pub fn stored_blocks(&self) -> Vec<u64> {
+        let mut blocks: Vec<u64> = fs::read_dir(&self.root_path).into_iter().flatten().flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let id = name.to_str()?.strip_prefix("qr_")?.strip_suffix(".png")?;
+                id.parse().ok()
+            })
+            .collect();
+        blocks.sort_unstable();
+        blocks
+    }
+
     pub fn count_blocks(&self) -> Result<u64, DeviceError> {
         let mut count = 0;
         // Leemos el directorio y contamos archivos .png
         if let Ok(entries) = fs::read_dir(&self.root_path) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("png") {
-                        count += 1;
-                    }
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|s| s.to_str()) == Some("png") {
+                    count += 1;
                 }
             }
         }
@@ -125,15 +205,21 @@ impl BlockDevice {
     /// Útil para liberar espacio en el disco anfitrión al hacer shrink.
     pub fn trim(&self, start_block: u64, end_block: u64) -> Result<(), DeviceError> {
         for i in start_block..end_block {
-            let path = self.get_path(i);
-            if path.exists() {
-                fs::remove_file(path)?;
-            }
+            remove_if_exists(&self.get_path(i))?;
+            remove_if_exists(&self.backup_path(i))?;
+            remove_if_exists(&self.temp_path(i))?;
         }
         Ok(())
     }
 }
 
+fn remove_if_exists(path: &Path) -> Result<(), DeviceError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,4 +248,27 @@ mod tests {
         // Limpieza final
         let _ = fs::remove_dir_all(test_dir);
     }
+
+    #[test]
+    fn test_backup_keeps_previous_generation() {
+        let test_dir = "test_qr_backup";
+        let _ = fs::remove_dir_all(test_dir);
+
+        let mut device = BlockDevice::new(test_dir).unwrap();
+        device.write_block(3, b"primera").unwrap();
+        // Sin respaldo pedido no queda ningún .bak (ni temporales)
+        device.write_block(3, b"segunda").unwrap();
+        assert!(!device.restore_backup(3).unwrap());
+
+        device.set_keep_backups(true);
+        device.write_block(3, b"tercera").unwrap();
+        assert_eq!(device.read_block(3).unwrap(), b"tercera");
+        assert_eq!(device.count_blocks().unwrap(), 1);
+
+        assert!(device.restore_backup(3).unwrap());
+        assert_eq!(device.read_block(3).unwrap(), b"segunda");
+        assert!(!Path::new(test_dir).join("qr_00003.png.tmp").exists());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
 }
@@ -0,0 +1,131 @@
+use thiserror::Error;
+
+use crate::compress::CompressError;
+use crate::crypto::CryptoError;
+use crate::device::DeviceError;
+use crate::journal::JournalError;
+use crate::snapshot::SnapshotError;
+use crate::xattr::XattrError;
+
+/// Errores de las operaciones del sistema de archivos.
+/// Cada uno corresponde a un errno de POSIX (ver `errno()`), que es lo que
+/// termina recibiendo el programa que hizo la llamada.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    #[error("No existe el archivo o directorio")]
+    NotFound,
+    #[error("El archivo ya existe")]
+    Exists,
+    #[error("No es un directorio")]
+    NotDir,
+    #[error("Es un directorio")]
+    IsDir,
+    #[error("El directorio no está vacío")]
+    NotEmpty,
+    #[error("Nombre de archivo demasiado largo")]
+    NameTooLong,
+    #[error("No queda espacio en el volumen")]
+    NoSpace,
+    #[error("Permiso denegado")]
+    AccessDenied,
+    #[error("Operación no permitida")]
+    NotPermitted,
+    #[error("Argumento inválido")]
+    InvalidArgument,
+    #[error("Demasiados enlaces")]
+    TooManyLinks,
+    #[error("El atributo no existe")]
+    NoData,
+    #[error("Operación no soportada")]
+    NotSupported,
+    #[error("El resultado no cabe en el buffer")]
+    Range,
+    #[error("Valor demasiado grande")]
+    TooBig,
+    #[error("El archivo superaría el tamaño máximo")]
+    FileTooBig,
+    #[error("Sistema de archivos de solo lectura")]
+    ReadOnly,
+    #[error("Error de entrada/salida (QR ilegible, datos corruptos o clave incorrecta)")]
+    Io,
+}
+
+impl FsError {
+    pub fn errno(self) -> i32 {
+        match self {
+            FsError::NotFound => libc::ENOENT,
+            FsError::Exists => libc::EEXIST,
+            FsError::NotDir => libc::ENOTDIR,
+            FsError::IsDir => libc::EISDIR,
+            FsError::NotEmpty => libc::ENOTEMPTY,
+            FsError::NameTooLong => libc::ENAMETOOLONG,
+            FsError::NoSpace => libc::ENOSPC,
+            FsError::AccessDenied => libc::EACCES,
+            FsError::NotPermitted => libc::EPERM,
+            FsError::InvalidArgument => libc::EINVAL,
+            FsError::TooManyLinks => libc::EMLINK,
+            FsError::NoData => libc::ENODATA,
+            FsError::NotSupported => libc::ENOTSUP,
+            FsError::Range => libc::ERANGE,
+            FsError::TooBig => libc::E2BIG,
+            FsError::FileTooBig => libc::EFBIG,
+            FsError::ReadOnly => libc::EROFS,
+            FsError::Io => libc::EIO,
+        }
+    }
+}
+
+impl From<XattrError> for FsError {
+    fn from(e: XattrError) -> Self {
+        match e {
+            XattrError::NotFound => FsError::NoData,
+            XattrError::Exists => FsError::Exists,
+            XattrError::BadName => FsError::Range,
+            XattrError::ValueTooLarge => FsError::TooBig,
+            XattrError::NoSpace => FsError::NoSpace,
+        }
+    }
+}
+
+// Lo que falla por debajo (QRs, cifrado, formato) le llega al usuario como EIO
+impl From<DeviceError> for FsError {
+    fn from(_: DeviceError) -> Self { FsError::Io }
+}
+
+impl From<CryptoError> for FsError {
+    fn from(_: CryptoError) -> Self { FsError::Io }
+}
+
+impl From<CompressError> for FsError {
+    fn from(_: CompressError) -> Self { FsError::Io }
+}
+
+impl From<JournalError> for FsError {
+    fn from(_: JournalError) -> Self { FsError::Io }
+}
+
+impl From<SnapshotError> for FsError {
+    fn from(e: SnapshotError) -> Self {
+        match e {
+            SnapshotError::CatalogFull | SnapshotError::NoSpace => FsError::NoSpace,
+            _ => FsError::Io,
+        }
+    }
+}
+
+impl From<bincode::Error> for FsError {
+    fn from(_: bincode::Error) -> Self { FsError::Io }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errno_mapping() {
+        assert_eq!(FsError::Exists.errno(), libc::EEXIST);
+        assert_eq!(FsError::NotEmpty.errno(), libc::ENOTEMPTY);
+        assert_eq!(FsError::from(XattrError::NotFound).errno(), libc::ENODATA);
+        assert_eq!(FsError::from(CryptoError::DecryptionError), FsError::Io);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use crate::types::Inode;
+
+/// Caché de bloques de la tabla de inodos.
+/// Se guarda el bloque completo (todos sus inodos) porque es la unidad que se
+/// lee y escribe en disco: así reescribir un inodo no obliga a escanear un QR
+/// para recuperar a sus vecinos. Cuando se llena, se descarta el bloque usado
+/// hace más tiempo (LRU). Las escrituras van directo a disco, así que descartar
+/// un bloque nunca pierde datos.
+pub struct InodeCache {
+    blocks: HashMap<u64, CachedBlock>,
+    capacity: usize,
+    tick: u64, // Reloj lógico para saber qué bloque se usó hace más tiempo
+}
+
+struct CachedBlock {
+    inodes: Vec<Inode>,
+    last_used: u64,
+}
+
+impl InodeCache {
+    /// `capacity` es la cantidad máxima de bloques de la tabla en RAM (al menos 1)
+    pub fn new(capacity: usize) -> Self {
+        Self { blocks: HashMap::new(), capacity: capacity.max(1), tick: 0 }
+    }
+
+    /// Inodos de un bloque de la tabla, si está en caché (y lo marca como recién usado)
+    pub fn get_mut(&mut self, table_block: u64) -> Option<&mut Vec<Inode>> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.blocks.get_mut(&table_block).map(|cached| {
+            cached.last_used = tick;
+            &mut cached.inodes
+        })
+    }
+
+    /// Agrega un bloque recién leído de disco, descartando el menos usado si no hay lugar
+    pub fn insert(&mut self, table_block: u64, inodes: Vec<Inode>) -> &mut Vec<Inode> {
+        if !self.blocks.contains_key(&table_block) && self.blocks.len() >= self.capacity {
+            let oldest = self.blocks.iter().min_by_key(|(_, c)| c.last_used).map(|(&b, _)| b);
+            if let Some(oldest) = oldest { self.blocks.remove(&oldest); }
+        }
+        self.tick += 1;
+        let cached = CachedBlock { inodes, last_used: self.tick };
+        &mut self.blocks.entry(table_block).insert_entry(cached).into_mut().inodes
+    }
+
+    /// Bloques actualmente en RAM
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Inodos en uso que están en caché (los vacíos tienen modo 0)
+    pub fn cached_inodes(&self) -> impl Iterator<Item = &Inode> {
+        self.blocks.values().flat_map(|c| c.inodes.iter()).filter(|i| i.mode != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FileType;
+
+    fn block_with_mode(mode: u16) -> Vec<Inode> {
+        vec![Inode::new(FileType::File, mode)]
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = InodeCache::new(2);
+        cache.insert(0, block_with_mode(0o600));
+        cache.insert(1, block_with_mode(0o644));
+
+        // Usar el 0 hace que el 1 sea el más viejo
+        assert!(cache.get_mut(0).is_some());
+        cache.insert(2, block_with_mode(0o755));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get_mut(1).is_none());
+        assert_eq!(cache.get_mut(0).unwrap()[0].mode, 0o600);
+        assert_eq!(cache.get_mut(2).unwrap()[0].mode, 0o755);
+    }
+
+    #[test]
+    fn test_cached_inodes_skip_empty_slots() {
+        let mut cache = InodeCache::new(4);
+        cache.insert(0, vec![Inode::new(FileType::File, 0), Inode::new(FileType::Directory, 0o755)]);
+        assert_eq!(cache.cached_inodes().count(), 1);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::crypto::{CryptoEngine, CryptoError};
+use crate::device::{BlockDevice, DeviceError};
+use crate::types::SuperBlock;
+
+// Bytes de la transacción por bloque del journal (con la cabecera, serializado y cifrado cabe en un QR)
+pub const JOURNAL_CHUNK: usize = 900;
+
+// Firma de los bloques del journal ("JRNL" en ASCII)
+const JOURNAL_MAGIC: u32 = 0x4A524E4C;
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("La transacción ocupa {0} bloques y el journal tiene {1}")]
+    TooLarge(u64, u64),
+    #[error("Error del dispositivo: {0}")]
+    Device(#[from] DeviceError),
+    #[error("Error de cifrado: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("Error de formato: {0}")]
+    Format(#[from] bincode::Error),
+}
+
+/// Bloques del journal para un volumen de `total_blocks` bloques
+/// (alcanza para varias transacciones grandes sin gastar demasiados QRs)
+pub fn journal_blocks_for(total_blocks: u64) -> u64 {
+    (total_blocks / 8).clamp(4, 64)
+}
+
+/// Un cambio atómico de metadatos: la imagen completa (en claro, antes de
+/// cifrar) de cada bloque que modifica. Se escribe entera en el journal antes
+/// de tocar ningún bloque en su lugar, así que después de un corte o se aplica
+/// completa (al rehacerla) o no se aplica nada.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Transaction {
+    writes: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Transaction {
+    /// Registra el contenido nuevo de un bloque (si ya estaba, gana el último)
+    pub fn write(&mut self, block: u64, bytes: Vec<u8>) {
+        self.writes.insert(block, bytes);
+    }
+
+    /// Contenido que la transacción le va a dar al bloque, si lo cambia
+    pub fn get(&self, block: u64) -> Option<&[u8]> {
+        self.writes.get(&block).map(Vec::as_slice)
+    }
+
+    /// Saca un bloque de la transacción (se escribió por fuera, ej. datos de un archivo)
+    pub fn forget(&mut self, block: u64) {
+        self.writes.remove(&block);
+    }
+
+    /// Bloques que cambia, en orden
+    pub fn blocks(&self) -> impl Iterator<Item = u64> + '_ {
+        self.writes.keys().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Cantidad de bloques que cambia
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Escribe cada bloque en su lugar definitivo.
+    /// Se puede repetir sin problema: siempre deja los mismos bloques con el mismo contenido.
+    pub fn apply(&self, device: &BlockDevice, crypto: &CryptoEngine) -> Result<(), JournalError> {
+        for (&block, bytes) in &self.writes {
+            device.write_block(block, &crypto.encrypt(bytes)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lo que va en cada QR del journal: un pedazo de una transacción serializada
+#[derive(Serialize, Deserialize, Debug)]
+struct JournalBlock {
+    magic: u32,
+    uuid: [u8; 16], // Del volumen: QRs viejos de otro volumen en la misma carpeta no cuentan
+    seq: u64,
+    part: u32,
+    parts: u32,
+    bytes: Vec<u8>,
+}
+
+/// Registro circular de transacciones (write-ahead log).
+///
+/// Las transacciones se escriben una detrás de otra, cada una en uno o más
+/// bloques con el mismo número de secuencia, y recién cuando está completa se
+/// aplica en su lugar. Como la siguiente no empieza hasta que la anterior se
+/// aplicó, al recuperar alcanza con rehacer la última transacción completa.
+pub struct Journal {
+    start: u64,
+    blocks: u64,
+    uuid: [u8; 16],
+    seq: u64,  // Última transacción escrita
+    head: u64, // Próximo bloque del journal a escribir (relativo a `start`)
+}
+
+impl Journal {
+    /// Journal de un volumen que se desmontó limpio (posición guardada en el superbloque)
+    pub fn from_superblock(sb: &SuperBlock) -> Self {
+        Self {
+            start: sb.journal_start,
+            blocks: sb.journal_blocks,
+            uuid: sb.uuid,
+            seq: sb.journal_seq,
+            head: sb.journal_head % sb.journal_blocks.max(1),
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn head(&self) -> u64 {
+        self.head
+    }
+
+    /// Escribe la transacción en el journal. Cuando vuelve Ok está confirmada:
+    /// después de un corte se va a rehacer aunque no se haya llegado a aplicar.
+    pub fn commit(&mut self, device: &BlockDevice, crypto: &CryptoEngine, txn: &Transaction) -> Result<(), JournalError> {
+        let bytes = bincode::serialize(txn)?;
+        let parts = bytes.len().div_ceil(JOURNAL_CHUNK).max(1) as u64;
+        if parts > self.blocks {
+            return Err(JournalError::TooLarge(parts, self.blocks));
+        }
+
+        // El último pedazo en escribirse es el que completa la transacción (el "commit")
+        let seq = self.seq + 1;
+        for (part, chunk) in bytes.chunks(JOURNAL_CHUNK).enumerate() {
+            let record = JournalBlock {
+                magic: JOURNAL_MAGIC,
+                uuid: self.uuid,
+                seq,
+                part: part as u32,
+                parts: parts as u32,
+                bytes: chunk.to_vec(),
+            };
+            let encrypted = crypto.encrypt(&bincode::serialize(&record)?)?;
+            device.write_block(self.start + self.head, &encrypted)?;
+            self.head = (self.head + 1) % self.blocks;
+        }
+        self.seq = seq;
+        Ok(())
+    }
+
+    /// Confirma una transacción vacía. Se usa antes de aplicar por fuera del
+    /// journal un cambio que no entra (`TooLarge`): si no, después de un corte
+    /// se rehace la transacción anterior encima de bloques que ese cambio ya
+    /// reescribió, y vuelven a su contenido viejo.
+    pub fn commit_barrier(&mut self, device: &BlockDevice, crypto: &CryptoEngine) -> Result<(), JournalError> {
+        self.commit(device, crypto, &Transaction::default())
+    }
+
+    /// Recorre el journal de un volumen que no se desmontó limpio.
+    /// Devuelve el journal listo para seguir escribiendo y la última transacción
+    /// confirmada (para rehacerla), o None si la última quedó a medio escribir:
+    /// en ese caso nunca se empezó a aplicar y todas las anteriores ya están aplicadas.
+    pub fn recover(sb: &SuperBlock, device: &BlockDevice, crypto: &CryptoEngine) -> Result<(Self, Option<Transaction>), JournalError> {
+        let mut journal = Self::from_superblock(sb);
+
+        // 1. Leer todo lo legible (un QR cortado a mitad de escritura no descifra y se ignora)
+        let mut records = Vec::new();
+        for pos in 0..journal.blocks {
+            let Ok(encrypted) = device.read_block(journal.start + pos) else { continue };
+            if encrypted.iter().all(|&x| x == 0) { continue; } // Nunca escrito
+            let Ok(plain) = crypto.decrypt(&encrypted) else { continue };
+            let Ok(record) = bincode::deserialize::<JournalBlock>(&plain) else { continue };
+            if record.magic == JOURNAL_MAGIC && record.uuid == journal.uuid {
+                records.push((pos, record));
+            }
+        }
+
+        // 2. La transacción más nueva, y dónde seguir escribiendo
+        let Some(last_seq) = records.iter().map(|(_, r)| r.seq).max() else {
+            return Ok((journal, None));
+        };
+        let mut last: Vec<(u64, JournalBlock)> = records.into_iter().filter(|(_, r)| r.seq == last_seq).collect();
+        last.sort_by_key(|(_, r)| r.part);
+        let last_pos = last.last().map(|(pos, _)| *pos).unwrap_or(0);
+        journal.seq = journal.seq.max(last_seq);
+        journal.head = (last_pos + 1) % journal.blocks;
+
+        // 3. Solo cuenta si están todos sus pedazos
+        let parts = last[0].1.parts as usize;
+        let complete = last.len() == parts && last.iter().enumerate().all(|(i, (_, r))| r.part as usize == i);
+        if !complete {
+            return Ok((journal, None));
+        }
+        let bytes: Vec<u8> = last.into_iter().flat_map(|(_, r)| r.bytes).collect();
+        Ok((journal, Some(bincode::deserialize(&bytes)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn superblock(journal_blocks: u64) -> SuperBlock {
+        SuperBlock { journal_start: 10, journal_blocks, uuid: [7; 16], ..SuperBlock::for_tests(100, 36) }
+    }
+
+    #[test]
+    fn test_recover_last_committed_transaction() {
+        let test_dir = "test_journal";
+        let _ = fs::remove_dir_all(test_dir);
+        let device = BlockDevice::new(test_dir).unwrap();
+        let crypto = CryptoEngine::new_plaintext();
+        let sb = superblock(4);
+
+        // Sin nada escrito no hay nada que rehacer
+        let (_, txn) = Journal::recover(&sb, &device, &crypto).unwrap();
+        assert!(txn.is_none());
+
+        let mut journal = Journal::from_superblock(&sb);
+        let mut first = Transaction::default();
+        first.write(50, vec![1; 10]);
+        journal.commit(&device, &crypto, &first).unwrap();
+
+        // La segunda ocupa dos bloques del journal
+        let mut second = Transaction::default();
+        second.write(51, vec![2; JOURNAL_CHUNK]);
+        second.write(50, vec![3; 10]);
+        journal.commit(&device, &crypto, &second).unwrap();
+
+        let (recovered, txn) = Journal::recover(&sb, &device, &crypto).unwrap();
+        assert_eq!(txn, Some(second.clone()));
+        assert_eq!((recovered.seq(), recovered.head()), (2, 3));
+
+        // Rehacerla deja los bloques con su contenido
+        txn.unwrap().apply(&device, &crypto).unwrap();
+        assert_eq!(*crypto.decrypt(&device.read_block(50).unwrap()).unwrap(), vec![3; 10]);
+
+        // Una transacción a medio escribir (falta su último pedazo) no se rehace
+        let mut third = Transaction::default();
+        third.write(52, vec![4; JOURNAL_CHUNK * 2]);
+        let mut partial = Journal::from_superblock(&sb);
+        partial.seq = 2;
+        partial.head = 3;
+        partial.commit(&device, &crypto, &third).unwrap();
+        fs::remove_file(format!("{}/qr_{:05}.png", test_dir, 10)).unwrap(); // Segundo de 3 pedazos (dio la vuelta)
+        let (recovered, txn) = Journal::recover(&sb, &device, &crypto).unwrap();
+        assert!(txn.is_none());
+        assert_eq!(recovered.seq(), 3); // La próxima no reusa su número
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_transaction_too_large() {
+        let test_dir = "test_journal_large";
+        let _ = fs::remove_dir_all(test_dir);
+        let device = BlockDevice::new(test_dir).unwrap();
+        let crypto = CryptoEngine::new_plaintext();
+
+        let mut journal = Journal::from_superblock(&superblock(4));
+        let mut txn = Transaction::default();
+        for block in 0..5 { txn.write(block, vec![0; JOURNAL_CHUNK]); }
+        assert!(matches!(journal.commit(&device, &crypto, &txn), Err(JournalError::TooLarge(6, 4))));
+        assert_eq!(journal.seq(), 0);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_barrier_before_unprotected_write() {
+        let test_dir = "test_journal_barrier";
+        let _ = fs::remove_dir_all(test_dir);
+        let device = BlockDevice::new(test_dir).unwrap();
+        let crypto = CryptoEngine::new_plaintext();
+        let sb = superblock(4);
+
+        // La transacción N queda en el journal, ya aplicada
+        let mut journal = Journal::from_superblock(&sb);
+        let mut small = Transaction::default();
+        small.write(50, vec![1; 10]);
+        journal.commit(&device, &crypto, &small).unwrap();
+        small.apply(&device, &crypto).unwrap();
+
+        // La siguiente no entra: barrera y se aplica por fuera del journal
+        let mut large = Transaction::default();
+        for block in 50..56 { large.write(block, vec![2; JOURNAL_CHUNK]); }
+        assert!(matches!(journal.commit(&device, &crypto, &large), Err(JournalError::TooLarge(..))));
+        journal.commit_barrier(&device, &crypto).unwrap();
+        large.apply(&device, &crypto).unwrap();
+
+        // Corte antes de la próxima transacción: al recuperar, N no se rehace
+        let (recovered, txn) = Journal::recover(&sb, &device, &crypto).unwrap();
+        assert_eq!(recovered.seq(), 2);
+        let txn = txn.unwrap();
+        assert!(txn.is_empty());
+        txn.apply(&device, &crypto).unwrap();
+        assert_eq!(*crypto.decrypt(&device.read_block(50).unwrap()).unwrap(), vec![2; JOURNAL_CHUNK]);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+}
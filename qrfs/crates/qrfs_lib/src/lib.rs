@@ -3,6 +3,20 @@ pub mod types;
 pub mod device;
 pub mod crypto;
 pub mod bitmap;
+pub mod compress;
+pub mod refcount;
+pub mod dedup;
+pub mod perm;
+pub mod times;
+pub mod xattr;
+pub mod acl;
+pub mod error;
+pub mod icache;
+pub mod journal;
+pub mod snapshot;
+pub mod scrub;
+pub mod volume;
+pub mod relocate;
 
 // Aquí pondremos más módulos en el futuro (ej. device, bitmap, crypto)
 // pub mod device;
@@ -0,0 +1,133 @@
+use crate::types::Inode;
+
+// Bits de la máscara de acceso (los mismos valores que R_OK/W_OK/X_OK de access(2))
+pub const MAY_READ: i32 = libc::R_OK;
+pub const MAY_WRITE: i32 = libc::W_OK;
+pub const MAY_EXEC: i32 = libc::X_OK;
+
+/// Bit "sticky": en un directorio, solo el dueño de una entrada puede borrarla o renombrarla
+pub const S_ISVTX: u16 = 0o1000;
+
+/// Identidad de quien hace la operación (viene de la petición FUSE).
+/// FUSE solo entrega el grupo principal: los suplementarios los agrega qrfs_mount
+/// (ver `parse_groups`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+}
+
+impl Caller {
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// ¿Es `gid` el grupo principal o uno de los suplementarios?
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    pub fn owns(&self, inode: &Inode) -> bool {
+        self.is_root() || self.uid == inode.uid
+    }
+}
+
+/// Chequeo POSIX clásico: se elige la tríada de dueño, grupo u otros (en ese orden)
+/// y todos los bits pedidos en `mask` deben estar en ella.
+/// Root puede leer y escribir todo, y ejecutar si alguna tríada tiene el bit x.
+pub fn check_access(inode: &Inode, caller: &Caller, mask: i32) -> bool {
+    let mask = (mask & (MAY_READ | MAY_WRITE | MAY_EXEC)) as u16;
+    let mode = inode.mode & 0o777;
+
+    if caller.is_root() {
+        return mask & MAY_EXEC as u16 == 0 || mode & 0o111 != 0;
+    }
+
+    let granted = if caller.uid == inode.uid {
+        mode >> 6
+    } else if caller.in_group(inode.gid) {
+        mode >> 3
+    } else {
+        mode
+    } & 0o7;
+    granted & mask == mask
+}
+
+/// ¿Puede `caller` quitar `victim` del directorio `dir`? (además de w+x en `dir`)
+pub fn may_delete(dir: &Inode, victim: &Inode, caller: &Caller) -> bool {
+    if dir.mode & S_ISVTX == 0 {
+        return true;
+    }
+    caller.owns(dir) || caller.owns(victim)
+}
+
+/// Grupos suplementarios de la línea "Groups:" de /proc/<pid>/status
+pub fn parse_groups(status: &str) -> Vec<u32> {
+    status.lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| groups.split_whitespace().filter_map(|gid| gid.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FileType;
+
+    fn inode(mode: u16, uid: u32, gid: u32) -> Inode {
+        let mut inode = Inode::new(FileType::File, mode);
+        inode.uid = uid;
+        inode.gid = gid;
+        inode
+    }
+
+    const ALICE: Caller = Caller { uid: 1000, gid: 1000, groups: Vec::new() };
+    const BOB: Caller = Caller { uid: 1001, gid: 1000, groups: Vec::new() };
+    const EVE: Caller = Caller { uid: 1002, gid: 1002, groups: Vec::new() };
+    const ROOT: Caller = Caller { uid: 0, gid: 0, groups: Vec::new() };
+
+    #[test]
+    fn test_owner_group_other() {
+        let file = inode(0o640, 1000, 1000);
+        assert!(check_access(&file, &ALICE, MAY_READ | MAY_WRITE));
+        assert!(check_access(&file, &BOB, MAY_READ));
+        assert!(!check_access(&file, &BOB, MAY_WRITE));
+        assert!(!check_access(&file, &EVE, MAY_READ));
+
+        // La tríada del dueño manda aunque otros tengan más permisos
+        let odd = inode(0o047, 1000, 1000);
+        assert!(!check_access(&odd, &ALICE, MAY_READ));
+        assert!(check_access(&odd, &EVE, MAY_READ | MAY_WRITE | MAY_EXEC));
+    }
+
+    #[test]
+    fn test_root_needs_some_exec_bit() {
+        assert!(check_access(&inode(0o000, 1000, 1000), &ROOT, MAY_READ | MAY_WRITE));
+        assert!(!check_access(&inode(0o666, 1000, 1000), &ROOT, MAY_EXEC));
+        assert!(check_access(&inode(0o100, 1000, 1000), &ROOT, MAY_EXEC));
+    }
+
+    #[test]
+    fn test_sticky_directory() {
+        let tmp = inode(0o1777, 0, 0);
+        let alices = inode(0o644, 1000, 1000);
+        assert!(may_delete(&tmp, &alices, &ALICE));
+        assert!(!may_delete(&tmp, &alices, &BOB));
+        assert!(may_delete(&tmp, &alices, &ROOT));
+        assert!(may_delete(&inode(0o777, 0, 0), &alices, &BOB));
+    }
+
+    #[test]
+    fn test_supplementary_groups() {
+        let shared = inode(0o060, 1000, 2000);
+        assert!(!check_access(&shared, &EVE, MAY_READ));
+        let member = Caller { groups: vec![1500, 2000], ..EVE };
+        assert!(check_access(&shared, &member, MAY_READ | MAY_WRITE));
+
+        let status = "Name:\tcat\nUid:\t1002\t1002\t1002\t1002\nGroups:\t24 27 2000 \nNgid:\t0\n";
+        assert_eq!(parse_groups(status), vec![24, 27, 2000]);
+        assert_eq!(parse_groups("Groups:\t\n"), Vec::<u32>::new());
+        assert_eq!(parse_groups("Name:\tcat\n"), Vec::<u32>::new());
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeSet;
+
+use crate::bitmap::Bitmap;
+
+// Contadores por página (u16 => 800 bytes + 8 del largo del Vec, cabe cifrado en un QR)
+pub const REFS_PER_BLOCK: usize = 400;
+
+/// Referencias EXTRA de cada bloque de datos (además de su dueño original).
+/// 0 significa "un solo dueño", así el camino normal de asignación no toca esta tabla;
+/// solo los bloques compartidos (dedup) tienen un valor distinto de 0.
+/// En disco es una región de páginas consecutivas, y solo se reescriben las páginas sucias.
+#[derive(Debug, Clone)]
+pub struct RefCounts {
+    shares: Vec<u16>,
+    dirty: BTreeSet<usize>,
+}
+
+impl RefCounts {
+    /// Tabla vacía (todo con un solo dueño) de `pages` páginas
+    pub fn new(pages: usize) -> Self {
+        Self {
+            shares: vec![0; pages * REFS_PER_BLOCK],
+            dirty: BTreeSet::new(),
+        }
+    }
+
+    /// Cuántas páginas hacen falta para rastrear `total_blocks` bloques
+    pub fn pages_for(total_blocks: u64) -> u64 {
+        total_blocks.div_ceil(REFS_PER_BLOCK as u64).max(1)
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.shares.len() / REFS_PER_BLOCK
+    }
+
+    /// Carga una página leída de disco
+    pub fn load_page(&mut self, page: usize, counts: &[u16]) {
+        let start = page * REFS_PER_BLOCK;
+        let len = counts.len().min(REFS_PER_BLOCK);
+        self.shares[start..start + len].copy_from_slice(&counts[..len]);
+    }
+
+    /// Contenido de una página (para serializar y guardar)
+    pub fn page(&self, page: usize) -> &[u16] {
+        let start = page * REFS_PER_BLOCK;
+        &self.shares[start..start + REFS_PER_BLOCK]
+    }
+
+    /// Referencias extra del bloque (0 si está fuera de la tabla)
+    pub fn shares(&self, block: u64) -> u16 {
+        self.shares.get(block as usize).copied().unwrap_or(0)
+    }
+
+    /// Registra un dueño más del bloque.
+    /// Retorna false si no se puede (fuera de la tabla o contador saturado).
+    pub fn add_share(&mut self, block: u64) -> bool {
+        let idx = block as usize;
+        match self.shares.get_mut(idx) {
+            Some(count) if *count < u16::MAX => {
+                *count += 1;
+                self.dirty.insert(idx / REFS_PER_BLOCK);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Suelta una referencia al bloque. Si nadie más lo usa, lo libera en el bitmap.
+    /// Retorna true si el bloque quedó libre.
+    pub fn release(&mut self, block: u64, bitmap: &mut Bitmap) -> bool {
+        let idx = block as usize;
+        if let Some(count) = self.shares.get_mut(idx)
+            && *count > 0
+        {
+            *count -= 1;
+            self.dirty.insert(idx / REFS_PER_BLOCK);
+            return false;
+        }
+        bitmap.set(idx, false);
+        true
+    }
+
+    /// Fija el contador de un bloque (lo usa fsck al reparar)
+    pub fn set_shares(&mut self, block: u64, shares: u16) {
+        let idx = block as usize;
+        if let Some(count) = self.shares.get_mut(idx)
+            && *count != shares
+        {
+            *count = shares;
+            self.dirty.insert(idx / REFS_PER_BLOCK);
+        }
+    }
+
+    /// Devuelve (y olvida) las páginas modificadas desde la última sincronización
+    pub fn take_dirty(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.dirty).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_block_is_freed_by_last_owner() {
+        let mut bitmap = Bitmap::new(16);
+        let mut refs = RefCounts::new(RefCounts::pages_for(16) as usize);
+
+        let block = bitmap.allocate().unwrap();
+        assert!(refs.add_share(block)); // Un segundo archivo apunta al mismo bloque
+        assert_eq!(refs.take_dirty(), vec![0]);
+
+        // El primer dueño lo suelta: sigue ocupado
+        assert!(!refs.release(block, &mut bitmap));
+        assert!(bitmap.get(block as usize));
+
+        // El último dueño lo suelta: queda libre
+        assert!(refs.release(block, &mut bitmap));
+        assert!(!bitmap.get(block as usize));
+    }
+
+    #[test]
+    fn test_out_of_range_block_cannot_be_shared() {
+        let mut refs = RefCounts::new(1);
+        assert!(!refs.add_share(REFS_PER_BLOCK as u64));
+        assert_eq!(refs.shares(REFS_PER_BLOCK as u64), 0);
+    }
+}
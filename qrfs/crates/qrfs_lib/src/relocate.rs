@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use thiserror::Error;
+
+use crate::bitmap::Bitmap;
+use crate::dedup::{ChunkIndex, IndexEntry};
+use crate::refcount::RefCounts;
+use crate::snapshot::Snapshot;
+use crate::types::{Inode, SuperBlock};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RelocateError {
+    #[error("La región {name} llega hasta el bloque {end}: el volumen no puede quedar más chico que eso")]
+    RegionPastEnd { name: &'static str, end: u64 },
+    #[error("No hay lugar: hay que mudar {needed} bloques y quedan {free} libres por debajo del bloque {limit}")]
+    NoRoom { needed: usize, free: usize, limit: u64 },
+}
+
+/// Tablas de inodos que hay en disco, por ubicación: la del volumen vivo y las
+/// copias de los snapshots (una copia puede ser de varios)
+pub type Tables = BTreeMap<u64, Vec<Inode>>;
+
+/// Mudanza de los bloques en uso de [new_size, total_blocks) a bloques libres
+/// por debajo de `new_size`, para achicar el volumen (qrfs_resize).
+/// Todo se calcula en memoria: copiar los bloques y escribir los metadatos
+/// cambiados queda a cargo de quien la usa.
+#[derive(Debug)]
+pub struct Relocation {
+    pub new_size: u64,
+    /// Bloques en uso más allá del nuevo final
+    pub movers: Vec<u64>,
+    /// Bloques más allá del nuevo final marcados como ocupados sin dueño: se descartan
+    pub leaked: Vec<u64>,
+    /// Bloque viejo -> destino (vacío hasta `assign`)
+    pub mapping: BTreeMap<u64, u64>,
+}
+
+/// Lo que cambió `Relocation::apply`
+#[derive(Debug, PartialEq)]
+pub struct Remapped {
+    /// Tablas de inodos con punteros cambiados: (ubicación en `tables`, dónde escribirla).
+    /// Son distintas si la copia de un snapshot también se mudó.
+    pub tables: Vec<(u64, u64)>,
+    /// Si cambió alguna copia de los snapshots (hay que reescribir el catálogo)
+    pub catalog: bool,
+}
+
+impl Relocation {
+    /// Qué hay que mudar y qué se descarta. Falla si algún metadato de lugar fijo
+    /// queda afuera, o si no hay lugar libre por debajo de `new_size`.
+    pub fn plan(
+        sb: &SuperBlock,
+        tables: &Tables,
+        snapshots: &[Snapshot],
+        bitmap: &Bitmap,
+        new_size: u64,
+    ) -> Result<Self, RelocateError> {
+        // A. Los metadatos tienen lugar fijo: tienen que quedar enteros adentro
+        let regions = [
+            ("del bitmap", sb.bitmap_start, sb.bitmap_blocks),
+            ("del bitmap de inodos", sb.inode_bitmap_start, sb.inode_bitmap_blocks),
+            ("del journal", sb.journal_start, sb.journal_blocks),
+            ("del catálogo de snapshots", sb.snapshot_start, sb.snapshot_blocks),
+            ("de la tabla de inodos", sb.inode_table_start, sb.inode_table_blocks()),
+            ("de contadores", sb.refcount_start, sb.refcount_blocks),
+            ("del índice de dedup", sb.dedup_index_start, sb.dedup_index_blocks),
+        ];
+        for (name, start, blocks) in regions {
+            if blocks > 0 && start + blocks > new_size {
+                return Err(RelocateError::RegionPastEnd { name, end: start + blocks });
+            }
+        }
+
+        // B. Bloques en uso: los que apuntan los inodos y las copias de los snapshots
+        let mut referenced: BTreeSet<u64> = tables.values().flatten()
+            .filter(|inode| inode.mode != 0)
+            .flat_map(|inode| inode.direct_blocks.iter().copied().chain(std::iter::once(inode.xattr_block)))
+            .filter(|&block| block != 0)
+            .collect();
+        referenced.extend(snapshots.iter().flat_map(|snap| snap.moved.values().copied()));
+
+        let movers: Vec<u64> = referenced.range(new_size..sb.total_blocks).copied().collect();
+        let leaked: Vec<u64> = (new_size..sb.total_blocks)
+            .filter(|&block| bitmap.get(block as usize) && !referenced.contains(&block))
+            .collect();
+
+        let free = (0..new_size).filter(|&block| !bitmap.get(block as usize)).count();
+        if free < movers.len() {
+            return Err(RelocateError::NoRoom { needed: movers.len(), free, limit: new_size });
+        }
+        Ok(Self { new_size, movers, leaked, mapping: BTreeMap::new() })
+    }
+
+    /// ¿Hay algo que cambiar?
+    pub fn is_empty(&self) -> bool {
+        self.movers.is_empty() && self.leaked.is_empty()
+    }
+
+    /// Reserva en el bitmap un destino por debajo del nuevo final para cada bloque a mudar
+    pub fn assign(&mut self, bitmap: &mut Bitmap) -> Result<(), RelocateError> {
+        for &old in &self.movers {
+            let new = bitmap.allocate_below(self.new_size).ok_or(RelocateError::NoRoom {
+                needed: self.movers.len(),
+                free: self.mapping.len(),
+                limit: self.new_size,
+            })?;
+            self.mapping.insert(old, new);
+        }
+        Ok(())
+    }
+
+    /// Dónde queda un bloque después de la mudanza
+    pub fn remap(&self, block: u64) -> u64 {
+        self.mapping.get(&block).copied().unwrap_or(block)
+    }
+
+    /// Cambia todo lo que apunta a un bloque mudado: punteros de los inodos (del
+    /// volumen vivo y de los snapshots, bloque de xattrs incluido), copias de los
+    /// snapshots, contadores de referencias e índice de dedup. Libera en el bitmap
+    /// los bloques mudados y los descartados.
+    pub fn apply(
+        &self,
+        tables: &mut Tables,
+        snapshots: &mut [Snapshot],
+        refs: &mut RefCounts,
+        chunk_index: &mut ChunkIndex,
+        bitmap: &mut Bitmap,
+    ) -> Remapped {
+        // A. Punteros de los inodos (en la copia ya mudada, si la tabla también se mudó)
+        let mut changed_tables = Vec::new();
+        for (&location, inodes) in tables.iter_mut() {
+            let mut changed = false;
+            for inode in inodes.iter_mut().filter(|inode| inode.mode != 0) {
+                for block in inode.direct_blocks.iter_mut().chain(std::iter::once(&mut inode.xattr_block)) {
+                    if self.mapping.contains_key(block) {
+                        *block = self.remap(*block);
+                        changed = true;
+                    }
+                }
+            }
+            if changed {
+                changed_tables.push((location, self.remap(location)));
+            }
+        }
+
+        // B. Copias de los snapshots
+        let mut catalog = false;
+        for copy in snapshots.iter_mut().flat_map(|snap| snap.moved.values_mut()) {
+            if self.mapping.contains_key(copy) {
+                *copy = self.remap(*copy);
+                catalog = true;
+            }
+        }
+
+        // C. Índice de dedup y contadores de referencias
+        let indexed: Vec<IndexEntry> = chunk_index.entries().filter(|e| self.mapping.contains_key(&e.block)).cloned().collect();
+        for entry in indexed {
+            chunk_index.remove_block(entry.block);
+            chunk_index.insert(entry.hash, self.remap(entry.block));
+        }
+        for (&old, &new) in &self.mapping {
+            refs.set_shares(new, refs.shares(old));
+            refs.set_shares(old, 0);
+        }
+
+        // D. Lo que queda más allá del nuevo final ya no se usa
+        for &old in self.movers.iter().chain(&self.leaked) {
+            bitmap.set(old as usize, false);
+        }
+        Remapped { tables: changed_tables, catalog }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::ChunkHash;
+    use crate::types::FileType;
+
+    const TOTAL: u64 = 40;
+    const NEW_SIZE: u64 = 20;
+    const TABLE: u64 = 2;
+
+    /// Bitmap en 1, tabla de inodos en 2 (un bloque), datos desde el 3
+    fn superblock() -> SuperBlock {
+        SuperBlock { bitmap_start: 1, bitmap_blocks: 1, inode_table_start: TABLE, ..SuperBlock::for_tests(TOTAL, 3) }
+    }
+
+    fn inode(blocks: &[u64], xattr_block: u64) -> Inode {
+        let mut inode = Inode::new(FileType::File, 0o644);
+        inode.direct_blocks[..blocks.len()].copy_from_slice(blocks);
+        inode.xattr_block = xattr_block;
+        inode
+    }
+
+    fn used(blocks: &[u64]) -> Bitmap {
+        let mut bitmap = Bitmap::new(TOTAL as usize);
+        for &block in [0, 1, TABLE].iter().chain(blocks) {
+            bitmap.set(block as usize, true);
+        }
+        bitmap
+    }
+
+    fn hash(n: u8) -> ChunkHash {
+        [n; 32]
+    }
+
+    #[test]
+    fn test_relocation_rewrites_every_pointer() {
+        let sb = superblock();
+        // Vivo: el inodo 1 tiene el 5 (se queda), el 25 (compartido con el
+        // snapshot) y sus xattrs en el 30. El snapshot tiene su copia de la
+        // tabla en el 26, con un inodo que además usa el 27 (indexado por dedup).
+        let empty = Inode::new(FileType::File, 0);
+        let mut tables: Tables = BTreeMap::from([
+            (TABLE, vec![empty.clone(), inode(&[5, 25], 30), empty.clone()]),
+            (26, vec![empty.clone(), inode(&[25, 27], 0), empty.clone()]),
+        ]);
+        let mut snapshot = Snapshot::new("s1", &sb).unwrap();
+        snapshot.moved.insert(TABLE, 26);
+        let mut snapshots = vec![snapshot];
+        let mut bitmap = used(&[5, 25, 26, 27, 30, 35]); // El 35 no es de nadie
+
+        let mut plan = Relocation::plan(&sb, &tables, &snapshots, &bitmap, NEW_SIZE).unwrap();
+        assert_eq!(plan.movers, vec![25, 26, 27, 30]);
+        assert_eq!(plan.leaked, vec![35]);
+
+        plan.assign(&mut bitmap).unwrap();
+        let new = |old: u64| plan.mapping[&old];
+        assert!(plan.mapping.values().all(|&block| block < NEW_SIZE && bitmap.get(block as usize)));
+
+        let mut refs = RefCounts::new(1);
+        refs.set_shares(25, 1);
+        let mut chunk_index = ChunkIndex::new(1);
+        chunk_index.insert(hash(7), 27);
+        chunk_index.insert(hash(8), 5);
+
+        let remapped = plan.apply(&mut tables, &mut snapshots, &mut refs, &mut chunk_index, &mut bitmap);
+        assert_eq!(remapped, Remapped { tables: vec![(TABLE, TABLE), (26, new(26))], catalog: true });
+
+        let live = &tables[&TABLE][1];
+        assert_eq!(&live.direct_blocks[..2], &[5, new(25)]);
+        assert_eq!(live.xattr_block, new(30));
+        assert_eq!(&tables[&26][1].direct_blocks[..2], &[new(25), new(27)]);
+        assert_eq!(snapshots[0].moved[&TABLE], new(26));
+
+        assert_eq!((refs.shares(25), refs.shares(new(25))), (0, 1));
+        assert_eq!(chunk_index.lookup(&hash(7)), Some(new(27)));
+        assert_eq!(chunk_index.lookup(&hash(8)), Some(5));
+        assert!((NEW_SIZE..TOTAL).all(|block| !bitmap.get(block as usize)));
+    }
+
+    #[test]
+    fn test_relocation_needs_room_below_the_new_end() {
+        let sb = superblock();
+        let tables: Tables = BTreeMap::from([(TABLE, vec![inode(&[], 0), inode(&[25, 26], 0), inode(&[], 0)])]);
+        // Por debajo del 20 queda libre solo el 19
+        let bitmap = used(&(3..19).chain([25, 26]).collect::<Vec<_>>());
+        let err = Relocation::plan(&sb, &tables, &[], &bitmap, NEW_SIZE).unwrap_err();
+        assert_eq!(err, RelocateError::NoRoom { needed: 2, free: 1, limit: NEW_SIZE });
+
+        // Sin nada que mudar no hace falta lugar
+        let plan = Relocation::plan(&sb, &tables, &[], &used(&(3..20).collect::<Vec<_>>()), 27).unwrap();
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn test_relocation_keeps_metadata_regions_inside() {
+        let sb = SuperBlock { refcount_start: 22, refcount_blocks: 1, ..superblock() };
+        let err = Relocation::plan(&sb, &Tables::new(), &[], &used(&[22]), NEW_SIZE).unwrap_err();
+        assert_eq!(err, RelocateError::RegionPastEnd { name: "de contadores", end: 23 });
+    }
+}
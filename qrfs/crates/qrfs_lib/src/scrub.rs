@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::bitmap::Bitmap;
+use crate::compress;
+use crate::dedup::IndexEntry;
+use crate::crypto::{CryptoEngine, VolumeHeader};
+use crate::device::{BlockDevice, DeviceError};
+use crate::snapshot;
+use crate::types::{DirEntry, FileType, Inode, SuperBlock, INODES_PER_BLOCK};
+
+/// Qué le pasa a una imagen
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrubStatus {
+    /// El bloque está asignado pero su `qr_XXXXX.png` no existe
+    Missing,
+    /// La imagen no abre o no tiene un QR legible
+    Unreadable,
+    /// El QR se lee pero no autentica (o su contenido no tiene el formato esperado)
+    Corrupt,
+    /// El QR no es un bloque QRFS, o la imagen está fuera del volumen
+    Foreign,
+}
+
+impl ScrubStatus {
+    pub const ALL: [ScrubStatus; 4] = [ScrubStatus::Missing, ScrubStatus::Unreadable, ScrubStatus::Corrupt, ScrubStatus::Foreign];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ScrubStatus::Missing => "FALTA",
+            ScrubStatus::Unreadable => "ILEGIBLE",
+            ScrubStatus::Corrupt => "CORRUPTO",
+            ScrubStatus::Foreign => "AJENO",
+        }
+    }
+}
+
+/// Una imagen con problemas, y de quién es el bloque
+#[derive(Serialize, Debug, Clone)]
+pub struct ScrubFinding {
+    pub block: u64,
+    pub status: ScrubStatus,
+    pub detail: String,
+    pub owner: String,
+}
+
+/// Cómo se valida el contenido de un bloque
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Content {
+    /// Bloque 0: cabecera en claro + superbloque cifrado
+    Superblock,
+    /// Página de un bitmap
+    Bitmap,
+    /// Página de contadores de referencias
+    Refcounts,
+    /// Cubeta del índice de dedup
+    Index,
+    /// Bloque de la tabla de inodos (puede no haberse escrito nunca)
+    Inodes,
+    /// Metadatos que se validan solo descifrándolos (copias de snapshots)
+    Meta,
+    /// Ídem, pero pueden no haberse escrito nunca (journal, catálogo)
+    Sparse,
+    /// Fragmento de datos (archivo, directorio, symlink, xattrs): se descomprime
+    Chunk,
+}
+
+/// De quién es un bloque: para el reporte y para saber cómo validarlo
+#[derive(Debug, Clone)]
+pub struct Owner {
+    pub description: String,
+    content: Content,
+}
+
+impl Owner {
+    fn new(description: impl Into<String>, content: Content) -> Self {
+        Self { description: description.into(), content }
+    }
+}
+
+/// Dueño de cada bloque asignado del volumen: las regiones de metadatos, los
+/// archivos (con su ruta, recorriendo el árbol desde la raíz) y los snapshots.
+/// Lo que no se puede leer se saltea: el propio scrub va a reportar ese bloque.
+pub fn block_owners(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock) -> BTreeMap<u64, Owner> {
+    let mut owners = BTreeMap::new();
+    owners.insert(0, Owner::new("superbloque", Content::Superblock));
+    let regions = [
+        (sb.bitmap_start, sb.bitmap_blocks, "bitmap de bloques", Content::Bitmap),
+        (sb.inode_bitmap_start, sb.inode_bitmap_blocks, "bitmap de inodos", Content::Bitmap),
+        (sb.journal_start, sb.journal_blocks, "journal", Content::Sparse),
+        (sb.snapshot_start, sb.snapshot_blocks, "catálogo de snapshots", Content::Sparse),
+        (sb.inode_table_start, sb.inode_table_blocks(), "tabla de inodos", Content::Inodes),
+        (sb.refcount_start, sb.refcount_blocks, "contadores de referencias", Content::Refcounts),
+        (sb.dedup_index_start, sb.dedup_index_blocks, "índice de dedup", Content::Index),
+    ];
+    for (start, blocks, name, content) in regions {
+        for block in start..start + blocks {
+            owners.insert(block, Owner::new(name, content));
+        }
+    }
+
+    // Volumen vivo (con rutas) y después cada snapshot (lo que no comparte con el vivo)
+    let inodes = load_inodes(device, crypto, sb, |block| block);
+    let paths = inode_paths(device, crypto, &inodes, sb.root_dir_inode);
+    add_inode_blocks(&mut owners, &inodes, |idx| {
+        paths.get(&idx).cloned().unwrap_or_else(|| format!("inodo {}", idx))
+    });
+
+    for snap in snapshot::read_catalog(device, crypto, sb).unwrap_or_default() {
+        for (&original, &copy) in &snap.moved {
+            owners.entry(copy).or_insert_with(|| {
+                Owner::new(format!("snapshot '{}': copia del bloque {}", snap.name, original), Content::Meta)
+            });
+        }
+        let snap_inodes = load_inodes(device, crypto, sb, |block| snap.locate(block));
+        add_inode_blocks(&mut owners, &snap_inodes, |idx| format!("snapshot '{}': inodo {}", snap.name, idx));
+    }
+    owners
+}
+
+/// Bloques a revisar: el 0 y todos los que el bitmap marca como ocupados
+pub fn allocated_blocks(bitmap: &Bitmap, total_blocks: u64) -> Vec<u64> {
+    (0..total_blocks).filter(|&block| block == 0 || bitmap.get(block as usize)).collect()
+}
+
+/// Lee, decodifica y descifra cada bloque de `blocks` con `jobs` hilos a la vez
+/// (leer un QR es lento: casi todo el tiempo se va en detectar la grilla).
+/// También reporta como ajenas las imágenes que están fuera del volumen.
+pub fn scrub(
+    device: &BlockDevice,
+    crypto: &CryptoEngine,
+    sb: &SuperBlock,
+    blocks: &[u64],
+    owners: &BTreeMap<u64, Owner>,
+    jobs: usize,
+) -> Vec<ScrubFinding> {
+    let unowned = Owner::new("sin dueño (bloque huérfano)", Content::Meta);
+    let next = AtomicUsize::new(0);
+    let findings = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(&block) = blocks.get(i) else { break };
+                let owner = owners.get(&block).unwrap_or(&unowned);
+                if let Some((status, detail)) = check_block(device, crypto, block, owner.content) {
+                    let finding = ScrubFinding { block, status, detail, owner: owner.description.clone() };
+                    findings.lock().unwrap_or_else(|e| e.into_inner()).push(finding);
+                }
+            });
+        }
+    });
+
+    let mut findings = findings.into_inner().unwrap_or_else(|e| e.into_inner());
+    for block in device.stored_blocks().into_iter().filter(|&b| b >= sb.total_blocks) {
+        findings.push(ScrubFinding {
+            block,
+            status: ScrubStatus::Foreign,
+            detail: format!("imagen fuera del volumen ({} bloques)", sb.total_blocks),
+            owner: "ninguno".to_string(),
+        });
+    }
+    findings.sort_by_key(|f| f.block);
+    findings
+}
+
+/// Cuántos hallazgos hay de cada tipo
+pub fn summarize(findings: &[ScrubFinding]) -> BTreeMap<ScrubStatus, usize> {
+    let mut counts: BTreeMap<ScrubStatus, usize> = ScrubStatus::ALL.iter().map(|&s| (s, 0)).collect();
+    for finding in findings {
+        *counts.entry(finding.status).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Revisa un bloque; None si está bien
+fn check_block(device: &BlockDevice, crypto: &CryptoEngine, block: u64, content: Content) -> Option<(ScrubStatus, String)> {
+    if !device.has_block(block) {
+        if matches!(content, Content::Sparse | Content::Inodes) { return None; } // Nunca escrito: se lee como vacío
+        return Some((ScrubStatus::Missing, "no existe la imagen".to_string()));
+    }
+
+    let raw = match device.read_block(block) {
+        Ok(raw) => raw,
+        Err(DeviceError::Base64Error(_)) => return Some((ScrubStatus::Foreign, "el QR no es un bloque QRFS".to_string())),
+        Err(e) => return Some((ScrubStatus::Unreadable, e.to_string())),
+    };
+
+    let encrypted = if content == Content::Superblock {
+        match VolumeHeader::parse(&raw) {
+            Ok((_, encrypted)) => encrypted,
+            Err(e) => return Some((ScrubStatus::Corrupt, e.to_string())),
+        }
+    } else {
+        &raw[..]
+    };
+    let plain = match crypto.decrypt(encrypted) {
+        Ok(plain) => plain,
+        Err(e) => return Some((ScrubStatus::Corrupt, e.to_string())),
+    };
+
+    // Sin cifrado no hay autenticación: que al menos tenga la forma esperada
+    let parsed = match content {
+        Content::Superblock => bincode::deserialize::<SuperBlock>(&plain).map(drop),
+        Content::Bitmap => bincode::deserialize::<Vec<u8>>(&plain).map(drop),
+        Content::Refcounts => bincode::deserialize::<Vec<u16>>(&plain).map(drop),
+        Content::Index => bincode::deserialize::<Vec<IndexEntry>>(&plain).map(drop),
+        Content::Inodes => bincode::deserialize::<Vec<Inode>>(&plain).map(drop),
+        Content::Chunk => {
+            return compress::unpack_chunk(&plain).err().map(|e| (ScrubStatus::Corrupt, e.to_string()));
+        }
+        Content::Meta | Content::Sparse => Ok(()),
+    };
+    parsed.err().map(|e| (ScrubStatus::Corrupt, format!("contenido ilegible: {}", e)))
+}
+
+/// Tabla de inodos entera; lo ilegible (o nunca escrito) cuenta como inodos vacíos
+fn load_inodes(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock, locate: impl Fn(u64) -> u64) -> Vec<Inode> {
+    let mut inodes = Vec::new();
+    for table_block in 0..sb.inode_table_blocks() {
+        let mut block_inodes: Vec<Inode> = device.read_block(locate(sb.inode_table_start + table_block)).ok()
+            .and_then(|encrypted| crypto.decrypt(&encrypted).ok())
+            .and_then(|plain| bincode::deserialize(&plain).ok())
+            .unwrap_or_default();
+        block_inodes.resize(INODES_PER_BLOCK, Inode::new(FileType::File, 0));
+        inodes.extend(block_inodes);
+    }
+    inodes
+}
+
+/// Anota los bloques de datos y de xattrs de cada inodo en uso (si nadie los anotó antes)
+fn add_inode_blocks(owners: &mut BTreeMap<u64, Owner>, inodes: &[Inode], name: impl Fn(u64) -> String) {
+    for (idx, inode) in inodes.iter().enumerate().filter(|(_, inode)| inode.mode != 0) {
+        let idx = idx as u64;
+        for &block in inode.direct_blocks.iter().filter(|&&b| b != 0) {
+            owners.entry(block).or_insert_with(|| Owner::new(name(idx), Content::Chunk));
+        }
+        if inode.xattr_block != 0 {
+            owners.entry(inode.xattr_block).or_insert_with(|| Owner::new(format!("{} (xattrs)", name(idx)), Content::Chunk));
+        }
+    }
+}
+
+/// Ruta de cada inodo alcanzable desde la raíz (la primera que se encuentra)
+fn inode_paths(device: &BlockDevice, crypto: &CryptoEngine, inodes: &[Inode], root: u64) -> HashMap<u64, String> {
+    let mut paths = HashMap::from([(root, "/".to_string())]);
+    let mut queue = VecDeque::from([root]);
+    while let Some(dir) = queue.pop_front() {
+        let Some(inode) = inodes.get(dir as usize) else { continue };
+        if inode.mode == 0 || inode.file_type != FileType::Directory { continue; }
+        let prefix = if dir == root { String::new() } else { paths[&dir].clone() };
+        for entry in read_dir(device, crypto, inode) {
+            if paths.contains_key(&entry.inode_idx) { continue; }
+            paths.insert(entry.inode_idx, format!("{}/{}", prefix, entry.display_name()));
+            queue.push_back(entry.inode_idx);
+        }
+    }
+    paths
+}
+
+/// Entradas de un directorio (ninguna si no se puede leer)
+fn read_dir(device: &BlockDevice, crypto: &CryptoEngine, inode: &Inode) -> Vec<DirEntry> {
+    let mut data = Vec::new();
+    for &block in inode.direct_blocks.iter().take_while(|&&b| b != 0) {
+        let chunk = device.read_block(block).ok()
+            .and_then(|encrypted| crypto.decrypt(&encrypted).ok())
+            .and_then(|plain| compress::unpack_chunk(&plain).ok());
+        match chunk {
+            Some(chunk) => data.extend_from_slice(&chunk),
+            None => return Vec::new(),
+        }
+    }
+    data.truncate(inode.size as usize);
+    bincode::deserialize(&data).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::Compression;
+    use std::fs;
+
+    #[test]
+    fn test_scrub_classifies_images() {
+        let test_dir = "test_scrub";
+        let _ = fs::remove_dir_all(test_dir);
+        let device = BlockDevice::new(test_dir).unwrap();
+        let crypto = CryptoEngine::new("clave", [3; 16]);
+        let sb = SuperBlock { inode_table_start: 1, ..SuperBlock::for_tests(10, 3) };
+
+        let (_, chunk) = compress::pack_chunk(b"hola", 900, Compression::None);
+        device.write_block(2, &crypto.encrypt(&chunk).unwrap()).unwrap();               // Sano
+        device.write_block(3, &CryptoEngine::new("otra", [3; 16]).encrypt(&chunk).unwrap()).unwrap(); // No autentica
+        fs::write(format!("{}/qr_00004.png", test_dir), b"no es un png").unwrap();       // Ilegible
+        device.write_block(12, &crypto.encrypt(&chunk).unwrap()).unwrap();              // Fuera del volumen
+
+        let owners: BTreeMap<u64, Owner> = (2..6).map(|b| (b, Owner::new(format!("/f{}", b), Content::Chunk))).collect();
+        let findings = scrub(&device, &crypto, &sb, &[1, 2, 3, 4, 5], &owners, 3);
+        let got: Vec<(u64, ScrubStatus)> = findings.iter().map(|f| (f.block, f.status)).collect();
+        assert_eq!(got, vec![
+            (1, ScrubStatus::Missing), // Sin dueño anotado: se espera que exista
+            (3, ScrubStatus::Corrupt),
+            (4, ScrubStatus::Unreadable),
+            (5, ScrubStatus::Missing),
+            (12, ScrubStatus::Foreign),
+        ]);
+        assert_eq!(findings[2].owner, "/f4");
+        assert_eq!(summarize(&findings)[&ScrubStatus::Missing], 2);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::bitmap::Bitmap;
+use crate::crypto::{CryptoEngine, CryptoError};
+use crate::device::{BlockDevice, DeviceError};
+use crate::journal::Transaction;
+use crate::refcount::RefCounts;
+use crate::types::{Inode, SuperBlock, MAX_FILENAME_LEN};
+
+// Snapshots que puede tener un volumen a la vez (el catálogo se reserva para el peor caso)
+pub const MAX_SNAPSHOTS: usize = 4;
+
+// Bytes del catálogo por bloque (con la cabecera, serializado y cifrado cabe en un QR)
+const CATALOG_CHUNK: usize = 900;
+
+// Lo que ocupa serializado un snapshot sin bloques propios (nombre máximo incluido),
+// y cada bloque propio (par original -> copia de un BTreeMap)
+const SNAPSHOT_BASE_BYTES: u64 = 128;
+const MOVED_ENTRY_BYTES: u64 = 16;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("No existe el snapshot '{0}'")]
+    NotFound(String),
+    #[error("Ya existe un snapshot '{0}'")]
+    Exists(String),
+    #[error("Nombre de snapshot inválido (1 a {MAX_FILENAME_LEN} bytes)")]
+    BadName,
+    #[error("El volumen ya tiene {MAX_SNAPSHOTS} snapshots")]
+    TooMany,
+    #[error("El catálogo de snapshots no entra en su región")]
+    CatalogFull,
+    #[error("No queda espacio para copiar los metadatos de un snapshot")]
+    NoSpace,
+    #[error("Error del dispositivo: {0}")]
+    Device(#[from] DeviceError),
+    #[error("Error de cifrado: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("Error de formato: {0}")]
+    Format(#[from] bincode::Error),
+}
+
+/// Bloques del catálogo para un volumen cuya tabla de inodos y bitmap de
+/// inodos suman `meta_blocks`: alcanza aunque cada snapshot tenga su propia
+/// copia de todos ellos
+pub fn catalog_blocks_for(meta_blocks: u64) -> u64 {
+    let worst = MAX_SNAPSHOTS as u64 * (SNAPSHOT_BASE_BYTES + MOVED_ENTRY_BYTES * meta_blocks) + 8;
+    worst.div_ceil(CATALOG_CHUNK as u64)
+}
+
+/// Una foto del volumen entero, de solo lectura.
+///
+/// Los bloques de datos (archivos, directorios, xattrs) se comparten con el
+/// volumen vivo mediante los contadores de referencias: mientras el snapshot
+/// exista, escribir en uno de ellos hace copy-on-write. La tabla de inodos y el
+/// bitmap de inodos, en cambio, se reescriben en su lugar: la primera vez que
+/// el volumen vivo cambia uno de esos bloques, el contenido viejo se copia a un
+/// bloque nuevo y queda anotado en `moved`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    pub created_at: SystemTime,
+    pub root_dir_inode: u64,
+    /// Bloque de metadatos original -> copia con el contenido del momento del snapshot
+    pub moved: BTreeMap<u64, u64>,
+}
+
+impl Snapshot {
+    pub fn new(name: &str, sb: &SuperBlock) -> Result<Self, SnapshotError> {
+        if name.is_empty() || name.len() > MAX_FILENAME_LEN {
+            return Err(SnapshotError::BadName);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            created_at: SystemTime::now(),
+            root_dir_inode: sb.root_dir_inode,
+            moved: BTreeMap::new(),
+        })
+    }
+
+    /// Dónde está hoy la versión del snapshot de un bloque de metadatos
+    pub fn locate(&self, block: u64) -> u64 {
+        self.moved.get(&block).copied().unwrap_or(block)
+    }
+}
+
+/// ¿Es un bloque que los snapshots ven en su lugar original hasta que se copia?
+/// (tabla de inodos y bitmap de inodos: lo demás se comparte con refcounts)
+pub fn is_preserved(sb: &SuperBlock, block: u64) -> bool {
+    (sb.inode_table_start..sb.inode_table_start + sb.inode_table_blocks()).contains(&block)
+        || (sb.inode_bitmap_start..sb.inode_bitmap_start + sb.inode_bitmap_blocks).contains(&block)
+}
+
+/// Copy-on-write de la tabla de inodos y el bitmap de inodos: antes de aplicar
+/// `txn`, cada bloque que pisa y que algún snapshot todavía ve en su lugar
+/// original se copia (con el contenido de disco, el viejo) a un bloque nuevo de
+/// `bitmap`, y la copia se agrega a la transacción.
+/// Devuelve true si hubo copias: el bitmap, los contadores y el catálogo
+/// (`catalog_pages`) cambiaron y también tienen que ir en la transacción.
+pub fn preserve(
+    sb: &SuperBlock,
+    snapshots: &mut [Snapshot],
+    txn: &mut Transaction,
+    bitmap: &mut Bitmap,
+    refs: &mut RefCounts,
+    device: &BlockDevice,
+    crypto: &CryptoEngine,
+) -> Result<bool, SnapshotError> {
+    let blocks: Vec<u64> = txn.blocks().filter(|&block| is_preserved(sb, block)).collect();
+
+    let mut changed = false;
+    for block in blocks {
+        let waiting: Vec<usize> = (0..snapshots.len())
+            .filter(|&i| !snapshots[i].moved.contains_key(&block))
+            .collect();
+        if waiting.is_empty() { continue; }
+
+        // Un bloque de la tabla nunca escrito son inodos vacíos
+        let encrypted = device.read_block(block)?;
+        let old = if encrypted.iter().all(|&x| x == 0) {
+            bincode::serialize(&Vec::<Inode>::new())?
+        } else {
+            crypto.decrypt(&encrypted)?.to_vec()
+        };
+        let copy = bitmap.allocate().ok_or(SnapshotError::NoSpace)?;
+        txn.write(copy, old);
+
+        // Una sola copia para todos: cada snapshot de más es una referencia extra
+        // (con MAX_SNAPSHOTS el contador no se satura)
+        for (n, &i) in waiting.iter().enumerate() {
+            snapshots[i].moved.insert(block, copy);
+            if n > 0 { refs.add_share(copy); }
+        }
+        changed = true;
+    }
+    Ok(changed)
+}
+
+/// Cada bloque del catálogo: un pedazo de la lista serializada
+#[derive(Serialize, Deserialize, Debug)]
+struct CatalogPage {
+    parts: u32, // Cuántos bloques ocupa la lista (solo importa en el primero)
+    bytes: Vec<u8>,
+}
+
+/// Lee la lista de snapshots (un catálogo nunca escrito es una lista vacía)
+pub fn read_catalog(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock) -> Result<Vec<Snapshot>, SnapshotError> {
+    if sb.snapshot_blocks == 0 { return Ok(Vec::new()); }
+
+    let mut bytes = Vec::new();
+    let mut parts = 1;
+    let mut page = 0;
+    while page < parts {
+        let encrypted = device.read_block(sb.snapshot_start + page as u64)?;
+        if encrypted.iter().all(|&x| x == 0) { return Ok(Vec::new()); }
+        let record: CatalogPage = bincode::deserialize(&crypto.decrypt(&encrypted)?)?;
+        if page == 0 { parts = (record.parts as usize).min(sb.snapshot_blocks as usize); }
+        bytes.extend_from_slice(&record.bytes);
+        page += 1;
+    }
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+/// Bloques (en claro) que hay que escribir para guardar la lista, para meterlos
+/// en una transacción del journal junto con el resto del cambio
+pub fn catalog_pages(sb: &SuperBlock, snapshots: &[Snapshot]) -> Result<Vec<(u64, Vec<u8>)>, SnapshotError> {
+    let bytes = bincode::serialize(snapshots)?;
+    let parts = bytes.len().div_ceil(CATALOG_CHUNK).max(1);
+    if parts as u64 > sb.snapshot_blocks {
+        return Err(SnapshotError::CatalogFull);
+    }
+    let mut pages = Vec::with_capacity(parts);
+    for (page, chunk) in bytes.chunks(CATALOG_CHUNK).enumerate() {
+        let record = CatalogPage { parts: parts as u32, bytes: chunk.to_vec() };
+        pages.push((sb.snapshot_start + page as u64, bincode::serialize(&record)?));
+    }
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn superblock() -> SuperBlock {
+        SuperBlock {
+            inode_table_start: 20,
+            bitmap_start: 1,
+            bitmap_blocks: 1,
+            inode_bitmap_start: 2,
+            inode_bitmap_blocks: 1,
+            journal_start: 3,
+            journal_blocks: 4,
+            snapshot_start: 7,
+            snapshot_blocks: catalog_blocks_for(13),
+            refcount_start: 32,
+            refcount_blocks: 1,
+            dedup_index_start: 33,
+            ..SuperBlock::for_tests(100, 36)
+        }
+    }
+
+    #[test]
+    fn test_catalog_round_trip() {
+        let test_dir = "test_snapshot_catalog";
+        let _ = fs::remove_dir_all(test_dir);
+        let device = BlockDevice::new(test_dir).unwrap();
+        let crypto = CryptoEngine::new_plaintext();
+        let sb = superblock();
+
+        assert!(read_catalog(&device, &crypto, &sb).unwrap().is_empty());
+
+        // El peor caso (todos con copia propia de toda la tabla y el bitmap) entra
+        let mut snapshots = Vec::new();
+        for i in 0..MAX_SNAPSHOTS {
+            let mut snap = Snapshot::new(&i.to_string().repeat(MAX_FILENAME_LEN), &sb).unwrap();
+            for block in (20..32).chain(2..3) {
+                snap.moved.insert(block, 50 + block);
+            }
+            snapshots.push(snap);
+        }
+        let pages = catalog_pages(&sb, &snapshots).unwrap();
+        assert!(pages.len() > 1);
+        for (block, bytes) in pages {
+            device.write_block(block, &crypto.encrypt(&bytes).unwrap()).unwrap();
+        }
+        let loaded = read_catalog(&device, &crypto, &sb).unwrap();
+        assert_eq!(loaded, snapshots);
+        assert_eq!(loaded[0].locate(21), 71);
+        assert_eq!(loaded[0].locate(40), 40);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_preserve_copies_once() {
+        let test_dir = "test_snapshot_preserve";
+        let _ = fs::remove_dir_all(test_dir);
+        let device = BlockDevice::new(test_dir).unwrap();
+        let crypto = CryptoEngine::new_plaintext();
+        let sb = superblock();
+        device.write_block(20, &crypto.encrypt(b"tabla vieja").unwrap()).unwrap();
+
+        let mut snapshots = vec![Snapshot::new("a", &sb).unwrap(), Snapshot::new("b", &sb).unwrap()];
+        let mut bitmap = Bitmap::new(100);
+        for block in 0..40 { bitmap.set(block, true); }
+        let mut refs = RefCounts::new(1);
+
+        // Pisar la tabla copia el contenido viejo, una sola vez para los dos snapshots
+        let mut txn = Transaction::default();
+        txn.write(20, b"tabla nueva".to_vec());
+        txn.write(40, b"datos".to_vec());
+        assert!(preserve(&sb, &mut snapshots, &mut txn, &mut bitmap, &mut refs, &device, &crypto).unwrap());
+        let copy = snapshots[0].locate(20);
+        assert_ne!(copy, 20);
+        assert_eq!(snapshots[1].locate(20), copy);
+        assert_eq!(txn.get(copy), Some(&b"tabla vieja"[..]));
+        assert_eq!(refs.shares(copy), 1);
+        assert_eq!(snapshots[0].locate(40), 40); // Los datos se comparten con refcounts
+
+        // Ya copiado: la próxima escritura no vuelve a copiar
+        let mut txn = Transaction::default();
+        txn.write(20, b"otra vez".to_vec());
+        assert!(!preserve(&sb, &mut snapshots, &mut txn, &mut bitmap, &mut refs, &device, &crypto).unwrap());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_preserved_blocks_and_names() {
+        let sb = superblock();
+        assert!(is_preserved(&sb, 2) && is_preserved(&sb, 20) && is_preserved(&sb, 31));
+        assert!(!is_preserved(&sb, 1) && !is_preserved(&sb, 32) && !is_preserved(&sb, 60));
+        assert!(matches!(Snapshot::new("", &sb), Err(SnapshotError::BadName)));
+    }
+}
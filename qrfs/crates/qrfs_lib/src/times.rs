@@ -0,0 +1,85 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::types::Inode;
+
+// Con relatime, un atime más viejo que esto se actualiza igual (como en Linux)
+const RELATIME_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Cuándo se actualiza el atime al leer. Cada actualización reescribe el
+/// bloque QR de la tabla de inodos, así que por defecto se usa `Relatime`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AtimePolicy {
+    /// En cada lectura
+    Strict,
+    /// Solo si el atime quedó atrás del mtime/ctime, o tiene más de un día
+    #[default]
+    Relatime,
+    /// Nunca
+    Noatime,
+}
+
+impl FromStr for AtimePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strictatime" => Ok(AtimePolicy::Strict),
+            "relatime" => Ok(AtimePolicy::Relatime),
+            "noatime" => Ok(AtimePolicy::Noatime),
+            _ => Err(format!("Política de atime desconocida '{}' (use strictatime, relatime o noatime)", s)),
+        }
+    }
+}
+
+impl AtimePolicy {
+    /// ¿Hay que actualizar el atime de este inodo por una lectura en `now`?
+    pub fn needs_update(&self, inode: &Inode, now: SystemTime) -> bool {
+        match self {
+            AtimePolicy::Strict => true,
+            AtimePolicy::Noatime => false,
+            AtimePolicy::Relatime => {
+                inode.accessed_at <= inode.modified_at
+                    || inode.accessed_at <= inode.changed_at
+                    || now.duration_since(inode.accessed_at).unwrap_or_default() >= RELATIME_MAX_AGE
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FileType;
+
+    fn inode_read_at(atime: SystemTime, mtime: SystemTime) -> Inode {
+        let mut inode = Inode::new(FileType::File, 0o644);
+        inode.modified_at = mtime;
+        inode.changed_at = mtime;
+        inode.accessed_at = atime;
+        inode
+    }
+
+    #[test]
+    fn test_relatime() {
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        let policy = AtimePolicy::Relatime;
+
+        // Leído después de la última modificación: no se toca
+        assert!(!policy.needs_update(&inode_read_at(now - hour, now - 2 * hour), now));
+        // Modificado después de la última lectura: se actualiza
+        assert!(policy.needs_update(&inode_read_at(now - 2 * hour, now - hour), now));
+        // Lectura de hace más de un día: se actualiza
+        assert!(policy.needs_update(&inode_read_at(now - 25 * hour, now - 30 * hour), now));
+    }
+
+    #[test]
+    fn test_strict_and_noatime() {
+        let now = SystemTime::now();
+        let inode = inode_read_at(now, now - Duration::from_secs(60));
+        assert!(AtimePolicy::Strict.needs_update(&inode, now));
+        assert!(!AtimePolicy::Noatime.needs_update(&inode, now));
+        assert_eq!("noatime".parse::<AtimePolicy>(), Ok(AtimePolicy::Noatime));
+    }
+}
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::time::SystemTime;
 
+use crate::compress::Compression;
+use crate::error::FsError;
+use crate::xattr::Xattr;
+
 // --- CONSTANTES DE DISEÑO ---
 
 // Tamaño de un bloque lógico.
@@ -19,6 +23,18 @@ pub const MAX_FILENAME_LEN: usize = 64;
 // (Simplificación para el proyecto universitario)
 pub const DIRECT_POINTERS: usize = 12; 
 
+// Bytes (ya empaquetados, antes de cifrar) que guardamos por bloque de datos.
+// Deja margen para el nonce y el tag de AES-GCM dentro de BLOCK_SIZE.
+pub const CHUNK_SIZE: usize = 900;
+
+// Inodos por bloque de la tabla. Con el cifrado cada bloque admite ~990 bytes,
+// así que dejamos ~320 bytes por inodo serializado (symlink y xattrs embebidos incluidos).
+pub const INODES_PER_BLOCK: usize = 3;
+
+// Destinos de symlink de hasta este largo se guardan dentro del inodo;
+// los más largos van a bloques de datos como el contenido de un archivo.
+pub const SYMLINK_INLINE_MAX: usize = 48;
+
 // --- ESTRUCTURAS PRINCIPALES ---
 
 /// El Superbloque contiene la información global del sistema de archivos.
@@ -29,21 +45,89 @@ pub struct SuperBlock {
     pub total_blocks: u64,      // Tamaño total del FS en bloques [cite: 45]
     pub total_inodes: u64,      // Cantidad total de inodos disponibles [cite: 46]
     pub free_blocks_count: u64, // Contador rápido de espacio libre
+    pub free_inodes_count: u64, // Idem para inodos (qrfs_mount los guarda al sincronizar/desmontar)
     
     // Punteros a áreas críticas (índice del bloque donde empiezan)
     pub inode_table_start: u64, // Dónde empieza la tabla de inodos [cite: 47]
     pub bitmap_start: u64,      // Dónde empieza el mapa de bits [cite: 47]
+    pub bitmap_blocks: u64,     // Páginas del mapa de bits (ver `Bitmap::pages_for`)
+    pub inode_bitmap_start: u64, // Mapa de bits de inodos (1 = inodo en uso)
+    pub inode_bitmap_blocks: u64,
+    pub journal_start: u64,     // Journal de metadatos (ver `journal::Journal`)
+    pub journal_blocks: u64,
+    pub journal_seq: u64,       // Última transacción y próxima posición del journal,
+    pub journal_head: u64,      // guardadas al desmontar
+    pub journal_needs_recovery: bool, // true mientras está montado: si se corta, hay que rehacer el journal
+    pub snapshot_start: u64,    // Catálogo de snapshots (ver `snapshot::Snapshot`)
+    pub snapshot_blocks: u64,
     pub root_dir_inode: u64,    // Cuál es el inodo de la raíz (usualmente el 1)
+
+    // Compresión de los bloques de datos (cada bloque lleva su propio flag)
+    pub compression: Compression,
+
+    // Contadores de referencias extra por bloque (bloques compartidos)
+    pub refcount_start: u64,
+    pub refcount_blocks: u64,
+
+    // Índice de deduplicación (0 bloques = dedup desactivado)
+    pub dedup_index_start: u64,
+    pub dedup_index_blocks: u64,
     
     // Seguridad
     pub uuid: [u8; 16],         // ID único del volumen
 }
 
-/// Tipo de archivo: ¿Es un archivo normal o un directorio?
+impl SuperBlock {
+    /// Bloques de la tabla de inodos. Sale de la cantidad de inodos y no de dónde
+    /// empiezan los contadores: qrfs_resize puede mudar los contadores al crecer.
+    pub fn inode_table_blocks(&self) -> u64 {
+        self.total_inodes.div_ceil(INODES_PER_BLOCK as u64)
+    }
+
+    /// Bloque de la tabla de inodos que guarda el inodo `inode_idx`
+    pub fn inode_block(&self, inode_idx: u64) -> u64 {
+        self.inode_table_start + inode_idx / INODES_PER_BLOCK as u64
+    }
+
+    /// Superbloque de prueba sin regiones (cada test pone las que usa con `..`).
+    /// Fuera de este crate, con la feature `test-util`.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn for_tests(total_blocks: u64, total_inodes: u64) -> Self {
+        Self {
+            magic: QRFS_MAGIC,
+            total_blocks,
+            total_inodes,
+            free_blocks_count: 0,
+            free_inodes_count: 0,
+            inode_table_start: 0,
+            bitmap_start: 0,
+            bitmap_blocks: 0,
+            inode_bitmap_start: 0,
+            inode_bitmap_blocks: 0,
+            journal_start: 0,
+            journal_blocks: 0,
+            journal_seq: 0,
+            journal_head: 0,
+            journal_needs_recovery: false,
+            snapshot_start: 0,
+            snapshot_blocks: 0,
+            root_dir_inode: 1,
+            compression: Compression::None,
+            refcount_start: 0,
+            refcount_blocks: 0,
+            dedup_index_start: 0,
+            dedup_index_blocks: 0,
+            uuid: [0; 16],
+        }
+    }
+}
+
+/// Tipo de archivo: ¿Es un archivo normal, un directorio o un enlace simbólico?
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Copy)]
 pub enum FileType {
     File,
     Directory,
+    Symlink,
 }
 
 /// El Inodo (Index Node) representa un objeto en el FS.
@@ -54,10 +138,15 @@ pub struct Inode {
     pub mode: u16,              // Permisos (ej. 755)
     pub size: u64,              // Tamaño lógico del archivo en bytes
     pub file_type: FileType,    // Archivo o Directorio
+    pub nlink: u32,             // Entradas de directorio que apuntan a este inodo
+    pub uid: u32,               // Dueño
+    pub gid: u32,               // Grupo
     
     // Tiempos (opcionales según enunciado, pero recomendados para FUSE)
     pub created_at: SystemTime,
-    pub modified_at: SystemTime,
+    pub modified_at: SystemTime, // mtime: cambió el contenido
+    pub accessed_at: SystemTime, // atime: última lectura (según la política de montaje)
+    pub changed_at: SystemTime,  // ctime: cambió el contenido o los metadatos
     
     // Bloques de datos: Lista de IDs de bloques donde está el contenido
     pub direct_blocks: [u64; DIRECT_POINTERS], 
@@ -65,6 +154,14 @@ pub struct Inode {
     // Para archivos más grandes que (DIRECT_POINTERS * BLOCK_SIZE),
     // usaríamos un bloque indirecto. (Opcional para simplificar si tus archivos son pequeños)
     pub indirect_block: u64, 
+
+    // Datos embebidos en el propio inodo (destino de symlinks cortos, sin gastar un QR)
+    pub inline_data: Vec<u8>,
+
+    // Atributos extendidos: en el inodo si son pocos, o en un bloque propio
+    // (`xattr_block`, 0 si no hay). Nunca en los dos lugares a la vez.
+    pub xattrs: Vec<Xattr>,
+    pub xattr_block: u64,
 }
 
 impl Inode {
@@ -74,12 +171,32 @@ impl Inode {
             mode,
             size: 0,
             file_type,
+            // Un directorio se cuenta a sí mismo ("."), además de su entrada en el padre
+            nlink: if file_type == FileType::Directory { 2 } else { 1 },
+            uid: 0,
+            gid: 0,
             created_at: SystemTime::now(),
             modified_at: SystemTime::now(),
+            accessed_at: SystemTime::now(),
+            changed_at: SystemTime::now(),
             direct_blocks: [0; DIRECT_POINTERS], // 0 indica "vacío" o "null"
             indirect_block: 0,
+            inline_data: Vec::new(),
+            xattrs: Vec::new(),
+            xattr_block: 0,
         }
     }
+
+    /// Cantidad de bloques de datos asignados (punteros directos no nulos)
+    pub fn used_blocks(&self) -> u64 {
+        self.direct_blocks.iter().filter(|&&b| b != 0).count() as u64
+    }
+
+    /// Bloques que ocuparía el contenido si se guardara sin comprimir
+    pub fn raw_blocks(&self) -> u64 {
+        if !self.inline_data.is_empty() { return 0; } // Vive dentro del inodo
+        self.size.div_ceil(CHUNK_SIZE as u64)
+    }
 }
 
 /// Entrada de Directorio.
@@ -88,5 +205,43 @@ impl Inode {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DirEntry {
     pub inode_idx: u64,            // A qué inodo apunta
-    pub name: String,              // Nombre del archivo ("hola.txt")
+    // Nombre del archivo ("hola.txt"). Son bytes, como en Linux: no tiene por qué
+    // ser UTF-8. (bincode lo guarda igual que un String, así que los volúmenes viejos se leen bien)
+    pub name: Vec<u8>,
+}
+
+impl DirEntry {
+    /// Valida un nombre antes de guardarlo en un directorio
+    pub fn check_name(name: &[u8]) -> Result<(), FsError> {
+        if name.is_empty() || name.contains(&b'/') || name.contains(&0) {
+            return Err(FsError::InvalidArgument);
+        }
+        if name.len() > MAX_FILENAME_LEN {
+            return Err(FsError::NameTooLong);
+        }
+        Ok(())
+    }
+
+    /// Nombre para mostrar (los bytes que no son UTF-8 se reemplazan)
+    pub fn display_name(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_entry_names() {
+        assert_eq!(DirEntry::check_name(b"hola.txt"), Ok(()));
+        assert_eq!(DirEntry::check_name(&[0xff, 0xfe]), Ok(())); // No UTF-8, pero válido
+        assert_eq!(DirEntry::check_name(&[b'a'; MAX_FILENAME_LEN + 1]), Err(FsError::NameTooLong));
+        assert_eq!(DirEntry::check_name(b"a/b"), Err(FsError::InvalidArgument));
+
+        // Mismo formato en disco que cuando el nombre era un String
+        let old = bincode::serialize(&(7u64, "hola".to_string())).unwrap();
+        let entry: DirEntry = bincode::deserialize(&old).unwrap();
+        assert_eq!(entry.name, b"hola");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,178 @@
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::bitmap::Bitmap;
+use crate::crypto::{CryptoEngine, CryptoError, VolumeHeader};
+use crate::dedup::{ChunkIndex, IndexEntry};
+use crate::device::{BlockDevice, DeviceError};
+use crate::journal::{Journal, JournalError, Transaction};
+use crate::refcount::RefCounts;
+use crate::types::{FileType, Inode, SuperBlock, INODES_PER_BLOCK, QRFS_MAGIC};
+
+#[derive(Error, Debug)]
+pub enum VolumeError {
+    #[error(transparent)]
+    Header(CryptoError),
+    #[error("Contraseña incorrecta")]
+    WrongPassphrase,
+    #[error("No es un volumen QRFS válido")]
+    NotQrfs,
+    #[error("No se pudo leer la passphrase: {0}")]
+    Prompt(std::io::Error),
+    #[error("Error del dispositivo: {0}")]
+    Device(#[from] DeviceError),
+    #[error("Error de cifrado: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("Error de formato: {0}")]
+    Format(#[from] bincode::Error),
+    #[error("Error del journal: {0}")]
+    Journal(#[from] JournalError),
+}
+
+/// Abre un volumen desmontado: lee la cabecera del bloque 0, pide la passphrase
+/// con `ask_passphrase` (solo si el volumen está cifrado) y descifra el superbloque
+pub fn open(
+    device: &BlockDevice,
+    ask_passphrase: impl FnOnce() -> std::io::Result<Zeroizing<String>>,
+) -> Result<(CryptoEngine, SuperBlock), VolumeError> {
+    let block0 = device.read_block(0)?;
+    let (header, encrypted_sb) = VolumeHeader::parse(&block0).map_err(VolumeError::Header)?;
+
+    let password = if header.needs_passphrase() {
+        ask_passphrase().map_err(VolumeError::Prompt)?
+    } else {
+        Zeroizing::new(String::new())
+    };
+    let crypto = CryptoEngine::from_header(&header, &password);
+    drop(password);
+
+    let sb = read_superblock(&crypto, encrypted_sb)?;
+    Ok((crypto, sb))
+}
+
+/// Descifra el superbloque (lo que sigue a la cabecera en el bloque 0) y verifica la firma
+pub fn read_superblock(crypto: &CryptoEngine, encrypted_sb: &[u8]) -> Result<SuperBlock, VolumeError> {
+    let sb_bytes = crypto.decrypt(encrypted_sb).map_err(|_| VolumeError::WrongPassphrase)?;
+    let sb: SuperBlock = bincode::deserialize(&sb_bytes)?;
+    if sb.magic != QRFS_MAGIC {
+        return Err(VolumeError::NotQrfs);
+    }
+    Ok(sb)
+}
+
+/// Reescribe el bloque 0 (cabecera en claro + superbloque cifrado)
+pub fn write_superblock(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock) -> Result<(), VolumeError> {
+    let mut block0 = crypto.header().to_bytes();
+    block0.extend_from_slice(&crypto.encrypt(&bincode::serialize(sb)?)?);
+    device.write_block(0, &block0)?;
+    Ok(())
+}
+
+/// Aplica un cambio con el volumen desmontado, protegido por el journal igual
+/// que una operación de qrfs_mount: si se corta, qrfs_fsck lo rehace.
+/// Si el cambio no entra en el journal se escribe sin él (después de una barrera,
+/// ver `Journal::commit_barrier`) y se devuelven sus bloques y los del journal.
+pub fn commit(
+    device: &BlockDevice,
+    crypto: &CryptoEngine,
+    sb: &mut SuperBlock,
+    txn: &Transaction,
+) -> Result<Option<(u64, u64)>, VolumeError> {
+    let mut journal = Journal::from_superblock(sb);
+    sb.journal_needs_recovery = true;
+    write_superblock(device, crypto, sb)?;
+
+    let unprotected = match journal.commit(device, crypto, txn) {
+        Ok(()) => None,
+        Err(JournalError::TooLarge(parts, blocks)) => {
+            journal.commit_barrier(device, crypto)?;
+            Some((parts, blocks))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    txn.apply(device, crypto)?;
+
+    sb.journal_seq = journal.seq();
+    sb.journal_head = journal.head();
+    sb.journal_needs_recovery = false;
+    write_superblock(device, crypto, sb)?;
+    Ok(unprotected)
+}
+
+/// Lee un bloque de la tabla de inodos (uno que nunca se escribió no tiene inodos)
+pub fn load_table_block(device: &BlockDevice, crypto: &CryptoEngine, block: u64) -> Result<Vec<Inode>, VolumeError> {
+    let enc_inodes = device.read_block(block)?;
+    let mut inodes: Vec<Inode> = if enc_inodes.iter().all(|&x| x == 0) {
+        Vec::new()
+    } else {
+        bincode::deserialize(&crypto.decrypt(&enc_inodes)?)?
+    };
+    inodes.resize(INODES_PER_BLOCK, Inode::new(FileType::File, 0));
+    Ok(inodes)
+}
+
+/// Lee la tabla de inodos entera; `locate` dice dónde está cada bloque (para leer
+/// la de un snapshot). Los bloques que nunca se escribieron cuentan como inodos vacíos.
+pub fn load_inode_table(
+    device: &BlockDevice,
+    crypto: &CryptoEngine,
+    sb: &SuperBlock,
+    locate: impl Fn(u64) -> u64,
+) -> Result<Vec<Inode>, VolumeError> {
+    let mut inode_list = Vec::new();
+    for table_block in 0..sb.inode_table_blocks() {
+        inode_list.extend(load_table_block(device, crypto, locate(sb.inode_table_start + table_block))?);
+    }
+    Ok(inode_list)
+}
+
+/// Lee el bitmap de bloques
+pub fn load_bitmap(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock) -> Result<Bitmap, VolumeError> {
+    load_bitmap_region(device, crypto, sb.bitmap_start..sb.bitmap_start + sb.bitmap_blocks, sb.total_blocks)
+}
+
+/// Lee el bitmap de inodos; `locate` dice dónde está cada página (como en `load_inode_table`)
+pub fn load_inode_bitmap(
+    device: &BlockDevice,
+    crypto: &CryptoEngine,
+    sb: &SuperBlock,
+    locate: impl Fn(u64) -> u64,
+) -> Result<Bitmap, VolumeError> {
+    let pages = (sb.inode_bitmap_start..sb.inode_bitmap_start + sb.inode_bitmap_blocks).map(locate);
+    load_bitmap_region(device, crypto, pages, sb.total_inodes)
+}
+
+/// Lee las páginas (en orden, de los bloques `pages`) de un bitmap que rastrea `size` bloques (o inodos)
+fn load_bitmap_region(
+    device: &BlockDevice,
+    crypto: &CryptoEngine,
+    pages: impl Iterator<Item = u64>,
+    size: u64,
+) -> Result<Bitmap, VolumeError> {
+    let mut bitmap = Bitmap::new(size as usize);
+    for (page, block) in pages.enumerate() {
+        let bytes: Vec<u8> = bincode::deserialize(&crypto.decrypt(&device.read_block(block)?)?)?;
+        bitmap.load_page(page, &bytes);
+    }
+    Ok(bitmap)
+}
+
+/// Lee los contadores de referencias
+pub fn load_refcounts(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock) -> Result<RefCounts, VolumeError> {
+    let mut refs = RefCounts::new(sb.refcount_blocks as usize);
+    for page in 0..sb.refcount_blocks {
+        let counts: Vec<u16> = bincode::deserialize(&crypto.decrypt(&device.read_block(sb.refcount_start + page)?)?)?;
+        refs.load_page(page as usize, &counts);
+    }
+    Ok(refs)
+}
+
+/// Lee el índice de dedup (vacío si el volumen no tiene dedup)
+pub fn load_chunk_index(device: &BlockDevice, crypto: &CryptoEngine, sb: &SuperBlock) -> Result<ChunkIndex, VolumeError> {
+    let mut chunk_index = ChunkIndex::new(sb.dedup_index_blocks.max(1) as usize);
+    for bucket in 0..sb.dedup_index_blocks {
+        let entries: Vec<IndexEntry> = bincode::deserialize(&crypto.decrypt(&device.read_block(sb.dedup_index_start + bucket)?)?)?;
+        chunk_index.load_bucket(bucket as usize, entries);
+    }
+    Ok(chunk_index)
+}
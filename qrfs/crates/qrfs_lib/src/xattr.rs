@@ -0,0 +1,126 @@
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::types::CHUNK_SIZE;
+
+// Largo máximo de un nombre (como XATTR_NAME_MAX de Linux)
+pub const XATTR_NAME_MAX: usize = 255;
+
+// Si la lista serializada cabe en esto, vive dentro del inodo (sin gastar un QR)
+pub const XATTR_INLINE_MAX: usize = 64;
+
+// Si no, se desborda a un bloque propio: un solo bloque por inodo
+// (menos el byte de formato de `compress::pack_chunk`)
+pub const XATTR_BLOCK_MAX: usize = CHUNK_SIZE - 1;
+
+// Flags de setxattr(2)
+pub const XATTR_CREATE: i32 = 1;
+pub const XATTR_REPLACE: i32 = 2;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum XattrError {
+    #[error("El atributo no existe")]
+    NotFound,
+    #[error("El atributo ya existe")]
+    Exists,
+    #[error("Nombre de atributo vacío o demasiado largo")]
+    BadName,
+    #[error("Valor de atributo demasiado grande")]
+    ValueTooLarge,
+    #[error("No hay espacio para más atributos en el inodo")]
+    NoSpace,
+}
+
+/// Un atributo extendido. El nombre incluye el espacio ("user.tag")
+/// y se guarda como bytes, igual que lo entrega el kernel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Xattr {
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// Tamaño serializado de una lista de atributos (lo que decide inline vs. bloque)
+pub fn serialized_len(xattrs: &[Xattr]) -> usize {
+    bincode::serialized_size(xattrs).map(|n| n as usize).unwrap_or(usize::MAX)
+}
+
+pub fn get<'a>(xattrs: &'a [Xattr], name: &[u8]) -> Option<&'a [u8]> {
+    xattrs.iter().find(|x| x.name == name).map(|x| x.value.as_slice())
+}
+
+/// Crea o reemplaza un atributo respetando XATTR_CREATE / XATTR_REPLACE.
+/// Falla con `NoSpace` si la lista resultante no cabe ni en un bloque.
+pub fn set(xattrs: &mut Vec<Xattr>, name: &[u8], value: &[u8], flags: i32) -> Result<(), XattrError> {
+    if name.is_empty() || name.len() > XATTR_NAME_MAX {
+        return Err(XattrError::BadName);
+    }
+    if value.len() > XATTR_BLOCK_MAX {
+        return Err(XattrError::ValueTooLarge);
+    }
+
+    let mut updated = xattrs.clone();
+    match updated.iter_mut().find(|x| x.name == name) {
+        Some(_) if flags & XATTR_CREATE != 0 => return Err(XattrError::Exists),
+        Some(existing) => existing.value = value.to_vec(),
+        None if flags & XATTR_REPLACE != 0 => return Err(XattrError::NotFound),
+        None => updated.push(Xattr { name: name.to_vec(), value: value.to_vec() }),
+    }
+
+    if serialized_len(&updated) > XATTR_BLOCK_MAX {
+        return Err(XattrError::NoSpace);
+    }
+    *xattrs = updated;
+    Ok(())
+}
+
+pub fn remove(xattrs: &mut Vec<Xattr>, name: &[u8]) -> Result<(), XattrError> {
+    let pos = xattrs.iter().position(|x| x.name == name).ok_or(XattrError::NotFound)?;
+    xattrs.remove(pos);
+    Ok(())
+}
+
+/// Nombres separados por NUL, el formato que espera listxattr(2)
+pub fn list(xattrs: &[Xattr]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for x in xattrs {
+        out.extend_from_slice(&x.name);
+        out.push(0);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_replace_remove() {
+        let mut xattrs = Vec::new();
+        set(&mut xattrs, b"user.tag", b"rojo", XATTR_CREATE).unwrap();
+        assert_eq!(set(&mut xattrs, b"user.tag", b"azul", XATTR_CREATE), Err(XattrError::Exists));
+        assert_eq!(set(&mut xattrs, b"user.otro", b"x", XATTR_REPLACE), Err(XattrError::NotFound));
+
+        set(&mut xattrs, b"user.tag", b"azul", 0).unwrap();
+        assert_eq!(get(&xattrs, b"user.tag"), Some(&b"azul"[..]));
+
+        set(&mut xattrs, b"user.otro", b"", 0).unwrap();
+        assert_eq!(list(&xattrs), b"user.tag\0user.otro\0");
+
+        remove(&mut xattrs, b"user.tag").unwrap();
+        assert_eq!(remove(&mut xattrs, b"user.tag"), Err(XattrError::NotFound));
+        assert_eq!(xattrs.len(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut xattrs = Vec::new();
+        assert_eq!(set(&mut xattrs, b"", b"x", 0), Err(XattrError::BadName));
+        assert_eq!(set(&mut xattrs, &[b'a'; XATTR_NAME_MAX + 1], b"x", 0), Err(XattrError::BadName));
+        assert_eq!(set(&mut xattrs, b"user.big", &[0; XATTR_BLOCK_MAX + 1], 0), Err(XattrError::ValueTooLarge));
+
+        // Entra sola, pero no junto a otra del mismo tamaño
+        set(&mut xattrs, b"user.a", &[0; 500], 0).unwrap();
+        assert_eq!(set(&mut xattrs, b"user.b", &[0; 500], 0), Err(XattrError::NoSpace));
+        assert!(get(&xattrs, b"user.b").is_none());
+    }
+}
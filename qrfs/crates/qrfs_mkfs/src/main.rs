@@ -2,12 +2,18 @@
 
 use clap::Parser;
 use qrfs_lib::device::BlockDevice;
-use qrfs_lib::types::{SuperBlock, Inode, FileType, BLOCK_SIZE, DIRECT_POINTERS, QRFS_MAGIC};
+use qrfs_lib::types::{SuperBlock, Inode, FileType, BLOCK_SIZE, CHUNK_SIZE, INODES_PER_BLOCK, QRFS_MAGIC};
 use qrfs_lib::bitmap::Bitmap;
 use qrfs_lib::crypto::CryptoEngine;
+use qrfs_lib::compress::{self, Compression};
+use qrfs_lib::refcount::RefCounts;
+use qrfs_lib::dedup::{ChunkIndex, IndexEntry};
+use qrfs_lib::journal::journal_blocks_for;
+use qrfs_lib::snapshot::catalog_blocks_for;
 use std::path::PathBuf;
 use std::io::Write;
 use rpassword::read_password;
+use zeroize::Zeroizing;
 
 /// Herramienta para formatear un sistema de archivos QRFS
 #[derive(Parser, Debug)]
@@ -20,6 +26,20 @@ struct Args {
     /// Número de bloques a crear (si no existen ya)
     #[arg(short, long, default_value_t = 100)]
     blocks: u64,
+
+    /// No cifrar el volumen (cifrado nulo): cualquiera puede leer los QRs.
+    /// Útil para depurar o para publicar archivos abiertos.
+    #[arg(long)]
+    no_encryption: bool,
+
+    /// Compresión de los datos antes de cifrar: none o deflate.
+    /// Menos bytes = menos QRs que imprimir y escanear.
+    #[arg(long, default_value = "none")]
+    compression: Compression,
+
+    /// Deduplicar bloques de datos idénticos (reserva espacio para el índice)
+    #[arg(long)]
+    dedup: bool,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -33,48 +53,70 @@ fn main() -> anyhow::Result<()> {
     // Si la carpeta está vacía, podríamos pre-generar los bloques físicos,
     // pero QRFS los creará on-demand al escribir. Validamos el tamaño.
     let total_blocks = args.blocks;
-    if total_blocks < 5 {
-        anyhow::bail!("El tamaño mínimo es de 5 bloques (Superbloque + Bitmap + Inodos + Raíz + Datos)");
+    if total_blocks < 12 {
+        anyhow::bail!("El tamaño mínimo es de 12 bloques (Superbloque + Bitmap + Bitmap de inodos + Journal + Snapshots + Inodos + Raíz + Datos)");
     }
 
-    // 2. Pedir contraseña
-    print!("Ingrese la passphrase para cifrar el sistema: ");
-    std::io::stdout().flush()?;
-    let password = read_password()?;
-    
-    print!("Confirme la passphrase: ");
-    std::io::stdout().flush()?;
-    let confirm = read_password()?;
+    // 2 y 3. Pedir contraseña e inicializar Criptografía (Genera un Salt aleatorio nuevo)
+    let crypto = if args.no_encryption {
+        println!("AVISO: el volumen NO estará cifrado. Cualquiera podrá leer los QRs.");
+        CryptoEngine::new_plaintext()
+    } else {
+        print!("Ingrese la passphrase para cifrar el sistema: ");
+        std::io::stdout().flush()?;
+        let password = Zeroizing::new(read_password()?);
+        
+        print!("Confirme la passphrase: ");
+        std::io::stdout().flush()?;
+        let confirm = Zeroizing::new(read_password()?);
 
-    if password != confirm {
-        anyhow::bail!("Las contraseñas no coinciden.");
-    }
+        if password != confirm {
+            anyhow::bail!("Las contraseñas no coinciden.");
+        }
 
-    // 3. Inicializar Criptografía (Genera un Salt aleatorio nuevo)
-    let crypto = CryptoEngine::new_with_random_salt(&password);
+        // Las passphrases se borran al salir de este bloque
+        CryptoEngine::new_with_random_salt(&password)
+    };
 
     println!("Iniciando formateo de {} bloques...", total_blocks);
 
     // --- CÁLCULO DE ESTRUCTURA ---
     // Distribución simple:
     // Bloque 0: Header (Salt) + Superbloque Cifrado
-    // Bloque 1: Bitmap (Cifrado)
-    // Bloque 2..N: Tabla de Inodos (Cifrada)
-    // Bloque N+1..: Datos
+    // Bloques 1..: Bitmap (Cifrado, una página por bloque)
+    // Luego: Bitmap de inodos (Cifrado, idem)
+    // Luego: Journal de metadatos (Cifrado, arranca vacío)
+    // Luego: Catálogo de snapshots (Cifrado, arranca vacío)
+    // Luego: Tabla de Inodos (Cifrada)
+    // Luego: Contadores de referencias + Índice de dedup (si se pidió)
+    // Resto: Datos
 
     // Reservamos espacio para tabla de inodos (ej. 10% del disco o fijo)
     // Simplificación: 10 inodos por bloque. Digamos que queremos soportar 'total_blocks' archivos.
     // Tamaño inode = aprox 120 bytes. En 1KB caben unos 8.
     // Reservamos (total_blocks / 8) bloques para inodos.
     let inode_blocks = (total_blocks / 8).max(1); 
-    let bitmap_blocks = 1; // Para 100 bloques sobra con 1 bloque de bitmap (maneja 8192 bloques)
+    let total_inodes = inode_blocks * INODES_PER_BLOCK as u64;
+    // Cada página del bitmap cubre 6400 bloques (o inodos)
+    let bitmap_blocks = Bitmap::pages_for(total_blocks);
+    let inode_bitmap_blocks = Bitmap::pages_for(total_inodes);
     
     let sb_idx = 0;
     let bitmap_idx = 1;
-    let inode_table_idx = 2;
-    let data_start_idx = inode_table_idx + inode_blocks;
-
-    let total_inodes = inode_blocks * (BLOCK_SIZE as u64 / 128); // Estimado grosero
+    let inode_bitmap_idx = bitmap_idx + bitmap_blocks;
+    let journal_idx = inode_bitmap_idx + inode_bitmap_blocks;
+    let journal_blocks = journal_blocks_for(total_blocks);
+    let snapshot_idx = journal_idx + journal_blocks;
+    let snapshot_blocks = catalog_blocks_for(inode_blocks + inode_bitmap_blocks);
+    let inode_table_idx = snapshot_idx + snapshot_blocks;
+    let refcount_idx = inode_table_idx + inode_blocks;
+    let refcount_blocks = RefCounts::pages_for(total_blocks);
+    let dedup_idx = refcount_idx + refcount_blocks;
+    let dedup_blocks = if args.dedup { ChunkIndex::buckets_for(total_blocks) } else { 0 };
+    let data_start_idx = dedup_idx + dedup_blocks;
+    if data_start_idx + 1 >= total_blocks {
+        anyhow::bail!("El volumen es muy pequeño: los metadatos ocupan {} bloques", data_start_idx);
+    }
 
     // 4. Crear Estructuras en Memoria
 
@@ -88,36 +130,57 @@ fn main() -> anyhow::Result<()> {
     let root_block = data_start_idx;
     bitmap.set(root_block as usize, true);
 
+    // A2) BITMAP DE INODOS: el 0 es el "nulo" y el 1 la raíz
+    let mut inode_bitmap = Bitmap::new(total_inodes as usize);
+    inode_bitmap.set(0, true);
+    inode_bitmap.set(1, true);
+
     // B) INODO RAÍZ
     let mut root_inode = Inode::new(FileType::Directory, 0o755);
     root_inode.size = 0; // El tamaño crece conforme metemos DirEntries
     root_inode.direct_blocks[0] = root_block; // Apunta al primer bloque de datos reservado
+    // La raíz es de quien formatea el volumen
+    // SAFETY: getuid y getgid no reciben punteros y siempre tienen éxito.
+    root_inode.uid = unsafe { libc::getuid() };
+    root_inode.gid = unsafe { libc::getgid() };
     
     // C) SUPERBLOQUE
     let sb = SuperBlock {
         magic: QRFS_MAGIC,
         total_blocks,
         total_inodes,
-        free_blocks_count: total_blocks - data_start_idx - 1,
+        free_blocks_count: bitmap.free_count(),
+        free_inodes_count: inode_bitmap.free_count(),
         inode_table_start: inode_table_idx,
         bitmap_start: bitmap_idx,
+        bitmap_blocks,
+        inode_bitmap_start: inode_bitmap_idx,
+        inode_bitmap_blocks,
+        journal_start: journal_idx,
+        journal_blocks,
+        journal_seq: 0,
+        journal_head: 0,
+        journal_needs_recovery: false,
+        snapshot_start: snapshot_idx,
+        snapshot_blocks,
         root_dir_inode: 1, // El inodo 1 será la raíz (el 0 suele ser nulo)
+        compression: args.compression,
+        refcount_start: refcount_idx,
+        refcount_blocks,
+        dedup_index_start: dedup_idx,
+        dedup_index_blocks: dedup_blocks,
         uuid: *uuid::Uuid::new_v4().as_bytes(),
     };
 
     // 5. Escritura en Disco (Física + Cifrado)
 
     // PASO 1: Escribir Superbloque (Bloque 0)
-    // Formato especial: [SALT (16 bytes)] [ENCRYPTED_DATA]
+    // Formato especial: [CIPHER (1 byte) | SALT (16 bytes)] [ENCRYPTED_DATA]
     let sb_bytes = bincode::serialize(&sb)?;
     let sb_encrypted = crypto.encrypt(&sb_bytes)?;
     
     let mut block0_data = Vec::new();
-    block0_data.extend_from_slice(&crypto.salt); // Guardamos Salt en claro
-    block0_data.extend_from_slice(&sb_encrypted);
-    
-    let mut block0_data = Vec::new();
-    block0_data.extend_from_slice(&crypto.salt); // Guardamos Salt en claro
+    block0_data.extend_from_slice(&crypto.header().to_bytes()); // Cabecera en claro
     block0_data.extend_from_slice(&sb_encrypted);
     
     // --- BORRA O COMENTA ESTO ---
@@ -132,23 +195,33 @@ fn main() -> anyhow::Result<()> {
     println!("[x] Superbloque escrito en bloque {}", sb_idx);
 
     // PASO 2: Escribir Bitmap
-    let bitmap_bytes = bincode::serialize(&bitmap)?;
-    let bitmap_encrypted = crypto.encrypt(&bitmap_bytes)?;
-    // Nota: Si el bitmap cifrado excede 1 bloque, esto fallará en device. 
-    // Para el proyecto, asumimos discos pequeños (<8000 bloques).
-    device.write_block(bitmap_idx, &bitmap_encrypted)?;
-    println!("[x] Bitmap escrito en bloque {}", bitmap_idx);
+    for page in 0..bitmap_blocks {
+        let page_bytes = bincode::serialize(bitmap.page(page as usize))?;
+        device.write_block(bitmap_idx + page, &crypto.encrypt(&page_bytes)?)?;
+    }
+    println!("[x] Bitmap escrito en bloques {}..{}", bitmap_idx, inode_bitmap_idx);
+
+    for page in 0..inode_bitmap_blocks {
+        let page_bytes = bincode::serialize(inode_bitmap.page(page as usize))?;
+        device.write_block(inode_bitmap_idx + page, &crypto.encrypt(&page_bytes)?)?;
+    }
+    println!("[x] Bitmap de inodos ({} inodos) escrito en bloques {}..{}", total_inodes, inode_bitmap_idx, journal_idx);
+
+    // El journal y el catálogo de snapshots arrancan vacíos: se borran los QRs
+    // que hubiera de un volumen anterior
+    device.trim(journal_idx, inode_table_idx)?;
+    println!("[x] Journal reservado en bloques {}..{}", journal_idx, snapshot_idx);
+    println!("[x] Catálogo de snapshots reservado en bloques {}..{}", snapshot_idx, inode_table_idx);
 
     // PASO 3: Escribir Tabla de Inodos
     // El Inodo Raíz (índice 1) vive en el primer bloque de la tabla de inodos.
     // Calculamos cuántos inodos caben en un bloque para no pasarnos.
     
-    // Un inodo serializado pesa aprox 120-150 bytes.
-    // En 1024 bytes caben unos 6-8 inodos.
-    let inodes_per_block = (BLOCK_SIZE / 150).max(1); 
+    // Un inodo serializado pesa aprox 150-240 bytes (según lo que lleve embebido).
+    // Usamos la misma cantidad por bloque que qrfs_mount.
     
-    // Creamos SOLO el primer paquete de inodos
-    let mut first_inode_block = vec![Inode::new(FileType::File, 0); inodes_per_block];
+    // Primer paquete de inodos (el resto de la tabla arranca vacío)
+    let mut first_inode_block = vec![Inode::new(FileType::File, 0); INODES_PER_BLOCK];
     first_inode_block[1] = root_inode; // Inodo 1 es Root
 
     let inodes_bytes = bincode::serialize(&first_inode_block)?;
@@ -160,15 +233,40 @@ fn main() -> anyhow::Result<()> {
 
     let inodes_encrypted = crypto.encrypt(&inodes_bytes)?;
     
-    // Escribimos SOLO el primer bloque de la tabla (donde está la raíz)
+    // El primer bloque de la tabla lleva la raíz
     device.write_block(inode_table_idx, &inodes_encrypted)?;
-    println!("[x] Tabla de inodos (bloque inicial) escrita en bloque {}", inode_table_idx);
+
+    // El resto va vacío: si la carpeta tenía QRs de un volumen anterior, no deben
+    // confundirse con inodos
+    let empty_bytes = bincode::serialize(&vec![Inode::new(FileType::File, 0); INODES_PER_BLOCK])?;
+    for table_block in 1..inode_blocks {
+        device.write_block(inode_table_idx + table_block, &crypto.encrypt(&empty_bytes)?)?;
+    }
+    println!("[x] Tabla de inodos escrita en bloques {}..{}", inode_table_idx, inode_table_idx + inode_blocks);
+
+    // PASO 3b: Contadores de referencias (todo en 0 = un solo dueño) e índice de dedup vacío
+    let refs = RefCounts::new(refcount_blocks as usize);
+    for page in 0..refcount_blocks {
+        let page_bytes = bincode::serialize(refs.page(page as usize))?;
+        device.write_block(refcount_idx + page, &crypto.encrypt(&page_bytes)?)?;
+    }
+    let empty_bucket: Vec<IndexEntry> = Vec::new();
+    let bucket_bytes = bincode::serialize(&empty_bucket)?;
+    for bucket in 0..dedup_blocks {
+        device.write_block(dedup_idx + bucket, &crypto.encrypt(&bucket_bytes)?)?;
+    }
+    println!("[x] Contadores de referencias en bloques {}..{}", refcount_idx, dedup_idx);
+    if dedup_blocks > 0 {
+        println!("[x] Índice de deduplicación en bloques {}..{}", dedup_idx, data_start_idx);
+    }
 
     // PASO 4: Escribir el directorio raíz (Datos)
     // El inodo raíz apunta a `root_block`. Debe contener una lista vacía de archivos.
     let empty_dir: Vec<qrfs_lib::types::DirEntry> = Vec::new();
     let dir_bytes = bincode::serialize(&empty_dir)?;
-    let dir_encrypted = crypto.encrypt(&dir_bytes)?;
+    // Los bloques de datos llevan el flag de compresión al inicio
+    let (_, dir_block) = compress::pack_chunk(&dir_bytes, CHUNK_SIZE, sb.compression);
+    let dir_encrypted = crypto.encrypt(&dir_block)?;
     device.write_block(root_block, &dir_encrypted)?;
     println!("[x] Directorio raíz inicializado en bloque {}", root_block);
 
@@ -2,109 +2,387 @@
 
 use fuser::{
     FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
-    ReplyCreate, ReplyWrite, ReplyEmpty, ReplyStatfs, ReplyOpen, Request,
+    ReplyCreate, ReplyWrite, ReplyEmpty, ReplyStatfs, ReplyOpen, ReplyXattr, Request,
     TimeOrNow,
 };
-use libc::{EIO, ENOENT, ENOSPC, ENAMETOOLONG, ENOTDIR, EISDIR, EACCES, ENOTEMPTY};
+use libc::{O_ACCMODE, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, RENAME_EXCHANGE, RENAME_NOREPLACE};
 use std::ffi::OsStr;
-use std::time::{Duration, UNIX_EPOCH, SystemTime};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use zeroize::Zeroizing;
 
 use qrfs_lib::device::BlockDevice;
-use qrfs_lib::crypto::CryptoEngine;
-use qrfs_lib::types::{SuperBlock, Inode, BLOCK_SIZE, DIRECT_POINTERS, DirEntry};
+use qrfs_lib::crypto::{CryptoEngine, VolumeHeader};
+use qrfs_lib::types::{SuperBlock, Inode, BLOCK_SIZE, CHUNK_SIZE, DIRECT_POINTERS, INODES_PER_BLOCK, MAX_FILENAME_LEN, SYMLINK_INLINE_MAX, DirEntry};
+use qrfs_lib::compress;
 use qrfs_lib::bitmap::Bitmap;
+use qrfs_lib::refcount::RefCounts;
+use qrfs_lib::dedup::ChunkIndex;
+use qrfs_lib::perm::{self, Caller, MAY_EXEC, MAY_READ, MAY_WRITE};
+use qrfs_lib::times::AtimePolicy;
+use qrfs_lib::xattr::{self, Xattr, XATTR_INLINE_MAX};
+use qrfs_lib::error::FsError;
+use qrfs_lib::icache::InodeCache;
+use qrfs_lib::journal::{Journal, JournalError, Transaction};
+use qrfs_lib::snapshot::{self, Snapshot, SnapshotError};
+use qrfs_lib::volume;
+use qrfs_lib::acl::{Acl, ACL_ACCESS_XATTR, ACL_DEFAULT_XATTR};
 use qrfs_lib::types::FileType as QrFileType;
 
 const TTL: Duration = Duration::from_secs(1);
 
+// Bloques de la tabla de inodos que se mantienen en RAM (x INODES_PER_BLOCK inodos)
+const INODE_CACHE_BLOCKS: usize = 256;
+
+// Tamaño máximo de un archivo: cada puntero directo guarda CHUNK_SIZE - 1 bytes en crudo
+const MAX_FILE_SIZE: u64 = DIRECT_POINTERS as u64 * (CHUNK_SIZE as u64 - 1);
+
+// Bit set-group-ID: en un directorio, lo nuevo hereda su grupo
+const S_ISGID: u16 = 0o2000;
+
+/// Quién hace la petición. Los grupos suplementarios no vienen en la petición FUSE:
+/// se leen de /proc (si el proceso ya terminó, cuenta solo el grupo principal)
+fn caller(req: &Request) -> Caller {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", req.pid())).unwrap_or_default();
+    Caller { uid: req.uid(), gid: req.gid(), groups: perm::parse_groups(&status) }
+}
+
+/// Respuesta estándar de getxattr/listxattr: con size 0 el kernel solo pregunta el largo
+fn reply_xattr(reply: ReplyXattr, data: &[u8], size: u32) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(FsError::Range.errno());
+    } else {
+        reply.data(data);
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct QRFS {
     device: BlockDevice,
     crypto: CryptoEngine,
     sb: SuperBlock,
     bitmap: Bitmap,
-    inodes: HashMap<u64, Inode>, // Cache en RAM de inodos
+    inode_bitmap: Bitmap,            // Qué inodos están en uso (se asignan desde aquí)
+    refs: RefCounts,                 // Referencias extra de bloques compartidos
+    chunk_index: Option<ChunkIndex>, // Índice de dedup (None si el volumen no deduplica)
+    // Bloques de la tabla de inodos leídos bajo demanda. RefCell porque hasta
+    // las operaciones de solo lectura pueden tener que traer un bloque de disco.
+    inodes: RefCell<InodeCache>,
+    journal: Journal,
+    // Metadatos cambiados por la operación en curso, que todavía no llegaron a
+    // disco (ver `commit`). RefCell por lo mismo que `inodes`.
+    pending: RefCell<Transaction>,
+    snapshots: Vec<Snapshot>,        // Catálogo: lo que hay que preservar al escribir
+    snapshot: Option<Snapshot>,      // Snapshot montado (solo lectura), si no es el volumen vivo
+    atime_policy: AtimePolicy,   // Cuándo una lectura actualiza el atime
+    // ACL de acceso de los inodos con xattrs en un bloque propio, junto con ese
+    // bloque: así check_perm no decodifica el QR en cada chequeo
+    acl_cache: RefCell<HashMap<u64, (u64, Option<Acl>)>>,
+    // Padre de cada directorio que el kernel ya conoce (se aprende en lookup,
+    // mkdir y rename), para el ".." de readdir
+    parents: HashMap<u64, u64>,
 }
 
 impl QRFS {
     // --- INICIALIZACIÓN (Mount) ---
-    pub fn try_mount(device: BlockDevice, password: &str) -> anyhow::Result<Self> {
+    /// Monta el volumen vivo, o con `snapshot` un snapshot en solo lectura
+    pub fn try_mount(device: BlockDevice, password: &str, snapshot: Option<&str>) -> anyhow::Result<Self> {
         // 1. Leer Superbloque
         let block0 = device.read_block(0)?;
-        if block0.len() < 16 { anyhow::bail!("Bloque 0 inválido"); }
-        
-        let (salt, encrypted_sb) = block0.split_at(16);
-        let mut salt_arr = [0u8; 16];
-        salt_arr.copy_from_slice(salt);
+        let (header, encrypted_sb) = VolumeHeader::parse(&block0)?;
 
-        let crypto = CryptoEngine::new(password, salt_arr);
+        let crypto = CryptoEngine::from_header(&header, password);
         let sb_bytes = crypto.decrypt(encrypted_sb).map_err(|_| anyhow::anyhow!("Error de autenticación"))?;
         let sb: SuperBlock = bincode::deserialize(&sb_bytes)?;
 
-        // 2. Leer Bitmap
-        let enc_bitmap = device.read_block(sb.bitmap_start)?;
-        let bitmap_bytes = crypto.decrypt(&enc_bitmap)?;
-        let bitmap: Bitmap = bincode::deserialize(&bitmap_bytes)?;
-
-        // 3. Leer Inodos (Bloque inicial)
-        let enc_inodes = device.read_block(sb.inode_table_start)?;
-        let inodes_bytes = crypto.decrypt(&enc_inodes)?;
-        let inode_list: Vec<Inode> = bincode::deserialize(&inodes_bytes)?;
-        
-        let mut inode_cache = HashMap::new();
-        for (i, inode) in inode_list.iter().enumerate() {
-            if inode.mode != 0 {
-                inode_cache.insert(i as u64, inode.clone());
+        // 1b. Si no se desmontó limpio, rehacer la última transacción del journal
+        // (antes de leer nada más: puede cambiar bitmaps, inodos y directorios)
+        let journal = if sb.journal_needs_recovery {
+            // Un snapshot se monta sin escribir nada, ni siquiera el journal
+            if snapshot.is_some() {
+                anyhow::bail!("El volumen está montado o no se desmontó limpio: ejecute qrfs_fsck antes de montar un snapshot");
+            }
+            let (journal, last) = Journal::recover(&sb, &device, &crypto)?;
+            if let Some(txn) = last {
+                log::warn!("Desmontaje sucio: rehaciendo la transacción {} del journal ({} bloques)", journal.seq(), txn.len());
+                txn.apply(&device, &crypto)?;
             }
+            journal
+        } else {
+            Journal::from_superblock(&sb)
+        };
+
+        // 1c. Snapshots: el catálogo, y el snapshot a montar si se pidió uno
+        let snapshots = snapshot::read_catalog(&device, &crypto, &sb)?;
+        let snapshot = match snapshot {
+            Some(name) => Some(
+                snapshots.iter().find(|s| s.name == name).cloned()
+                    .ok_or_else(|| SnapshotError::NotFound(name.to_string()))?,
+            ),
+            None => None,
+        };
+        let locate = |block: u64| snapshot.as_ref().map_or(block, |s| s.locate(block));
+
+        // 2. Leer Bitmaps (de bloques y de inodos; el de inodos, el del snapshot si es uno)
+        let bitmap = volume::load_bitmap(&device, &crypto, &sb)?;
+        let inode_bitmap = volume::load_inode_bitmap(&device, &crypto, &sb, locate)?;
+        // Los bitmaps mandan: si los contadores no coinciden, se corrigen en el próximo sync
+        // (el bitmap de inodos de un snapshot es otro: ahí no se compara)
+        if snapshot.is_none()
+            && (sb.free_blocks_count != bitmap.free_count() || sb.free_inodes_count != inode_bitmap.free_count())
+        {
+            log::warn!(
+                "Contadores del superbloque desactualizados (¿desmontaje sucio?): {} bloques / {} inodos libres, el bitmap dice {} / {}",
+                sb.free_blocks_count, sb.free_inodes_count, bitmap.free_count(), inode_bitmap.free_count()
+            );
         }
 
-        Ok(Self { device, crypto, sb, bitmap, inodes: inode_cache })
+        // 2b. Leer contadores de referencias e índice de dedup
+        let refs = volume::load_refcounts(&device, &crypto, &sb)?;
+        let chunk_index = (sb.dedup_index_blocks > 0)
+            .then(|| volume::load_chunk_index(&device, &crypto, &sb))
+            .transpose()?;
+
+        // 3. Los inodos se leen bajo demanda: al montar basta con la raíz
+        let fs = Self {
+            device, crypto, sb, bitmap, inode_bitmap, refs, chunk_index,
+            inodes: RefCell::new(InodeCache::new(INODE_CACHE_BLOCKS)),
+            journal,
+            pending: RefCell::new(Transaction::default()),
+            snapshots,
+            snapshot,
+            atime_policy: AtimePolicy::default(),
+            acl_cache: RefCell::new(HashMap::new()),
+            parents: HashMap::new(),
+        };
+        fs.inode(fs.sb.root_dir_inode)
+            .map_err(|e| anyhow::anyhow!("No se pudo leer el inodo raíz: {}", e))?;
+        Ok(fs)
+    }
+
+    /// Cambia la política de atime (por defecto relatime)
+    pub fn set_atime_policy(&mut self, policy: AtimePolicy) {
+        self.atime_policy = policy;
+    }
+
+    /// Bloquea en RAM la clave de cifrado (ver `CryptoEngine::lock_in_memory`)
+    pub fn lock_key_memory(&self) -> std::io::Result<()> {
+        self.crypto.lock_in_memory()
     }
 
     // --- HELPERS INTERNOS DE PERSISTENCIA ---
 
-    /// Guarda el bitmap en disco
-    fn sync_bitmap(&self) -> Result<(), i32> {
-        let bytes = bincode::serialize(&self.bitmap).map_err(|_| EIO)?;
-        let encrypted = self.crypto.encrypt(&bytes).map_err(|_| EIO)?;
-        self.device.write_block(self.sb.bitmap_start, &encrypted).map_err(|_| EIO)?;
+    /// Guarda los contadores de libres en el superbloque, si cambiaron desde la
+    /// última vez (reescribir el bloque 0 es caro: se hace al sincronizar y al desmontar)
+    fn sync_superblock(&mut self) -> Result<(), FsError> {
+        if self.read_only() { return Ok(()); }
+        let (free_blocks, free_inodes) = (self.bitmap.free_count(), self.inode_bitmap.free_count());
+        if self.sb.free_blocks_count == free_blocks && self.sb.free_inodes_count == free_inodes {
+            return Ok(());
+        }
+        self.sb.free_blocks_count = free_blocks;
+        self.sb.free_inodes_count = free_inodes;
+        self.write_superblock()
+    }
+
+    /// Reescribe el bloque 0 (cabecera en claro + superbloque cifrado)
+    fn write_superblock(&self) -> Result<(), FsError> {
+        let sb_bytes = bincode::serialize(&self.sb)?;
+        let mut block0 = self.crypto.header().to_bytes();
+        block0.extend_from_slice(&self.crypto.encrypt(&sb_bytes)?);
+        self.device.write_block(0, &block0)?;
         Ok(())
     }
 
-    /// Guarda un inodo específico en disco
-    fn sync_inode(&self, inode_idx: u64, inode: &Inode) -> Result<(), i32> {
-        // Leemos la lista actual (simulada en caché + defaults)
-        let mut inode_list = vec![Inode::new(QrFileType::File, 0); 5]; 
-        
-        for (k, v) in &self.inodes {
-            if *k < inode_list.len() as u64 {
-                inode_list[*k as usize] = v.clone();
+    /// Guarda las páginas modificadas del bitmap
+    fn sync_bitmap(&mut self) -> Result<(), FsError> {
+        for page in self.bitmap.take_dirty() {
+            let bytes = bincode::serialize(self.bitmap.page(page))?;
+            self.write_meta(self.sb.bitmap_start + page as u64, bytes);
+        }
+        Ok(())
+    }
+
+    /// Guarda las páginas modificadas del bitmap de inodos
+    fn sync_inode_bitmap(&mut self) -> Result<(), FsError> {
+        for page in self.inode_bitmap.take_dirty() {
+            let bytes = bincode::serialize(self.inode_bitmap.page(page))?;
+            self.write_meta(self.sb.inode_bitmap_start + page as u64, bytes);
+        }
+        Ok(())
+    }
+
+    /// Reserva un inodo libre del bitmap de inodos (ENOSPC si la tabla está llena)
+    fn allocate_inode(&mut self) -> Result<u64, FsError> {
+        let inode_idx = self.inode_bitmap.allocate().ok_or(FsError::NoSpace)?;
+        self.sync_inode_bitmap()?;
+        Ok(inode_idx)
+    }
+
+    /// Guarda las páginas modificadas de los contadores de referencias
+    fn sync_refcounts(&mut self) -> Result<(), FsError> {
+        for page in self.refs.take_dirty() {
+            let bytes = bincode::serialize(self.refs.page(page))?;
+            self.write_meta(self.sb.refcount_start + page as u64, bytes);
+        }
+        Ok(())
+    }
+
+    /// Guarda las cubetas modificadas del índice de dedup
+    fn sync_chunk_index(&mut self) -> Result<(), FsError> {
+        let Some(index) = self.chunk_index.as_mut() else { return Ok(()) };
+        for bucket in index.take_dirty() {
+            let bytes = bincode::serialize(index.bucket(bucket))?;
+            self.pending.borrow_mut().write(self.sb.dedup_index_start + bucket as u64, bytes);
+        }
+        Ok(())
+    }
+
+    // --- TRANSACCIONES (journal) ---
+
+    /// Los metadatos no van directo a su bloque: se juntan en la transacción
+    /// de la operación en curso y llegan a disco todos juntos en `commit`
+    fn write_meta(&self, block: u64, bytes: Vec<u8>) {
+        self.pending.borrow_mut().write(block, bytes);
+    }
+
+    /// Contenido en claro de un bloque: el que le dio la transacción en curso si lo
+    /// cambió, si no el de disco (None si el bloque nunca se escribió)
+    fn read_meta(&self, block: u64) -> Result<Option<Zeroizing<Vec<u8>>>, FsError> {
+        if let Some(bytes) = self.pending.borrow().get(block) {
+            return Ok(Some(Zeroizing::new(bytes.to_vec())));
+        }
+        let encrypted = self.device.read_block(block)?;
+        if encrypted.iter().all(|&x| x == 0) { return Ok(None); }
+        Ok(Some(self.crypto.decrypt(&encrypted)?))
+    }
+
+    /// Confirma la transacción en curso: primero entera en el journal, después
+    /// cada bloque en su lugar. Un corte en el medio se arregla al montar
+    /// rehaciéndola desde el journal, así nunca queda aplicada a medias.
+    fn commit(&mut self) -> Result<(), FsError> {
+        if self.pending.borrow().is_empty() { return Ok(()); }
+        if self.read_only() {
+            self.pending.take();
+            return Err(FsError::ReadOnly);
+        }
+        self.preserve_for_snapshots()?;
+        let txn = self.pending.take();
+
+        // Desde la primera transacción, un corte deja trabajo pendiente en el journal
+        if !self.sb.journal_needs_recovery {
+            self.sb.journal_needs_recovery = true;
+            self.write_superblock()?;
+        }
+        match self.journal.commit(&self.device, &self.crypto, &txn) {
+            Ok(()) => {}
+            Err(JournalError::TooLarge(needed, size)) => {
+                log::warn!("Transacción de {} bloques no entra en el journal ({}): se escribe sin protección", needed, size);
+                // Que un corte no rehaga la transacción anterior encima de esta
+                self.journal.commit_barrier(&self.device, &self.crypto)?;
             }
+            Err(e) => return Err(e.into()),
         }
-        if inode_idx < inode_list.len() as u64 {
-            inode_list[inode_idx as usize] = inode.clone();
+        txn.apply(&self.device, &self.crypto)?;
+        Ok(())
+    }
+
+    /// Copy-on-write para los snapshots (ver `snapshot::preserve`): la copia de lo
+    /// que van a pisar, el catálogo actualizado y el bitmap van en la misma transacción
+    fn preserve_for_snapshots(&mut self) -> Result<(), FsError> {
+        if self.snapshots.is_empty() { return Ok(()); }
+        let changed = snapshot::preserve(
+            &self.sb, &mut self.snapshots, &mut self.pending.borrow_mut(),
+            &mut self.bitmap, &mut self.refs, &self.device, &self.crypto,
+        )?;
+        if changed {
+            self.sync_bitmap()?;
+            self.sync_refcounts()?;
+            for (block, bytes) in snapshot::catalog_pages(&self.sb, &self.snapshots)? {
+                self.write_meta(block, bytes);
+            }
         }
+        Ok(())
+    }
 
-        let bytes = bincode::serialize(&inode_list).map_err(|_| EIO)?;
-        let encrypted = self.crypto.encrypt(&bytes).map_err(|_| EIO)?;
-        self.device.write_block(self.sb.inode_table_start, &encrypted).map_err(|_| EIO)?;
-        
+    /// Montado un snapshot, no se escribe nada
+    fn read_only(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    /// Dónde leer un bloque de la tabla de inodos o del bitmap de inodos
+    /// (montado un snapshot, puede estar en la copia que se le hizo)
+    fn locate(&self, block: u64) -> u64 {
+        self.snapshot.as_ref().map_or(block, |s| s.locate(block))
+    }
+
+    /// Cierra una operación: confirma lo que haya cambiado (aunque haya fallado a
+    /// mitad: lo que ya cambió en memoria tiene que llegar a disco igual) y
+    /// devuelve su resultado
+    fn finish<T>(&mut self, result: Result<T, FsError>) -> Result<T, FsError> {
+        let committed = self.commit();
+        let value = result?;
+        committed.map(|_| value)
+    }
+
+    /// Aplica `f` a los inodos de un bloque de la tabla, trayéndolo de disco si
+    /// no está en caché (los bloques que nunca se escribieron son inodos vacíos)
+    fn with_table_block<R>(&self, table_block: u64, f: impl FnOnce(&mut Vec<Inode>) -> R) -> Result<R, FsError> {
+        let mut cache = self.inodes.borrow_mut();
+        if let Some(inodes) = cache.get_mut(table_block) {
+            return Ok(f(inodes));
+        }
+
+        let mut inodes: Vec<Inode> = match self.read_meta(self.locate(self.sb.inode_table_start + table_block))? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => Vec::new(),
+        };
+        inodes.resize(INODES_PER_BLOCK, Inode::new(QrFileType::File, 0));
+        Ok(f(cache.insert(table_block, inodes)))
+    }
+
+    /// Lee un inodo en uso (de la caché o de su bloque de la tabla)
+    fn inode(&self, inode_idx: u64) -> Result<Inode, FsError> {
+        // El bitmap dice si existe sin tener que leer ningún QR
+        if !self.inode_bitmap.get(inode_idx as usize) { return Err(FsError::NotFound); }
+
+        let table_block = inode_idx / INODES_PER_BLOCK as u64;
+        let slot = (inode_idx % INODES_PER_BLOCK as u64) as usize;
+        let inode = self.with_table_block(table_block, |inodes| inodes[slot].clone())?;
+        if inode.mode == 0 { return Err(FsError::NotFound); }
+        Ok(inode)
+    }
+
+    /// Guarda un inodo específico en disco (reescribe su bloque de la tabla)
+    fn sync_inode(&self, inode_idx: u64, inode: &Inode) -> Result<(), FsError> {
+        // Armamos el bloque completo desde la caché (los vecinos se leen si hace falta)
+        let table_block = inode_idx / INODES_PER_BLOCK as u64;
+        let slot = (inode_idx % INODES_PER_BLOCK as u64) as usize;
+        let inode_list = self.with_table_block(table_block, |inodes| {
+            inodes[slot] = inode.clone();
+            inodes.clone()
+        })?;
+
+        self.write_meta(self.sb.inode_block(inode_idx), bincode::serialize(&inode_list)?);
         Ok(())
     }
 
     // --- HELPERS DE LECTURA/ESCRITURA DE DATOS ---
 
     /// Lee y descifra los bloques de datos de un inodo
-    fn read_inode_data(&self, inode: &Inode) -> Result<Vec<u8>, i32> {
+    fn read_inode_data(&self, inode: &Inode) -> Result<Vec<u8>, FsError> {
         let mut data = Vec::new();
         for &block_id in inode.direct_blocks.iter() {
             if block_id == 0 { break; }
             
-            let enc_block = self.device.read_block(block_id).map_err(|_| EIO)?;
-            if enc_block.iter().all(|&x| x == 0) { continue; } // Bloque vacío
-            
-            let plain_block = self.crypto.decrypt(&enc_block).map_err(|_| EIO)?;
-            data.extend_from_slice(&plain_block);
+            let Some(plain_block) = self.read_meta(block_id)? else { continue }; // Bloque vacío
+            let chunk = compress::unpack_chunk(&plain_block)?;
+            data.extend_from_slice(&chunk);
         }
         // Ajustar al tamaño real del archivo
         if data.len() > inode.size as usize {
@@ -113,323 +391,979 @@ impl QRFS {
         Ok(data)
     }
 
-    /// Cifra y escribe datos en un inodo, asignando bloques si es necesario
-    fn write_inode_data(&mut self, inode_idx: u64, new_data: &[u8]) -> Result<(), i32> {
-        let mut inode = self.inodes.get(&inode_idx).ok_or(ENOENT)?.clone();
+    /// Comprime (si el volumen lo pide), cifra y escribe datos en un inodo,
+    /// asignando bloques si es necesario. Con dedup, los fragmentos que ya
+    /// existen en otro bloque se comparten en vez de escribirse otra vez.
+    fn write_inode_data(&mut self, inode_idx: u64, new_data: &[u8]) -> Result<(), FsError> {
+        let mut inode = self.inode(inode_idx)?;
+        // Solo deduplicamos archivos regulares (los directorios cambian en cada operación)
+        let dedup = self.chunk_index.is_some() && inode.file_type == QrFileType::File;
         let mut written = 0;
         let mut block_ptr_idx = 0;
-        const CHUNK_SIZE: usize = 900; 
 
         while written < new_data.len() {
-            if block_ptr_idx >= DIRECT_POINTERS { return Err(ENOSPC); }
+            if block_ptr_idx >= DIRECT_POINTERS { return Err(FsError::FileTooBig); }
+
+            // Compress-then-encrypt: metemos en el bloque todo lo que quepa
+            let (consumed, packed) = compress::pack_chunk(&new_data[written..], CHUNK_SIZE, self.sb.compression);
+            let old_block = inode.direct_blocks[block_ptr_idx];
+
+            let hash = dedup.then(|| self.crypto.chunk_hash(&new_data[written..written + consumed]));
+            let existing = hash.and_then(|h| self.chunk_index.as_ref()?.lookup(&h));
 
-            let mut block_id = inode.direct_blocks[block_ptr_idx];
-            if block_id == 0 {
-                // Asignar nuevo bloque
-                block_id = self.bitmap.allocate().ok_or(ENOSPC)?;
+            if existing.is_some_and(|b| b == old_block) {
+                // El bloque ya tiene exactamente este contenido: nada que reescribir
+            } else if let Some(shared) = existing.filter(|&b| self.refs.add_share(b)) {
+                // Otro bloque ya guarda este contenido: lo compartimos
+                if old_block != 0 { self.release_block(old_block); }
+                inode.direct_blocks[block_ptr_idx] = shared;
+            } else {
+                // Pegado al bloque anterior del archivo, si tiene, para que quede contiguo
+                let goal = block_ptr_idx.checked_sub(1).map(|prev| inode.direct_blocks[prev] + 1);
+                let block_id = self.writable_block(old_block, goal)?;
                 inode.direct_blocks[block_ptr_idx] = block_id;
-                self.sync_bitmap()?;
-            }
 
-            let end = std::cmp::min(written + CHUNK_SIZE, new_data.len());
-            let chunk = &new_data[written..end];
-            
-            let encrypted = self.crypto.encrypt(chunk).map_err(|_| EIO)?;
-            self.device.write_block(block_id, &encrypted).map_err(|_| EIO)?;
+                if inode.file_type == QrFileType::File {
+                    // Los datos de un archivo van directo a disco, antes de confirmar los
+                    // metadatos que los apuntan (como el modo "ordered" de ext3/ext4)
+                    self.pending.borrow_mut().forget(block_id);
+                    self.device.write_block(block_id, &self.crypto.encrypt(&packed)?)?;
+                } else {
+                    // Directorios y destinos de symlinks son metadatos: van en la transacción
+                    self.write_meta(block_id, packed);
+                }
 
-            written += chunk.len();
+                if let (Some(h), Some(index)) = (hash, self.chunk_index.as_mut()) {
+                    index.insert(h, block_id);
+                }
+            }
+
+            written += consumed;
             block_ptr_idx += 1;
         }
 
         // Liberar bloques sobrantes si el archivo se hizo más pequeño
-        for i in block_ptr_idx+1..DIRECT_POINTERS {
+        for i in block_ptr_idx..DIRECT_POINTERS {
             if inode.direct_blocks[i] != 0 {
-                self.bitmap.set(inode.direct_blocks[i] as usize, false);
+                self.release_block(inode.direct_blocks[i]);
                 inode.direct_blocks[i] = 0;
             }
         }
         self.sync_bitmap()?;
+        self.sync_refcounts()?;
+        self.sync_chunk_index()?;
 
         // Actualizar inodo
         inode.size = new_data.len() as u64;
         inode.modified_at = SystemTime::now();
-        self.inodes.insert(inode_idx, inode.clone());
+        inode.changed_at = inode.modified_at;
         self.sync_inode(inode_idx, &inode)?;
 
         Ok(())
     }
 
-    /// Lee entradas de directorio (solo soporta directorio raíz plano por ahora)
-    fn read_dir_entries(&self, inode_idx: u64) -> Result<Vec<DirEntry>, i32> {
-        // En este diseño simple, asumimos que solo el inodo 1 tiene entradas
-        if inode_idx != 1 { return Err(ENOTDIR); }
-        
-        let root_inode = self.inodes.get(&1).ok_or(ENOENT)?;
-        let data = self.read_inode_data(root_inode)?;
+    /// Bloque donde se puede escribir un fragmento sin pisar datos ajenos: el mismo
+    /// si es exclusivo del inodo, uno nuevo si no había o si está compartido (copy-on-write).
+    fn writable_block(&mut self, old_block: u64, goal: Option<u64>) -> Result<u64, FsError> {
+        if old_block != 0 && self.refs.shares(old_block) == 0 {
+            // Se sobrescribe en su lugar: su contenido anterior deja de estar indexado
+            if let Some(index) = self.chunk_index.as_mut() { index.remove_block(old_block); }
+            return Ok(old_block);
+        }
+        let new_block = match goal {
+            Some(goal) => self.bitmap.allocate_near(goal),
+            None => self.bitmap.allocate(),
+        }.ok_or(FsError::NoSpace)?;
+        if old_block != 0 { self.release_block(old_block); }
+        Ok(new_block)
+    }
+
+    /// Suelta una referencia a un bloque de datos; si era la última, lo libera
+    fn release_block(&mut self, block_id: u64) {
+        if self.refs.release(block_id, &mut self.bitmap)
+            && let Some(index) = self.chunk_index.as_mut()
+        {
+            index.remove_block(block_id);
+        }
+    }
+
+    /// Lee las entradas de un directorio
+    fn read_dir_entries(&self, inode_idx: u64) -> Result<Vec<DirEntry>, FsError> {
+        let dir_inode = self.inode(inode_idx)?;
+        if dir_inode.file_type != QrFileType::Directory { return Err(FsError::NotDir); }
+
+        let data = self.read_inode_data(&dir_inode)?;
         if data.is_empty() { return Ok(Vec::new()); }
         
-        bincode::deserialize(&data).map_err(|_| EIO)
+        bincode::deserialize(&data).map_err(FsError::from)
     }
 
-    /// Agrega entrada al directorio raíz
-    fn add_dir_entry(&mut self, name: String, inode_idx: u64) -> Result<(), i32> {
-        let mut entries = self.read_dir_entries(1)?;
-        if entries.iter().any(|e| e.name == name) { return Err(EIO); }
-        
-        entries.push(DirEntry { name, inode_idx });
+    /// Agrega una entrada al directorio `parent`
+    fn add_dir_entry(&mut self, parent: u64, name: &[u8], inode_idx: u64) -> Result<(), FsError> {
+        DirEntry::check_name(name)?;
+        let mut entries = self.read_dir_entries(parent)?;
+        if entries.iter().any(|e| e.name == name) { return Err(FsError::Exists); }
         
-        let new_data = bincode::serialize(&entries).map_err(|_| EIO)?;
-        self.write_inode_data(1, &new_data)
+        entries.push(DirEntry { name: name.to_vec(), inode_idx });
+        self.write_dir_entries(parent, &entries)
     }
 
-    /// Remueve entrada del directorio raíz
-    fn remove_dir_entry(&mut self, name: &str) -> Result<u64, i32> {
-        let mut entries = self.read_dir_entries(1)?;
-        let pos = entries.iter().position(|e| e.name == name).ok_or(ENOENT)?;
+    /// Remueve una entrada del directorio `parent`
+    fn remove_dir_entry(&mut self, parent: u64, name: &[u8]) -> Result<u64, FsError> {
+        let mut entries = self.read_dir_entries(parent)?;
+        let pos = entries.iter().position(|e| e.name == name).ok_or(FsError::NotFound)?;
         let inode_idx = entries[pos].inode_idx;
         
         entries.remove(pos);
-        
-        let new_data = bincode::serialize(&entries).map_err(|_| EIO)?;
-        self.write_inode_data(1, &new_data)?;
+        self.write_dir_entries(parent, &entries)?;
         
         Ok(inode_idx)
     }
 
+    /// Reescribe todas las entradas de un directorio
+    fn write_dir_entries(&mut self, dir: u64, entries: &[DirEntry]) -> Result<(), FsError> {
+        let new_data = bincode::serialize(entries)?;
+        self.write_inode_data(dir, &new_data)
+    }
+
+    /// ¿Está `target` dentro del subárbol del directorio `dir` (o es él mismo)?
+    /// No guardamos el padre de cada inodo, así que se recorre hacia abajo.
+    fn subtree_contains(&self, dir: u64, target: u64) -> Result<bool, FsError> {
+        let mut pending = vec![dir];
+        while let Some(current) = pending.pop() {
+            if current == target { return Ok(true); }
+            for entry in self.read_dir_entries(current)? {
+                if self.is_dir(entry.inode_idx) {
+                    pending.push(entry.inode_idx);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Directorio que contiene a `dir` (la raíz es su propio padre). Si el kernel
+    /// todavía no lo buscó por nombre, se recorre el árbol desde la raíz.
+    fn parent_of(&self, dir: u64) -> Result<u64, FsError> {
+        let root = self.sb.root_dir_inode;
+        if dir == root { return Ok(root); }
+        if let Some(&parent) = self.parents.get(&dir) { return Ok(parent); }
+
+        let mut pending = vec![root];
+        while let Some(current) = pending.pop() {
+            for entry in self.read_dir_entries(current)? {
+                if entry.inode_idx == dir { return Ok(current); }
+                if self.is_dir(entry.inode_idx) {
+                    pending.push(entry.inode_idx);
+                }
+            }
+        }
+        Err(FsError::NotFound)
+    }
+
+    fn is_dir(&self, inode_idx: u64) -> bool {
+        self.inode(inode_idx).is_ok_and(|i| i.file_type == QrFileType::Directory)
+    }
+
+    /// rename(2) completo: reemplaza el destino si existe (liberando su inodo),
+    /// mueve entre directorios y soporta RENAME_NOREPLACE / RENAME_EXCHANGE.
+    /// Dentro de un mismo directorio el cambio es una sola escritura del directorio.
+    fn rename_entry(&mut self, who: &Caller, parent: u64, old_name: &[u8], newparent: u64, new_name: &[u8], flags: u32) -> Result<(), FsError> {
+        let exchange = flags & RENAME_EXCHANGE != 0;
+        let noreplace = flags & RENAME_NOREPLACE != 0;
+        if flags & !(RENAME_EXCHANGE | RENAME_NOREPLACE) != 0 || (exchange && noreplace) {
+            return Err(FsError::InvalidArgument);
+        }
+
+        // 1. Resolver origen y destino, y validar permisos
+        DirEntry::check_name(new_name)?;
+        let moved = self.find_entry(parent, old_name)?;
+        let target = match self.find_entry(newparent, new_name) {
+            Ok(ino) => Some(ino),
+            Err(FsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        self.check_delete(parent, moved, who)?;
+        self.check_perm(newparent, who, MAY_WRITE | MAY_EXEC)?;
+        if let Some(target) = target {
+            self.check_delete(newparent, target, who)?;
+        }
+
+        if noreplace && target.is_some() { return Err(FsError::Exists); }
+        if exchange && target.is_none() { return Err(FsError::NotFound); }
+        // Origen y destino son el mismo archivo: POSIX dice que no se hace nada
+        if target == Some(moved) { return Ok(()); }
+
+        // 2. Un directorio no puede terminar dentro de sí mismo, y moverlo de padre
+        //    reescribe su "..": hace falta permiso de escritura sobre él
+        let moved_is_dir = self.is_dir(moved);
+        if moved_is_dir && parent != newparent {
+            self.check_perm(moved, who, MAY_WRITE)?;
+        }
+        if let Some(target) = target
+            && exchange
+            && self.is_dir(target)
+            && parent != newparent
+        {
+            self.check_perm(target, who, MAY_WRITE)?;
+        }
+        if moved_is_dir && parent != newparent && self.subtree_contains(moved, newparent)? {
+            return Err(FsError::InvalidArgument);
+        }
+        if let Some(target) = target
+            && exchange
+            && self.is_dir(target)
+            && parent != newparent
+            && self.subtree_contains(target, parent)?
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        // 3. Reglas de tipos al reemplazar
+        if let Some(target) = target
+            && !exchange
+        {
+            match (moved_is_dir, self.is_dir(target)) {
+                (true, false) => return Err(FsError::NotDir),
+                (false, true) => return Err(FsError::IsDir),
+                (true, true) if !self.read_dir_entries(target)?.is_empty() => return Err(FsError::NotEmpty),
+                _ => {}
+            }
+        }
+
+        // 4. Reescribir los directorios (primero el destino: si algo falla a mitad,
+        //    el archivo queda con dos nombres en vez de ninguno)
+        let rename_in = |entries: &mut Vec<DirEntry>, from: &[u8], to: &[u8]| {
+            entries.retain(|e| e.name != to);
+            if let Some(e) = entries.iter_mut().find(|e| e.name == from) { e.name = to.to_vec(); }
+        };
+        if parent == newparent {
+            let mut entries = self.read_dir_entries(parent)?;
+            if exchange {
+                for e in entries.iter_mut() {
+                    if e.name == old_name { e.inode_idx = target.unwrap(); }
+                    else if e.name == new_name { e.inode_idx = moved; }
+                }
+            } else {
+                rename_in(&mut entries, old_name, new_name);
+            }
+            self.write_dir_entries(parent, &entries)?;
+        } else {
+            let mut new_entries = self.read_dir_entries(newparent)?;
+            new_entries.retain(|e| e.name != new_name);
+            new_entries.push(DirEntry { name: new_name.to_vec(), inode_idx: moved });
+            self.write_dir_entries(newparent, &new_entries)?;
+
+            let mut old_entries = self.read_dir_entries(parent)?;
+            match target {
+                Some(target) if exchange => {
+                    if let Some(e) = old_entries.iter_mut().find(|e| e.name == old_name) { e.inode_idx = target; }
+                }
+                _ => old_entries.retain(|e| e.name != old_name),
+            }
+            self.write_dir_entries(parent, &old_entries)?;
+        }
+
+        // 5. Contadores de enlaces: el ".." de un directorio movido cambia de padre
+        if parent != newparent {
+            if moved_is_dir {
+                self.parents.insert(moved, newparent);
+                self.adjust_nlink(parent, -1)?;
+                self.adjust_nlink(newparent, 1)?;
+            }
+            if let Some(target) = target
+                && exchange
+                && self.is_dir(target)
+            {
+                self.parents.insert(target, parent);
+                self.adjust_nlink(newparent, -1)?;
+                self.adjust_nlink(parent, 1)?;
+            }
+        }
+
+        // 6. El destino reemplazado pierde su nombre (y su inodo, si era el último)
+        if let Some(target) = target
+            && !exchange
+        {
+            if self.is_dir(target) {
+                self.free_inode_resources(target)?;
+                self.adjust_nlink(newparent, -1)?; // Se fue su ".."
+            } else {
+                self.drop_link(target)?;
+            }
+        }
+
+        self.touch_ctime(moved)?;
+        if let Some(target) = target
+            && exchange
+        {
+            self.touch_ctime(target)?;
+        }
+        Ok(())
+    }
+
     /// Libera recursos de un inodo borrado
-    fn free_inode_resources(&mut self, inode_idx: u64) -> Result<(), i32> {
-        if let Some(mut inode) = self.inodes.get(&inode_idx).cloned() {
+    fn free_inode_resources(&mut self, inode_idx: u64) -> Result<(), FsError> {
+        if let Ok(mut inode) = self.inode(inode_idx) {
             for &block_id in inode.direct_blocks.iter() {
-                if block_id != 0 { self.bitmap.set(block_id as usize, false); }
+                if block_id != 0 { self.release_block(block_id); }
             }
+            if inode.xattr_block != 0 { self.release_block(inode.xattr_block); }
             self.sync_bitmap()?;
+            self.sync_refcounts()?;
+            self.sync_chunk_index()?;
 
             inode.mode = 0; // Marcar como borrado
             inode.size = 0;
             inode.direct_blocks = [0; DIRECT_POINTERS];
-            self.inodes.insert(inode_idx, inode.clone());
+            inode.inline_data.clear();
+            inode.xattrs.clear();
+            inode.xattr_block = 0;
             self.sync_inode(inode_idx, &inode)?;
-            self.inodes.remove(&inode_idx);
+
+            self.inode_bitmap.set(inode_idx as usize, false);
+            self.sync_inode_bitmap()?;
         }
         Ok(())
     }
 
+    // --- ATRIBUTOS EXTENDIDOS ---
+
+    /// Lista de xattrs de un inodo (del propio inodo o de su bloque desbordado)
+    fn load_xattrs(&self, inode: &Inode) -> Result<Vec<Xattr>, FsError> {
+        if inode.xattr_block == 0 {
+            return Ok(inode.xattrs.clone());
+        }
+        let plain_block = self.read_meta(inode.xattr_block)?.ok_or(FsError::Io)?;
+        let bytes = compress::unpack_chunk(&plain_block)?;
+        bincode::deserialize(&bytes).map_err(FsError::from)
+    }
+
+    /// Guarda la lista de xattrs: dentro del inodo si es chica, si no en un bloque propio
+    fn store_xattrs(&mut self, inode_idx: u64, mut inode: Inode, xattrs: Vec<Xattr>) -> Result<(), FsError> {
+        self.acl_cache.get_mut().remove(&inode_idx);
+        let bytes = bincode::serialize(&xattrs)?;
+
+        if bytes.len() <= XATTR_INLINE_MAX {
+            if inode.xattr_block != 0 {
+                self.release_block(inode.xattr_block);
+                inode.xattr_block = 0;
+                self.sync_bitmap()?;
+            }
+            inode.xattrs = xattrs;
+        } else {
+            // `xattr::set` ya garantiza que la lista cabe en un bloque
+            let (used, packed) = compress::pack_chunk(&bytes, CHUNK_SIZE, self.sb.compression);
+            if used < bytes.len() { return Err(FsError::NoSpace); }
+
+            // Si lo comparte con un snapshot, se copia (como los bloques de datos)
+            let block_id = self.writable_block(inode.xattr_block, None)?;
+            self.sync_bitmap()?;
+            self.sync_refcounts()?;
+            self.write_meta(block_id, packed);
+
+            inode.xattr_block = block_id;
+            inode.xattrs.clear();
+        }
+
+        inode.changed_at = SystemTime::now();
+        self.sync_inode(inode_idx, &inode)
+    }
+
+    /// Reglas por espacio de nombres: `user.` exige permisos sobre el archivo
+    /// (y no aplica a symlinks), `trusted.` es solo para root, `security.` lo
+    /// escribe el dueño. El resto (`system.` incluido) no está soportado.
+    fn check_xattr_access(&self, ino: u64, name: &[u8], caller: &Caller, write: bool) -> Result<(), FsError> {
+        let inode = self.inode(ino)?;
+        if name.starts_with(b"user.") {
+            if inode.file_type == QrFileType::Symlink {
+                return Err(if write { FsError::NotPermitted } else { FsError::NoData });
+            }
+            let mask = if write { MAY_WRITE } else { MAY_READ };
+            return self.check_perm(ino, caller, mask);
+        }
+        if name.starts_with(b"trusted.") {
+            return if caller.is_root() { Ok(()) } else { Err(FsError::NotPermitted) };
+        }
+        if name.starts_with(b"security.") {
+            return if !write || caller.owns(&inode) { Ok(()) } else { Err(FsError::NotPermitted) };
+        }
+        if name == ACL_ACCESS_XATTR || name == ACL_DEFAULT_XATTR {
+            // Las ACLs se leen libremente y solo las cambia el dueño
+            if inode.file_type == QrFileType::Symlink { return Err(FsError::NotSupported); }
+            return if !write || caller.owns(&inode) { Ok(()) } else { Err(FsError::NotPermitted) };
+        }
+        Err(FsError::NotSupported)
+    }
+
+    // --- ACLs POSIX (guardadas como xattrs system.posix_acl_*) ---
+
+    /// ACL guardada en el xattr `name` (acceso o default), si la hay
+    fn load_acl(&self, inode: &Inode, name: &[u8]) -> Result<Option<Acl>, FsError> {
+        let xattrs = self.load_xattrs(inode)?;
+        match xattr::get(&xattrs, name) {
+            Some(value) => Acl::from_xattr(value).map(Some).map_err(|_| FsError::Io),
+            None => Ok(None),
+        }
+    }
+
+    /// ACL de acceso para check_perm. Si los xattrs están en un bloque propio se
+    /// guarda en `acl_cache` (vale mientras el inodo siga apuntando a ese bloque)
+    fn access_acl(&self, ino: u64, inode: &Inode) -> Result<Option<Acl>, FsError> {
+        if inode.xattr_block == 0 {
+            return self.load_acl(inode, ACL_ACCESS_XATTR);
+        }
+        if let Some((block, acl)) = self.acl_cache.borrow().get(&ino)
+            && *block == inode.xattr_block
+        {
+            return Ok(acl.clone());
+        }
+        let acl = self.load_acl(inode, ACL_ACCESS_XATTR)?;
+        self.acl_cache.borrow_mut().insert(ino, (inode.xattr_block, acl.clone()));
+        Ok(acl)
+    }
+
+    /// setxattr de una ACL: se valida, y la de acceso además fija los bits del modo.
+    /// Una ACL de acceso mínima no se guarda: los bits del modo ya la representan.
+    fn set_posix_acl(&mut self, ino: u64, name: &[u8], value: &[u8], flags: i32) -> Result<(), FsError> {
+        let acl = Acl::from_xattr(value).map_err(|_| FsError::InvalidArgument)?;
+        let mut inode = self.inode(ino)?;
+        let mut xattrs = self.load_xattrs(&inode)?;
+
+        if name == ACL_ACCESS_XATTR {
+            inode.mode = (inode.mode & !0o777) | acl.mode_bits();
+            if acl.is_minimal() {
+                let _ = xattr::remove(&mut xattrs, name);
+            } else {
+                xattr::set(&mut xattrs, name, value, flags)?;
+            }
+        } else {
+            // Solo los directorios tienen ACL default
+            if inode.file_type != QrFileType::Directory { return Err(FsError::AccessDenied); }
+            xattr::set(&mut xattrs, name, value, flags)?;
+        }
+
+        self.store_xattrs(ino, inode, xattrs)
+    }
+
+    /// chmod sobre un inodo con ACL de acceso: la ACL sigue al modo nuevo
+    fn chmod_acl(&mut self, ino: u64, mode: u16) -> Result<(), FsError> {
+        let inode = self.inode(ino)?;
+        let Some(mut acl) = self.load_acl(&inode, ACL_ACCESS_XATTR)? else { return Ok(()); };
+        let mut xattrs = self.load_xattrs(&inode)?;
+
+        acl.apply_mode(mode);
+        xattr::set(&mut xattrs, ACL_ACCESS_XATTR, &acl.to_xattr(), 0)?;
+        self.store_xattrs(ino, inode, xattrs)
+    }
+
+    /// Lo creado dentro de un directorio con ACL default la hereda: como ACL
+    /// de acceso (recortada por el modo pedido) y, si es un directorio, también como default
+    fn inherit_acls(&mut self, parent: u64, ino: u64) -> Result<(), FsError> {
+        let dir = self.inode(parent)?;
+        let Some(default) = self.load_acl(&dir, ACL_DEFAULT_XATTR)? else { return Ok(()); };
+        let mut inode = self.inode(ino)?;
+
+        let access = default.inherit(inode.mode);
+        inode.mode = (inode.mode & !0o777) | access.mode_bits();
+        let mut xattrs = Vec::new();
+        if !access.is_minimal() {
+            xattr::set(&mut xattrs, ACL_ACCESS_XATTR, &access.to_xattr(), 0)?;
+        }
+        if inode.file_type == QrFileType::Directory {
+            xattr::set(&mut xattrs, ACL_DEFAULT_XATTR, &default.to_xattr(), 0)?;
+        }
+
+        self.store_xattrs(ino, inode, xattrs)
+    }
+
+    // --- PERMISOS ---
+
+    /// FsError::AccessDenied si `caller` no tiene los permisos `mask` sobre el inodo
+    fn check_perm(&self, ino: u64, caller: &Caller, mask: i32) -> Result<(), FsError> {
+        let inode = self.inode(ino)?;
+        // Con ACL de acceso manda la ACL; si no, los bits del modo
+        let allowed = match self.access_acl(ino, &inode)? {
+            Some(acl) => acl.permits(&inode, caller, mask),
+            None => perm::check_access(&inode, caller, mask),
+        };
+        if allowed { Ok(()) } else { Err(FsError::AccessDenied) }
+    }
+
+    /// Para quitar `victim` de `parent` hace falta w+x en el padre y,
+    /// si el padre es "sticky", ser dueño de uno de los dos
+    fn check_delete(&self, parent: u64, victim: u64, caller: &Caller) -> Result<(), FsError> {
+        self.check_perm(parent, caller, MAY_WRITE | MAY_EXEC)?;
+        let dir = self.inode(parent)?;
+        let victim = self.inode(victim)?;
+        if perm::may_delete(&dir, &victim, caller) { Ok(()) } else { Err(FsError::NotPermitted) }
+    }
+
+    /// Reserva un inodo para `inode` y lo enlaza en `parent` (create y mkdir).
+    /// Si no se puede enlazar, el inodo se libera.
+    fn new_entry(&mut self, parent: u64, name: &[u8], inode: Inode) -> Result<(u64, Inode), FsError> {
+        let ino = self.allocate_inode()?;
+        self.sync_inode(ino, &inode)?;
+        self.inherit_acls(parent, ino)?;
+        if let Err(e) = self.add_dir_entry(parent, name, ino) {
+            let _ = self.free_inode_resources(ino);
+            return Err(e);
+        }
+        Ok((ino, self.inode(ino)?))
+    }
+
+    /// Inodo nuevo dentro de `parent`, a nombre de quien lo crea
+    fn new_owned_inode(&self, req: &Request, parent: u64, file_type: QrFileType, mode: u16) -> Inode {
+        let mut inode = Inode::new(file_type, mode);
+        inode.uid = req.uid();
+        inode.gid = req.gid();
+        if let Ok(dir) = self.inode(parent)
+            && dir.mode & S_ISGID != 0
+        {
+            inode.gid = dir.gid;
+            if file_type == QrFileType::Directory { inode.mode |= S_ISGID; }
+        }
+        inode
+    }
+
+    /// Busca el inodo de una entrada del directorio
+    fn find_entry(&self, parent: u64, name: &[u8]) -> Result<u64, FsError> {
+        self.read_dir_entries(parent)?
+            .into_iter()
+            .find(|e| e.name == name)
+            .map(|e| e.inode_idx)
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Suma (o resta) enlaces a un inodo y lo guarda
+    fn adjust_nlink(&mut self, inode_idx: u64, delta: i32) -> Result<Inode, FsError> {
+        let mut inode = self.inode(inode_idx)?;
+        inode.nlink = inode.nlink.checked_add_signed(delta).ok_or(FsError::Io)?;
+        inode.changed_at = SystemTime::now();
+        self.sync_inode(inode_idx, &inode)?;
+        Ok(inode)
+    }
+
+    /// Marca una lectura del inodo, si la política de atime lo pide.
+    /// Es "best effort": un fallo al guardar no debe romper la lectura.
+    fn touch_atime(&mut self, inode_idx: u64) {
+        let now = SystemTime::now();
+        if self.read_only() { return; }
+        if let Ok(mut inode) = self.inode(inode_idx)
+            && self.atime_policy.needs_update(&inode, now)
+        {
+            inode.accessed_at = now;
+            let result = self.sync_inode(inode_idx, &inode);
+            let _ = self.finish(result);
+        }
+    }
+
+    /// Marca un cambio de metadatos (ctime) sin tocar el contenido
+    fn touch_ctime(&mut self, inode_idx: u64) -> Result<(), FsError> {
+        let mut inode = self.inode(inode_idx)?;
+        inode.changed_at = SystemTime::now();
+        self.sync_inode(inode_idx, &inode)
+    }
+
+    /// Quita un enlace a un inodo; con el último, libera sus recursos
+    fn drop_link(&mut self, inode_idx: u64) -> Result<(), FsError> {
+        let nlink = self.inode(inode_idx)?.nlink;
+        if nlink <= 1 {
+            self.free_inode_resources(inode_idx)
+        } else {
+            self.adjust_nlink(inode_idx, -1).map(|_| ())
+        }
+    }
+
+    /// Bloques de datos que ocuparían los archivos sin comprimir vs. los que
+    /// realmente ocupan (QRs ahorrados por la compresión). Solo cuenta los
+    /// inodos en caché: recorrer toda la tabla costaría un QR por bloque.
+    fn compression_stats(&self) -> (u64, u64) {
+        self.inodes.borrow().cached_inodes().fold((0, 0), |(raw, used), inode| {
+            (raw + inode.raw_blocks(), used + inode.used_blocks())
+        })
+    }
+
     /// Convierte Inode a FileAttr de FUSE
     fn get_file_attr(&self, inode_idx: u64, inode: &Inode) -> FileAttr {
         FileAttr {
             ino: inode_idx,
             size: inode.size,
-            blocks: (inode.size + BLOCK_SIZE as u64 - 1) / BLOCK_SIZE as u64,
-            atime: UNIX_EPOCH,
+            blocks: inode.size.div_ceil(BLOCK_SIZE as u64),
+            atime: inode.accessed_at,
             mtime: inode.modified_at,
-            ctime: inode.created_at,
+            ctime: inode.changed_at,
             crtime: inode.created_at,
             kind: match inode.file_type {
                 QrFileType::File => FileType::RegularFile,
                 QrFileType::Directory => FileType::Directory,
+                QrFileType::Symlink => FileType::Symlink,
             },
             perm: inode.mode,
-            nlink: 1,
-            uid: 501, gid: 20, rdev: 0, flags: 0,
+            nlink: inode.nlink,
+            uid: inode.uid, gid: inode.gid, rdev: 0, flags: 0,
             blksize: BLOCK_SIZE as u32,
         }
     }
 }
 
 impl Filesystem for QRFS {
+    // 0. DESTROY: Desmontaje (los contadores de libres quedan en el superbloque)
+    fn destroy(&mut self) {
+        if self.read_only() { return; }
+        // Todo quedó aplicado: el próximo montaje no necesita mirar el journal
+        let result = self.commit().and_then(|_| {
+            self.sb.free_blocks_count = self.bitmap.free_count();
+            self.sb.free_inodes_count = self.inode_bitmap.free_count();
+            self.sb.journal_seq = self.journal.seq();
+            self.sb.journal_head = self.journal.head();
+            self.sb.journal_needs_recovery = false;
+            self.write_superblock()
+        });
+        if let Err(e) = result {
+            log::error!("No se pudo guardar el superbloque al desmontar: {}", e);
+        }
+    }
+
     // 1. LOOKUP: Buscar archivo por nombre
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        if parent != 1 { reply.error(ENOENT); return; } // Solo soportamos nivel 1
-        let name_str = name.to_str().unwrap();
-
-        match self.read_dir_entries(parent) {
-            Ok(entries) => {
-                for entry in entries {
-                    if entry.name == name_str {
-                        if let Some(inode) = self.inodes.get(&entry.inode_idx) {
-                            reply.entry(&TTL, &self.get_file_attr(entry.inode_idx, inode), 0);
-                            return;
-                        }
-                    }
-                }
-                reply.error(ENOENT);
-            },
-            Err(e) => reply.error(e),
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let name = name.as_bytes();
+        if name.len() > MAX_FILENAME_LEN { reply.error(FsError::NameTooLong.errno()); return; }
+        if let Err(e) = self.check_perm(parent, &caller(req), MAY_EXEC) { reply.error(e.errno()); return; }
+
+        let found = self.find_entry(parent, name)
+            .and_then(|ino| self.inode(ino).map(|inode| (ino, inode)));
+        match found {
+            Ok((ino, inode)) => {
+                if inode.file_type == QrFileType::Directory { self.parents.insert(ino, parent); }
+                reply.entry(&TTL, &self.get_file_attr(ino, &inode), 0)
+            }
+            Err(e) => reply.error(e.errno()),
         }
     }
 
     // 2. GETATTR: Obtener metadatos
     fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        if let Some(inode) = self.inodes.get(&ino) {
-            reply.attr(&TTL, &self.get_file_attr(ino, inode));
-        } else {
-            reply.error(ENOENT);
+        match self.inode(ino) {
+            Ok(inode) => reply.attr(&TTL, &self.get_file_attr(ino, &inode)),
+            Err(e) => reply.error(e.errno()),
         }
     }
 
     // 3. SETATTR: Cambiar permisos, tamaño, tiempos (chmod, truncate, touch)
     fn setattr(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
         size: Option<u64>,
-        _atime: Option<TimeOrNow>,
-        _mtime: Option<TimeOrNow>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
         _ctime: Option<SystemTime>,
-        _fh: Option<u64>,
+        fh: Option<u64>,
         _crtime: Option<SystemTime>,
         _chgtime: Option<SystemTime>,
         _bkuptime: Option<SystemTime>,
         _flags: Option<u32>,
         reply: ReplyAttr
     ) {
-        if let Some(mut inode) = self.inodes.get(&ino).cloned() {
+        if let Ok(mut inode) = self.inode(ino) {
+            let who = caller(req);
             if let Some(new_mode) = mode {
+                // chmod: solo el dueño (o root)
+                if !who.owns(&inode) { reply.error(FsError::NotPermitted.errno()); return; }
                 inode.mode = new_mode as u16;
+                // Quien no es del grupo no puede dejar el bit setgid puesto
+                if !who.is_root() && !who.in_group(inode.gid) { inode.mode &= !S_ISGID; }
+            }
+
+            // chown: el dueño solo puede pasarle el archivo a su propio grupo;
+            // cambiar el dueño es cosa de root
+            if let Some(new_uid) = uid
+                && new_uid != inode.uid
+                && !who.is_root()
+            {
+                reply.error(FsError::NotPermitted.errno()); return;
+            }
+            if let Some(new_gid) = gid
+                && new_gid != inode.gid
+                && !(who.is_root() || (who.uid == inode.uid && who.in_group(new_gid)))
+            {
+                reply.error(FsError::NotPermitted.errno()); return;
+            }
+            if uid.is_some() || gid.is_some() {
+                inode.uid = uid.unwrap_or(inode.uid);
+                inode.gid = gid.unwrap_or(inode.gid);
+                // Como en Linux: un chown hecho por no-root apaga setuid/setgid
+                if !who.is_root() { inode.mode &= !0o6000; }
+            }
+
+            // truncate(2) por ruta no pasa por open: el permiso se valida aquí
+            if size.is_some() && fh.is_none()
+                && let Err(e) = self.check_perm(ino, &who, MAY_WRITE)
+            {
+                reply.error(e.errno()); return;
+            }
+            // Sin bloques indirectos, un archivo no pasa de sus punteros directos:
+            // se rechaza antes de reservar nada
+            if size.is_some_and(|new_size| new_size > MAX_FILE_SIZE) {
+                reply.error(FsError::FileTooBig.errno()); return;
             }
             
+            // utimens: fijar una fecha explícita es cosa del dueño;
+            // "ahora" (touch sin -d) también lo puede hacer quien tenga permiso de escritura
+            let now = SystemTime::now();
+            if (atime.is_some() || mtime.is_some()) && !who.owns(&inode) {
+                let explicit = matches!(atime, Some(TimeOrNow::SpecificTime(_)))
+                    || matches!(mtime, Some(TimeOrNow::SpecificTime(_)));
+                if explicit { reply.error(FsError::NotPermitted.errno()); return; }
+                if let Err(e) = self.check_perm(ino, &who, MAY_WRITE) { reply.error(e.errno()); return; }
+            }
+            let resolve = |t: TimeOrNow| match t {
+                TimeOrNow::SpecificTime(t) => t,
+                TimeOrNow::Now => now,
+            };
+            if let Some(t) = atime { inode.accessed_at = resolve(t); }
+            if let Some(t) = mtime { inode.modified_at = resolve(t); }
+            
             if let Some(new_size) = size {
-                // Si cambiamos tamaño, deberíamos truncar o expandir datos
-                // Aquí solo actualizamos metadatos para simplificar, 
-                // pero write_inode_data maneja expansión.
+                // truncate: los datos se recortan (o se rellenan con ceros) de verdad,
+                // así el tamaño y los bloques asignados siempre coinciden
+                if new_size != inode.size {
+                    let resized = self.read_inode_data(&inode).and_then(|mut data| {
+                        data.resize(new_size as usize, 0);
+                        self.write_inode_data(ino, &data)?;
+                        self.inode(ino)
+                    });
+                    match resized {
+                        Ok(updated) => inode.direct_blocks = updated.direct_blocks,
+                        Err(e) => {
+                            let _ = self.finish(Ok(()));
+                            reply.error(e.errno());
+                            return;
+                        }
+                    }
+                }
                 inode.size = new_size;
+                if mtime.is_none() { inode.modified_at = now; }
             }
 
-            inode.modified_at = SystemTime::now();
-            self.inodes.insert(ino, inode.clone());
+            // Cualquier setattr cambia metadatos
+            inode.changed_at = now;
             let _ = self.sync_inode(ino, &inode); // Intentar guardar
+            let result = if mode.is_some() { self.chmod_acl(ino, inode.mode) } else { Ok(()) };
+            if let Err(e) = self.finish(result) { reply.error(e.errno()); return; }
             
             reply.attr(&TTL, &self.get_file_attr(ino, &inode));
         } else {
-            reply.error(ENOENT);
+            reply.error(FsError::NotFound.errno());
         }
     }
 
     // 4. READDIR: Listar contenido
     fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
-        if ino != 1 { reply.error(ENOENT); return; }
-
+        let parent = match self.parent_of(ino) {
+            Ok(parent) => parent,
+            Err(e) => { reply.error(e.errno()); return; }
+        };
         let mut entries_fs = vec![
-            (1, FileType::Directory, ".".to_string()),
-            (1, FileType::Directory, "..".to_string()),
+            (ino, FileType::Directory, b".".to_vec()),
+            (parent, FileType::Directory, b"..".to_vec()),
         ];
-        if let Ok(disk_entries) = self.read_dir_entries(ino) {
-            for entry in disk_entries {
-                let kind = if let Some(node) = self.inodes.get(&entry.inode_idx) {
-                    match node.file_type {
-                        QrFileType::Directory => FileType::Directory,
-                        _ => FileType::RegularFile,
-                    }
-                } else {
-                    FileType::RegularFile
-                };
-                entries_fs.push((entry.inode_idx, kind, entry.name));
-            }
+        let disk_entries = match self.read_dir_entries(ino) {
+            Ok(entries) => entries,
+            Err(e) => { reply.error(e.errno()); return; }
+        };
+        for entry in disk_entries {
+            let kind = if let Ok(node) = self.inode(entry.inode_idx) {
+                match node.file_type {
+                    QrFileType::Directory => FileType::Directory,
+                    QrFileType::Symlink => FileType::Symlink,
+                    _ => FileType::RegularFile,
+                }
+            } else {
+                FileType::RegularFile
+            };
+            entries_fs.push((entry.inode_idx, kind, entry.name));
         }
 
         for (i, entry) in entries_fs.into_iter().enumerate().skip(offset as usize) {
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+            if reply.add(entry.0, (i + 1) as i64, entry.1, OsStr::from_bytes(&entry.2)) {
                 break;
             }
         }
         reply.ok();
+        self.touch_atime(ino);
     }
 
     // 5. CREATE: Crear archivo regular
-    fn create(&mut self, _req: &Request, parent: u64, name: &OsStr, mode: u32, _umask: u32, _flags: i32, reply: ReplyCreate) {
-        if parent != 1 { reply.error(ENOENT); return; }
-        let name_str = name.to_str().unwrap().to_string();
-
-        let mut new_inode_id = 2;
-        while self.inodes.contains_key(&new_inode_id) { new_inode_id += 1; }
-
-        let new_inode = Inode::new(QrFileType::File, mode as u16);
-        self.inodes.insert(new_inode_id, new_inode.clone());
-        
-        if let Err(e) = self.sync_inode(new_inode_id, &new_inode) { reply.error(e); return; }
-        if let Err(e) = self.add_dir_entry(name_str, new_inode_id) { reply.error(e); return; }
-
-        reply.created(&TTL, &self.get_file_attr(new_inode_id, &new_inode), 0, 0, 0);
+    fn create(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, _umask: u32, _flags: i32, reply: ReplyCreate) {
+        let name = name.as_bytes();
+        if let Err(e) = DirEntry::check_name(name) { reply.error(e.errno()); return; }
+        if let Err(e) = self.check_perm(parent, &caller(req), MAY_WRITE | MAY_EXEC) { reply.error(e.errno()); return; }
+
+        let new_inode = self.new_owned_inode(req, parent, QrFileType::File, mode as u16);
+        let result = self.new_entry(parent, name, new_inode);
+        match self.finish(result) {
+            Ok((ino, inode)) => reply.created(&TTL, &self.get_file_attr(ino, &inode), 0, 0, 0),
+            Err(e) => reply.error(e.errno()),
+        }
     }
 
     // 6. MKDIR: Crear directorio (opcional, pero implementado)
-    fn mkdir(&mut self, _req: &Request, parent: u64, name: &OsStr, mode: u32, _umask: u32, reply: ReplyEntry) {
-        if parent != 1 { reply.error(ENOENT); return; } // No soportamos subdirectorios anidados
-        let name_str = name.to_str().unwrap().to_string();
-
-        let mut new_inode_id = 2;
-        while self.inodes.contains_key(&new_inode_id) { new_inode_id += 1; }
+    fn mkdir(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, _umask: u32, reply: ReplyEntry) {
+        let name = name.as_bytes();
+        if let Err(e) = DirEntry::check_name(name) { reply.error(e.errno()); return; }
+        if let Err(e) = self.check_perm(parent, &caller(req), MAY_WRITE | MAY_EXEC) { reply.error(e.errno()); return; }
 
         // Tipo Directorio
-        let new_inode = Inode::new(QrFileType::Directory, mode as u16);
-        self.inodes.insert(new_inode_id, new_inode.clone());
+        let new_inode = self.new_owned_inode(req, parent, QrFileType::Directory, mode as u16);
+        let result = self.new_entry(parent, name, new_inode).and_then(|created| {
+            // El ".." del nuevo directorio apunta al padre
+            self.adjust_nlink(parent, 1)?;
+            Ok(created)
+        });
+        match self.finish(result) {
+            Ok((ino, inode)) => {
+                self.parents.insert(ino, parent);
+                reply.entry(&TTL, &self.get_file_attr(ino, &inode), 0)
+            }
+            Err(e) => reply.error(e.errno()),
+        }
+    }
 
-        if let Err(e) = self.sync_inode(new_inode_id, &new_inode) { reply.error(e); return; }
-        if let Err(e) = self.add_dir_entry(name_str, new_inode_id) { reply.error(e); return; }
+    // 6b. SYMLINK: Crear enlace simbólico
+    fn symlink(&mut self, req: &Request, parent: u64, name: &OsStr, link: &Path, reply: ReplyEntry) {
+        let name = name.as_bytes();
+        if let Err(e) = DirEntry::check_name(name) { reply.error(e.errno()); return; }
+        if let Err(e) = self.check_perm(parent, &caller(req), MAY_WRITE | MAY_EXEC) { reply.error(e.errno()); return; }
+        let target = link.as_os_str().as_bytes();
+        if target.is_empty() { reply.error(FsError::InvalidArgument.errno()); return; }
+        if target.len() > CHUNK_SIZE - 1 { reply.error(FsError::NameTooLong.errno()); return; } // Un solo bloque de datos
+
+        // Los permisos de un symlink no se usan: siempre lrwxrwxrwx
+        let mut new_inode = self.new_owned_inode(req, parent, QrFileType::Symlink, 0o777);
+        let result = self.allocate_inode().and_then(|new_inode_id| {
+            if target.len() <= SYMLINK_INLINE_MAX {
+                // Destino corto: vive dentro del inodo
+                new_inode.inline_data = target.to_vec();
+                new_inode.size = target.len() as u64;
+                self.sync_inode(new_inode_id, &new_inode)?;
+            } else {
+                // Destino largo: se guarda en un bloque de datos como un archivo
+                if let Err(e) = self.sync_inode(new_inode_id, &new_inode)
+                    .and_then(|_| self.write_inode_data(new_inode_id, target))
+                {
+                    let _ = self.free_inode_resources(new_inode_id);
+                    return Err(e);
+                }
+                new_inode = self.inode(new_inode_id)?;
+            }
+            if let Err(e) = self.add_dir_entry(parent, name, new_inode_id) {
+                let _ = self.free_inode_resources(new_inode_id);
+                return Err(e);
+            }
+            Ok(new_inode_id)
+        });
+        match self.finish(result) {
+            Ok(ino) => reply.entry(&TTL, &self.get_file_attr(ino, &new_inode), 0),
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    // 6c. READLINK: Leer destino de un enlace simbólico
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        let inode = match self.inode(ino) { Ok(inode) => inode, Err(e) => { reply.error(e.errno()); return; } };
+        if inode.file_type != QrFileType::Symlink { reply.error(FsError::InvalidArgument.errno()); return; }
 
-        reply.entry(&TTL, &self.get_file_attr(new_inode_id, &new_inode), 0);
+        if !inode.inline_data.is_empty() {
+            reply.data(&inode.inline_data);
+            return;
+        }
+        match self.read_inode_data(&inode) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e.errno()),
+        }
     }
 
     // 7. OPEN: Abrir archivo
-    fn open(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
-        if let Some(inode) = self.inodes.get(&ino) {
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        if let Ok(inode) = self.inode(ino) {
+            let mut mask = match flags & O_ACCMODE {
+                O_RDONLY => MAY_READ,
+                O_WRONLY => MAY_WRITE,
+                O_RDWR => MAY_READ | MAY_WRITE,
+                _ => { reply.error(FsError::InvalidArgument.errno()); return; }
+            };
+            if flags & O_TRUNC != 0 { mask |= MAY_WRITE; }
+
             if inode.file_type == QrFileType::Directory {
-                reply.error(EISDIR);
+                reply.error(FsError::IsDir.errno());
+            } else if let Err(e) = self.check_perm(ino, &caller(req), mask) {
+                reply.error(e.errno());
             } else {
                 reply.opened(0, 0);
             }
         } else {
-            reply.error(ENOENT);
+            reply.error(FsError::NotFound.errno());
         }
     }
 
     // 8. OPENDIR: Abrir directorio
-    fn opendir(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
-        if let Some(inode) = self.inodes.get(&ino) {
-            if inode.file_type == QrFileType::Directory {
-                reply.opened(0, 0);
+    fn opendir(&mut self, req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        if let Ok(inode) = self.inode(ino) {
+            if inode.file_type != QrFileType::Directory {
+                reply.error(FsError::NotDir.errno());
+            } else if let Err(e) = self.check_perm(ino, &caller(req), MAY_READ) {
+                reply.error(e.errno());
             } else {
-                reply.error(ENOTDIR);
+                reply.opened(0, 0);
             }
         } else {
-            reply.error(ENOENT);
+            reply.error(FsError::NotFound.errno());
         }
     }
 
     // 9. READ: Leer datos
     fn read(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, size: u32, _flags: i32, _lock: Option<u64>, reply: ReplyData) {
-        if let Some(inode) = self.inodes.get(&ino) {
-            match self.read_inode_data(inode) {
+        if let Ok(inode) = self.inode(ino) {
+            match self.read_inode_data(&inode) {
                 Ok(data) => {
                     let start = offset as usize;
                     if start >= data.len() { reply.data(&[]); return; }
                     let end = std::cmp::min(start + size as usize, data.len());
                     reply.data(&data[start..end]);
+                    self.touch_atime(ino);
                 },
-                Err(e) => reply.error(e),
+                Err(e) => reply.error(e.errno()),
             }
         } else {
-            reply.error(ENOENT);
+            reply.error(FsError::NotFound.errno());
         }
     }
 
     // 10. WRITE: Escribir datos con soporte de Offset (CORREGIDO)
     fn write(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, data: &[u8], _wf: u32, _fl: i32, _lo: Option<u64>, reply: ReplyWrite) {
         // 1. Obtener el inodo para saber el tamaño actual
-        let inode = match self.inodes.get(&ino) {
-            Some(i) => i.clone(), // Clonamos para no bloquear self
-            None => { reply.error(ENOENT); return; }
+        let inode = match self.inode(ino) {
+            Ok(i) => i,
+            Err(e) => { reply.error(e.errno()); return; }
         };
 
+        // 1b. Como en setattr: lo que no entra en los punteros directos se rechaza
+        // antes de reservar nada
+        let write_end = (offset as usize) + data.len();
+        if write_end as u64 > MAX_FILE_SIZE {
+            reply.error(FsError::FileTooBig.errno()); return;
+        }
+
         // 2. Preparar el buffer final de datos
         let mut final_data = if offset > 0 {
             // Si hay offset, necesitamos recuperar lo que ya estaba escrito
             match self.read_inode_data(&inode) {
                 Ok(existing_data) => existing_data,
-                Err(e) => { reply.error(e); return; }
+                Err(e) => { reply.error(e.errno()); return; }
             }
         } else {
             // Si offset es 0, optimización: asumimos que empezamos de cero 
@@ -438,7 +1372,6 @@ impl Filesystem for QRFS {
         };
 
         // 3. Expandir el buffer si el offset está más allá del final actual (huecos)
-        let write_end = (offset as usize) + data.len();
         if final_data.len() < write_end {
             final_data.resize(write_end, 0);
         }
@@ -448,8 +1381,9 @@ impl Filesystem for QRFS {
         final_data[offset as usize..write_end].copy_from_slice(data);
 
         // 5. Guardar todo el conjunto de nuevo
-        if let Err(e) = self.write_inode_data(ino, &final_data) {
-            reply.error(e);
+        let result = self.write_inode_data(ino, &final_data);
+        if let Err(e) = self.finish(result) {
+            reply.error(e.errno());
         } else {
             // FUSE espera que devolvamos cuánto escribimos en ESTA llamada, no el total
             reply.written(data.len() as u32);
@@ -457,119 +1391,192 @@ impl Filesystem for QRFS {
     }
 
     // 11. UNLINK: Borrar archivo
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        if parent != 1 { reply.error(ENOENT); return; }
-        let name_str = name.to_str().unwrap();
-
-        match self.remove_dir_entry(name_str) {
-            Ok(inode_idx) => {
-                let _ = self.free_inode_resources(inode_idx);
-                reply.ok();
-            },
-            Err(e) => reply.error(e),
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let name = name.as_bytes();
+        let victim = match self.find_entry(parent, name) { Ok(ino) => ino, Err(e) => { reply.error(e.errno()); return; } };
+        if let Err(e) = self.check_delete(parent, victim, &caller(req)) { reply.error(e.errno()); return; }
+        // Los directorios se borran con rmdir
+        if self.is_dir(victim) { reply.error(FsError::IsDir.errno()); return; }
+
+        let result = self.remove_dir_entry(parent, name).and_then(|inode_idx| self.drop_link(inode_idx));
+        match self.finish(result) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    // 11b. LINK: Crear enlace duro
+    fn link(&mut self, req: &Request, ino: u64, newparent: u64, newname: &OsStr, reply: ReplyEntry) {
+        let newname = newname.as_bytes();
+        if let Err(e) = DirEntry::check_name(newname) { reply.error(e.errno()); return; }
+        if let Err(e) = self.check_perm(newparent, &caller(req), MAY_WRITE | MAY_EXEC) { reply.error(e.errno()); return; }
+
+        let inode = match self.inode(ino) { Ok(inode) => inode, Err(e) => { reply.error(e.errno()); return; } };
+        // Enlaces duros a directorios romperían el árbol
+        if inode.file_type == QrFileType::Directory { reply.error(FsError::NotPermitted.errno()); return; }
+        if inode.nlink == u32::MAX { reply.error(FsError::TooManyLinks.errno()); return; }
+
+        let result = self.add_dir_entry(newparent, newname, ino).and_then(|_| self.adjust_nlink(ino, 1));
+        match self.finish(result) {
+            Ok(inode) => reply.entry(&TTL, &self.get_file_attr(ino, &inode), 0),
+            Err(e) => reply.error(e.errno()),
         }
     }
 
     // 12. RMDIR: Borrar directorio
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
-        if parent != 1 { reply.error(ENOENT); return; }
-        let name_str = name.to_str().unwrap();
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let name = name.as_bytes();
         
         // Verificar tipo
-        let mut target_inode = 0;
-        if let Ok(entries) = self.read_dir_entries(parent) {
-            for entry in entries {
-                if entry.name == name_str { target_inode = entry.inode_idx; break; }
-            }
+        let target_inode = match self.find_entry(parent, name) { Ok(ino) => ino, Err(e) => { reply.error(e.errno()); return; } };
+        if let Err(e) = self.check_delete(parent, target_inode, &caller(req)) { reply.error(e.errno()); return; }
+
+        if let Ok(inode) = self.inode(target_inode)
+            && inode.file_type != QrFileType::Directory
+        {
+            reply.error(FsError::NotDir.errno()); return;
+        }
+        // Borrarlo con contenido dejaría inodos huérfanos
+        match self.read_dir_entries(target_inode) {
+            Ok(entries) if !entries.is_empty() => { reply.error(FsError::NotEmpty.errno()); return; }
+            Ok(_) => {}
+            Err(e) => { reply.error(e.errno()); return; }
         }
-        if target_inode == 0 { reply.error(ENOENT); return; }
 
-        if let Some(inode) = self.inodes.get(&target_inode) {
-            if inode.file_type != QrFileType::Directory {
-                reply.error(ENOTDIR); return;
+        let result = self.remove_dir_entry(parent, name).and_then(|inode_idx| {
+            self.parents.remove(&inode_idx);
+            self.free_inode_resources(inode_idx)?;
+            self.adjust_nlink(parent, -1).map(|_| ()) // Se fue su ".."
+        });
+        match self.finish(result) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    // 13. RENAME: Renombrar / mover
+    fn rename(&mut self, req: &Request, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, flags: u32, reply: ReplyEmpty) {
+        let result = self.rename_entry(&caller(req), parent, name.as_bytes(), newparent, newname.as_bytes(), flags);
+        match self.finish(result) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    // 13b. XATTRS: Atributos extendidos
+    fn setxattr(&mut self, req: &Request, ino: u64, name: &OsStr, value: &[u8], flags: i32, _position: u32, reply: ReplyEmpty) {
+        let name = name.as_bytes();
+        if let Err(e) = self.check_xattr_access(ino, name, &caller(req), true) { reply.error(e.errno()); return; }
+
+        if name == ACL_ACCESS_XATTR || name == ACL_DEFAULT_XATTR {
+            let result = self.set_posix_acl(ino, name, value, flags);
+            match self.finish(result) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e.errno()),
             }
-            // Verificar si está vacío (opcional, aquí simplificamos borrado)
+            return;
         }
 
-        match self.remove_dir_entry(name_str) {
-            Ok(inode_idx) => {
-                let _ = self.free_inode_resources(inode_idx);
-                reply.ok();
-            },
-            Err(e) => reply.error(e),
+        let result = self.inode(ino).and_then(|inode| {
+            let mut xattrs = self.load_xattrs(&inode)?;
+            xattr::set(&mut xattrs, name, value, flags)?;
+            self.store_xattrs(ino, inode, xattrs)
+        });
+        match self.finish(result) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno()),
         }
     }
 
-    // 13. RENAME: Renombrar
-    fn rename(&mut self, _req: &Request, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, _flags: u32, reply: ReplyEmpty) {
-        if parent != 1 || newparent != 1 { reply.error(ENOENT); return; }
-        let old_name = name.to_str().unwrap();
-        let new_name = newname.to_str().unwrap().to_string();
+    fn getxattr(&mut self, req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let name = name.as_bytes();
+        if let Err(e) = self.check_xattr_access(ino, name, &caller(req), false) { reply.error(e.errno()); return; }
+        let inode = match self.inode(ino) { Ok(inode) => inode, Err(e) => { reply.error(e.errno()); return; } };
 
-        if let Ok(mut entries) = self.read_dir_entries(parent) {
-            if entries.iter().any(|e| e.name == new_name) { reply.error(EIO); return; }
+        match self.load_xattrs(&inode) {
+            Ok(xattrs) => match xattr::get(&xattrs, name) {
+                Some(value) => reply_xattr(reply, value, size),
+                None => reply.error(FsError::NoData.errno()),
+            },
+            Err(e) => reply.error(e.errno()),
+        }
+    }
 
-            if let Some(pos) = entries.iter().position(|e| e.name == old_name) {
-                entries[pos].name = new_name;
-                
-                let new_data = bincode::serialize(&entries).unwrap();
-                if let Err(e) = self.write_inode_data(1, &new_data) { reply.error(e); } 
-                else { reply.ok(); }
-            } else {
-                reply.error(ENOENT);
+    fn listxattr(&mut self, req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let inode = match self.inode(ino) { Ok(inode) => inode, Err(e) => { reply.error(e.errno()); return; } };
+        match self.load_xattrs(&inode) {
+            Ok(mut xattrs) => {
+                // Los `trusted.` no existen para quien no es root
+                if !caller(req).is_root() {
+                    xattrs.retain(|x| !x.name.starts_with(b"trusted."));
+                }
+                reply_xattr(reply, &xattr::list(&xattrs), size);
             }
-        } else {
-            reply.error(EIO);
+            Err(e) => reply.error(e.errno()),
+        }
+    }
+
+    fn removexattr(&mut self, req: &Request, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        let name = name.as_bytes();
+        if let Err(e) = self.check_xattr_access(ino, name, &caller(req), true) { reply.error(e.errno()); return; }
+
+        let result = self.inode(ino).and_then(|inode| {
+            let mut xattrs = self.load_xattrs(&inode)?;
+            xattr::remove(&mut xattrs, name)?;
+            self.store_xattrs(ino, inode, xattrs)
+        });
+        match self.finish(result) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno()),
         }
     }
 
     // 14. STATFS: Espacio libre
     fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
-        let mut free_blocks = 0;
-        for i in 0..self.sb.total_blocks {
-            if !self.bitmap.get(i as usize) { free_blocks += 1; }
+        let free_blocks = self.bitmap.free_count();
+
+        // statfs no tiene un campo para esto, así que lo dejamos en el log
+        let (raw, used) = self.compression_stats();
+        if used > 0 {
+            log::info!(
+                "statfs: compresión {:?}, {} bloques de datos (sin comprimir serían {}, ratio {:.2}x)",
+                self.sb.compression, used, raw, raw as f64 / used as f64
+            );
         }
         reply.statfs(
             self.sb.total_blocks, free_blocks, free_blocks, 
-            self.sb.total_inodes, self.sb.total_inodes - self.inodes.len() as u64,
-            BLOCK_SIZE as u32, 255, BLOCK_SIZE as u32,
+            self.sb.total_inodes, self.inode_bitmap.free_count(),
+            BLOCK_SIZE as u32, MAX_FILENAME_LEN as u32, BLOCK_SIZE as u32,
         );
     }
 
     // 15. ACCESS: Verificar permisos de acceso a un archivo
     // Se llama antes de open/read/write para verificar derechos.
-    fn access(&mut self, _req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
-        // 1. Verificar si el archivo existe en nuestra estructura descifrada
-        if let Some(inode) = self.inodes.get(&ino) {
-            // Aquí es donde "usamos" la seguridad:
-            // Si podemos leer el inodo de nuestra tabla hash, significa que 
-            // la criptografía (passphrase) fue correcta al montar y tenemos acceso a la estructura.
-            
-            // Nota técnica: 'mask' contiene flags como R_OK (4), W_OK (2), X_OK (1).
-            // En un FS real compararíamos (inode.mode & mask).
-            // Para este proyecto, si el inodo existe y somos el dueño (simulado), damos acceso.
-            
-            // Opcional: Podrías validar si intentan escribir (W_OK) en un archivo de solo lectura,
-            // pero por ahora devolvemos OK para permitir la operación.
-            reply.ok();
-        } else {
-            // Si el inodo no está en memoria, el archivo no existe o está corrupto.
-            reply.error(ENOENT);
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        // F_OK (mask 0) solo pregunta si existe; lo demás es el chequeo POSIX
+        // de dueño/grupo/otros contra inode.mode
+        match self.check_perm(ino, &caller(req), mask) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e.errno()),
         }
     }
 
     // 16. FSYNC: Asegurar que los datos bajen al disco físico
     fn fsync(&mut self, _req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
-        if self.inodes.contains_key(&ino) {
+        if self.inode(ino).is_ok() {
+            // Lo único que se guarda de forma diferida son los contadores del superbloque
+            // (cada operación ya confirmó su transacción antes de responder)
+            if let Err(e) = self.commit().and_then(|_| self.sync_superblock()) { reply.error(e.errno()); return; }
+
             // En QRFS, la escritura es Síncrona.
             // Cuando llamamos a `write`, este llama a `device.write_block`, 
             // el cual genera el PNG y lo guarda en el disco duro inmediatamente.
             
-            // Por lo tanto, no tenemos un "buffer en RAM" pendiente de escribir.
+            // Por lo tanto, no tenemos otro "buffer en RAM" pendiente de escribir.
             // Simplemente le decimos al SO: "Tranquilo, los datos ya están en los QRs".
             reply.ok();
         } else {
-            reply.error(ENOENT);
+            reply.error(FsError::NotFound.errno());
         }
     }
 }
\ No newline at end of file
@@ -2,8 +2,11 @@ use clap::Parser;
 use std::path::PathBuf;
 use std::io::Write;
 use rpassword::read_password;
+use zeroize::Zeroizing;
 use fuser::MountOption;
 use qrfs_lib::device::BlockDevice;
+use qrfs_lib::crypto::VolumeHeader;
+use qrfs_lib::times::AtimePolicy;
 
 mod fs; // Importamos el módulo fs.rs que acabamos de crear
 
@@ -17,6 +20,25 @@ struct Args {
     /// Carpeta donde se montará el FS (disco lógico)
     #[arg(value_name = "MOUNT_POINT")]
     mountpoint: PathBuf,
+
+    /// Permitir que otros usuarios entren al montaje (los permisos de cada
+    /// archivo los valida QRFS). Requiere user_allow_other en /etc/fuse.conf si no es root
+    #[arg(long)]
+    allow_other: bool,
+
+    /// Cuándo actualizar el atime al leer: strictatime, relatime o noatime.
+    /// Cada actualización reescribe un QR de la tabla de inodos
+    #[arg(long, value_name = "POLICY", default_value = "relatime")]
+    atime: AtimePolicy,
+
+    /// Al reescribir un QR, conservar la versión anterior como qr_XXXXX.png.bak
+    /// (qrfs_fsck --repair la usa si el superbloque queda ilegible). Duplica el espacio
+    #[arg(long)]
+    keep_backups: bool,
+
+    /// Montar este snapshot (ver qrfs_snapshot) en vez del volumen vivo, en solo lectura
+    #[arg(long, value_name = "NAME")]
+    snapshot: Option<String>,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -31,30 +53,58 @@ fn main() -> anyhow::Result<()> {
         std::fs::create_dir_all(&args.mountpoint)?;
     }
 
-    // 2. Pedir contraseña
-    print!("Password para montar QRFS: ");
-    std::io::stdout().flush()?;
-    let password = read_password()?;
+    // 2. Inicializar Dispositivo
+    let mut device = BlockDevice::new(&args.source)?;
+    device.set_keep_backups(args.keep_backups);
 
-    // 3. Inicializar Dispositivo
-    let device = BlockDevice::new(&args.source)?;
+    // 3. Pedir contraseña (solo si la cabecera del bloque 0 dice que está cifrado)
+    let block0 = device.read_block(0)?;
+    let (header, _) = VolumeHeader::parse(&block0)?;
+    let password = if header.needs_passphrase() {
+        print!("Password para montar QRFS: ");
+        std::io::stdout().flush()?;
+        Zeroizing::new(read_password()?)
+    } else {
+        println!("Volumen sin cifrar: no se necesita passphrase.");
+        Zeroizing::new(String::new())
+    };
 
     // 4. Intentar montar (Descifrar y cargar en RAM)
     println!("Descifrando sistema de archivos...");
-    let filesystem = fs::QRFS::try_mount(device, &password)?;
+    let mut filesystem = fs::QRFS::try_mount(device, &password, args.snapshot.as_deref())?;
+    drop(password); // La clave ya está derivada, la passphrase sobra
+    filesystem.set_atime_policy(args.atime);
+
+    // Evitar que la clave termine en el swap o en un core dump
+    disable_core_dumps();
+    if let Err(e) = filesystem.lock_key_memory() {
+        eprintln!("Aviso: no se pudo bloquear la clave en RAM (mlock): {}", e);
+    }
 
     // 5. Iniciar FUSE
     println!("Montando en {:?}... (Ctrl+C para desmontar)", args.mountpoint);
     
     // Opciones de montaje estándar
-    let options = vec![
-        MountOption::RW,
+    let mut options = vec![
+        if args.snapshot.is_some() { MountOption::RO } else { MountOption::RW },
         MountOption::FSName("qrfs".to_string()),
         MountOption::AutoUnmount, // Desmontar automáticamente al matar el proceso
     ];
+    if args.allow_other {
+        options.push(MountOption::AllowOther);
+    }
 
     // Esta función bloquea el hilo hasta que se desmonte
     fuser::mount2(filesystem, &args.mountpoint, &options)?;
 
     Ok(())
+}
+
+/// Pone el límite de core dumps en 0 para este proceso (RLIMIT_CORE).
+fn disable_core_dumps() {
+    let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: setrlimit solo lee la estructura que le pasamos.
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        eprintln!("Aviso: no se pudieron desactivar los core dumps");
+    }
 }
\ No newline at end of file
@@ -1,9 +1,14 @@
 use clap::Parser;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use glob::glob;
 use printpdf::*; // Importamos todo lo de printpd
+use rpassword::read_password;
+use zeroize::Zeroizing;
+
+use qrfs_lib::device::BlockDevice;
+use qrfs_lib::volume;
 
 
 #[derive(Parser, Debug)]
@@ -14,6 +19,11 @@ struct Args {
 
     #[arg(value_name = "OUTPUT_PDF", default_value = "backup_fs.pdf")]
     output: PathBuf,
+
+    /// Mostrar cuántos QRs ocupan los datos y el ratio de compresión
+    /// (abre el volumen: pide la passphrase si está cifrado)
+    #[arg(long)]
+    stats: bool,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -23,10 +33,8 @@ fn main() -> anyhow::Result<()> {
     // 1. Buscar archivos
     let pattern = args.path.join("qr_*.png");
     let mut files: Vec<PathBuf> = Vec::new();
-    for entry in glob(pattern.to_str().unwrap())? {
-        if let Ok(path) = entry {
-            files.push(path);
-        }
+    for path in glob(pattern.to_str().unwrap())?.flatten() {
+        files.push(path);
     }
     files.sort();
 
@@ -34,6 +42,11 @@ fn main() -> anyhow::Result<()> {
         anyhow::bail!("No hay imágenes QR.");
     }
 
+    // 1b. Cuánto se ahorra en papel con la compresión
+    if args.stats {
+        print_stats(&args.path)?;
+    }
+
     // 2. Crear Documento (A4)
     let (doc, page1, layer1) = PdfDocument::new("QRFS", Mm(210.0), Mm(297.0), "Layer 1");
     let font = doc.add_builtin_font(BuiltinFont::Helvetica).unwrap();
@@ -80,4 +93,25 @@ fn main() -> anyhow::Result<()> {
 
     println!("¡PDF creado en {:?}!", args.output);
     Ok(())
+}
+
+/// Muestra los QRs de datos del volumen y cuántos serían sin comprimir
+fn print_stats(path: &Path) -> anyhow::Result<()> {
+    let device = BlockDevice::new(path)?;
+    let (crypto, sb) = volume::open(&device, || {
+        print!("Passphrase: ");
+        std::io::stdout().flush()?;
+        Ok(Zeroizing::new(read_password()?))
+    })?;
+    let inodes = volume::load_inode_table(&device, &crypto, &sb, |block| block)?;
+    let (raw, used) = inodes.iter().filter(|inode| inode.mode != 0)
+        .fold((0, 0), |(raw, used), inode| (raw + inode.raw_blocks(), used + inode.used_blocks()));
+
+    println!("Compresión: {:?}", sb.compression);
+    if used > 0 {
+        println!("QRs de datos: {} (sin comprimir serían {}, ratio {:.2}x)", used, raw, raw as f64 / used as f64);
+    } else {
+        println!("QRs de datos: 0");
+    }
+    Ok(())
 }
\ No newline at end of file
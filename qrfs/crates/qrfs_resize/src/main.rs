@@ -1,13 +1,21 @@
 use clap::Parser;
 use std::path::PathBuf;
 use std::io::Write;
+use std::collections::btree_map::Entry;
 use rpassword::read_password;
+use zeroize::Zeroizing;
 use colored::*;
 
 use qrfs_lib::device::BlockDevice;
 use qrfs_lib::crypto::CryptoEngine;
-use qrfs_lib::types::{SuperBlock, QRFS_MAGIC, BLOCK_SIZE};
+use qrfs_lib::types::SuperBlock;
 use qrfs_lib::bitmap::Bitmap;
+use qrfs_lib::dedup::ChunkIndex;
+use qrfs_lib::refcount::RefCounts;
+use qrfs_lib::journal::Transaction;
+use qrfs_lib::snapshot;
+use qrfs_lib::relocate::{Relocation, Tables};
+use qrfs_lib::volume;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -19,6 +27,10 @@ struct Args {
     /// Nueva cantidad total de bloques
     #[arg(long)]
     new_size: u64,
+
+    /// Mostrar qué se haría (cuántos bloques se mudarían al achicar) sin escribir nada
+    #[arg(long)]
+    dry_run: bool,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -27,23 +39,16 @@ fn main() -> anyhow::Result<()> {
 
     // 1. Setup
     let device = BlockDevice::new(&args.path)?;
-    print!("Passphrase: ");
-    std::io::stdout().flush()?;
-    let password = read_password()?;
-
-    // 2. Leer Superbloque
-    let block0 = device.read_block(0)?;
-    if block0.len() < 16 { anyhow::bail!("Disco corrupto"); }
-    let (salt, encrypted_sb) = block0.split_at(16);
-    let mut salt_arr = [0u8; 16];
-    salt_arr.copy_from_slice(salt);
-
-    let crypto = CryptoEngine::new(&password, salt_arr);
-    let sb_bytes = crypto.decrypt(encrypted_sb).map_err(|_| anyhow::anyhow!("Contraseña incorrecta"))?;
-    let mut sb: SuperBlock = bincode::deserialize(&sb_bytes)?;
 
-    if sb.magic != QRFS_MAGIC {
-        anyhow::bail!("No es un volumen QRFS válido");
+    // 2. Leer Superbloque (la cabecera dice si hay que pedir passphrase)
+    let (crypto, mut sb) = volume::open(&device, || {
+        print!("Passphrase: ");
+        std::io::stdout().flush()?;
+        Ok(Zeroizing::new(read_password()?))
+    })?;
+    // El bitmap en disco puede no estar al día hasta que se rehaga el journal
+    if sb.journal_needs_recovery {
+        anyhow::bail!("El volumen no se desmontó limpio: ejecute qrfs_fsck (rehace el journal) antes de redimensionar");
     }
 
     println!("Tamaño actual: {} bloques", sb.total_blocks);
@@ -54,10 +59,23 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    // 3. Leer Bitmap
-    let enc_bitmap = device.read_block(sb.bitmap_start)?;
-    let bitmap_bytes = crypto.decrypt(&enc_bitmap)?;
-    let mut bitmap: Bitmap = bincode::deserialize(&bitmap_bytes)?;
+    // 3. Leer Bitmap (una página por bloque)
+    let mut bitmap = volume::load_bitmap(&device, &crypto, &sb)?;
+
+    // 3b. Al achicar, lo que está en uso más allá del nuevo final se muda antes
+    if args.new_size < sb.total_blocks {
+        let moved = relocate(&device, &crypto, &mut sb, &mut bitmap, args.new_size, args.dry_run)?;
+        if args.dry_run {
+            println!("{}", format!("[DRY-RUN] Se mudarían {} bloques; no se escribió nada.", moved).magenta());
+            return Ok(());
+        }
+        if moved > 0 {
+            println!("{}", format!("[OK] {} bloques mudados por debajo del bloque {}", moved, args.new_size).green());
+        }
+    } else if args.dry_run {
+        println!("{}", format!("[DRY-RUN] Se agregarían {} bloques; no se escribió nada.", args.new_size - sb.total_blocks).magenta());
+        return Ok(());
+    }
 
     // 4. Ejecutar Redimensión Lógica
     // Aquí usamos la función segura que agregamos al bitmap
@@ -78,36 +96,185 @@ fn main() -> anyhow::Result<()> {
     // 6. Actualizar Superbloque
     let old_blocks = sb.total_blocks;
     sb.total_blocks = args.new_size;
-    
-    // Recalcular bloques libres (Aproximación simple: sumar/restar diferencia)
-    if args.new_size > old_blocks {
-        sb.free_blocks_count += args.new_size - old_blocks;
-    } else {
-        sb.free_blocks_count -= old_blocks - args.new_size;
-    }
-
-    // 7. Guardar Cambios (Cifrar y Escribir)
-    // A. Guardar Bitmap
-    let new_bitmap_bytes = bincode::serialize(&bitmap)?;
-    // Validación de seguridad: ¿Cabe el nuevo bitmap en su bloque?
-    // Asumimos bloque único para bitmap por diseño actual del proyecto
-    if new_bitmap_bytes.len() > (BLOCK_SIZE - 28) { 
-        anyhow::bail!("El nuevo tamaño excede la capacidad del bloque de Bitmap. Límite alcanzado.");
-    }
-    let enc_new_bitmap = crypto.encrypt(&new_bitmap_bytes)?;
-    device.write_block(sb.bitmap_start, &enc_new_bitmap)?;
-
-    // B. Guardar Superbloque
-    let new_sb_bytes = bincode::serialize(&sb)?;
-    let enc_new_sb = crypto.encrypt(&new_sb_bytes)?;
-    
-    let mut new_block0 = Vec::new();
-    new_block0.extend_from_slice(&crypto.salt);
-    new_block0.extend_from_slice(&enc_new_sb);
-    device.write_block(0, &new_block0)?;
+
+    // 6b. Si el bitmap, los contadores de referencias o el índice de dedup ya no
+    // alcanzan para el tamaño nuevo, se mudan al comienzo del espacio nuevo (que
+    // está libre) y sus regiones viejas quedan como bloques de datos
+    let needed_pages = Bitmap::pages_for(args.new_size);
+    let relocated = needed_pages > sb.bitmap_blocks;
+    let refcount_pages = RefCounts::pages_for(args.new_size);
+    let grow_refs = refcount_pages > sb.refcount_blocks;
+    let index_buckets = if sb.dedup_index_blocks > 0 { ChunkIndex::buckets_for(args.new_size) } else { 0 };
+    let grow_index = index_buckets > sb.dedup_index_blocks;
+
+    let needed: u64 = [(relocated, needed_pages), (grow_refs, refcount_pages), (grow_index, index_buckets)]
+        .iter()
+        .filter(|(grows, _)| *grows)
+        .map(|(_, blocks)| blocks)
+        .sum();
+    if args.new_size.saturating_sub(old_blocks) < needed {
+        anyhow::bail!("El crecimiento es muy chico: los metadatos que hay que agrandar ocupan {} bloques", needed);
+    }
+    let mut next_block = old_blocks;
+
+    if relocated {
+        move_region(&mut bitmap, (sb.bitmap_start, sb.bitmap_blocks), next_block, needed_pages);
+        println!("Bitmap reubicado en bloques {}..{}", next_block, next_block + needed_pages);
+        sb.bitmap_start = next_block;
+        sb.bitmap_blocks = needed_pages;
+        next_block += needed_pages;
+    }
+
+    // Los contadores se copian tal cual en una tabla más grande (los bloques
+    // nuevos no tienen dueños) y se escriben enteros en su lugar nuevo, sin
+    // journal: nadie lo apunta hasta que se guarde el superbloque
+    if grow_refs {
+        let old_refs = volume::load_refcounts(&device, &crypto, &sb)?;
+        let mut refs = RefCounts::new(refcount_pages as usize);
+        for page in 0..old_refs.page_count() {
+            refs.load_page(page, old_refs.page(page));
+        }
+        for page in 0..refs.page_count() {
+            let page_bytes = bincode::serialize(refs.page(page))?;
+            device.write_block(next_block + page as u64, &crypto.encrypt(&page_bytes)?)?;
+        }
+        move_region(&mut bitmap, (sb.refcount_start, sb.refcount_blocks), next_block, refcount_pages);
+        println!("Contadores de referencias reubicados en bloques {}..{}", next_block, next_block + refcount_pages);
+        sb.refcount_start = next_block;
+        sb.refcount_blocks = refcount_pages;
+        next_block += refcount_pages;
+    }
+
+    // El índice de dedup reparte por hash módulo la cantidad de cubetas: con
+    // más cubetas hay que volver a insertar cada entrada
+    if grow_index {
+        let old_index = volume::load_chunk_index(&device, &crypto, &sb)?;
+        let mut chunk_index = ChunkIndex::new(index_buckets as usize);
+        for entry in old_index.entries() {
+            chunk_index.insert(entry.hash, entry.block);
+        }
+        for bucket in 0..chunk_index.bucket_count() {
+            let bucket_bytes = bincode::serialize(chunk_index.bucket(bucket))?;
+            device.write_block(next_block + bucket as u64, &crypto.encrypt(&bucket_bytes)?)?;
+        }
+        move_region(&mut bitmap, (sb.dedup_index_start, sb.dedup_index_blocks), next_block, index_buckets);
+        println!("Índice de dedup reubicado en bloques {}..{}", next_block, next_block + index_buckets);
+        sb.dedup_index_start = next_block;
+        sb.dedup_index_blocks = index_buckets;
+    }
+
+    // El bitmap lleva la cuenta de lo libre
+    sb.free_blocks_count = bitmap.free_count();
+
+    // 7. Guardar Cambios: las páginas del Bitmap que cambiaron (todas, si se mudó)
+    // y el superbloque van juntos por el journal. El bitmap ya libera las regiones
+    // viejas: escrito sin el superbloque nuevo, las daría como libres mientras
+    // el superbloque viejo las sigue usando.
+    let dirty = bitmap.take_dirty();
+    let pages = if relocated { (0..bitmap.page_count()).collect() } else { dirty };
+    let mut txn = Transaction::default();
+    for page in pages {
+        txn.write(sb.bitmap_start + page as u64, bincode::serialize(bitmap.page(page))?);
+    }
+    commit(&device, &crypto, &mut sb, &txn)?;
 
     println!("{}", "¡Redimensión completada exitosamente!".bold().green());
     println!("Nuevo espacio libre: {} bloques", sb.free_blocks_count);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Marca como ocupada la región nueva de `blocks` bloques que empieza en `start`
+/// y libera la vieja (`old` = (inicio, bloques))
+fn move_region(bitmap: &mut Bitmap, old: (u64, u64), start: u64, blocks: u64) {
+    for block in start..start + blocks {
+        bitmap.set(block as usize, true);
+    }
+    for block in old.0..old.0 + old.1 {
+        bitmap.set(block as usize, false);
+    }
+}
+
+/// Muda cada bloque en uso de [new_size, total_blocks) a un bloque libre por
+/// debajo de `new_size` y reescribe todo lo que lo apunta (ver `Relocation`).
+/// Devuelve cuántos bloques se mudan.
+///
+/// Primero se copian los bloques (a lugares libres: si se corta ahí, solo quedan
+/// bloques perdidos que qrfs_fsck recupera) y después se cambian los punteros en
+/// una sola transacción del journal.
+fn relocate(
+    device: &BlockDevice,
+    crypto: &CryptoEngine,
+    sb: &mut SuperBlock,
+    bitmap: &mut Bitmap,
+    new_size: u64,
+    dry_run: bool,
+) -> anyhow::Result<usize> {
+    // A. Todas las tablas de inodos que hay en disco: la del volumen vivo y las
+    // copias de los snapshots (por ubicación: una copia puede ser de varios)
+    let mut snapshots = snapshot::read_catalog(device, crypto, sb)?;
+    let table = sb.inode_table_start..sb.inode_table_start + sb.inode_table_blocks();
+    let mut tables = Tables::new();
+    let locations = table.clone()
+        .chain(snapshots.iter().flat_map(|snap| snap.moved.iter())
+            .filter(|(original, _)| table.contains(original))
+            .map(|(_, &copy)| copy)
+            .collect::<Vec<_>>());
+    for location in locations {
+        if let Entry::Vacant(slot) = tables.entry(location) {
+            slot.insert(volume::load_table_block(device, crypto, location)?);
+        }
+    }
+
+    // B. Qué se muda y qué se descarta
+    let mut plan = Relocation::plan(sb, &tables, &snapshots, bitmap, new_size)?;
+    if !plan.leaked.is_empty() {
+        println!("    {} {} bloques marcados como ocupados sin dueño se descartan", "[WARN]".yellow(), plan.leaked.len());
+    }
+    println!("Bloques a mudar: {}", plan.movers.len());
+    if dry_run || plan.is_empty() {
+        return Ok(plan.movers.len());
+    }
+
+    // C. Destinos, y copiar el contenido (cifrado tal cual: el cifrado no depende del bloque)
+    plan.assign(bitmap)?;
+    for (&old, &new) in &plan.mapping {
+        device.write_block(new, &device.read_block(old)?)?;
+    }
+
+    // D. Punteros, copias de los snapshots, contadores e índice de dedup
+    let mut refs = volume::load_refcounts(device, crypto, sb)?;
+    let mut chunk_index = volume::load_chunk_index(device, crypto, sb)?;
+    let remapped = plan.apply(&mut tables, &mut snapshots, &mut refs, &mut chunk_index, bitmap);
+
+    let mut txn = Transaction::default();
+    for (location, target) in remapped.tables {
+        txn.write(target, bincode::serialize(&tables[&location])?);
+    }
+    if remapped.catalog {
+        for (block, bytes) in snapshot::catalog_pages(sb, &snapshots)? {
+            txn.write(block, bytes);
+        }
+    }
+    for page in bitmap.take_dirty() {
+        txn.write(sb.bitmap_start + page as u64, bincode::serialize(bitmap.page(page))?);
+    }
+    for page in refs.take_dirty() {
+        txn.write(sb.refcount_start + page as u64, bincode::serialize(refs.page(page))?);
+    }
+    if sb.dedup_index_blocks > 0 {
+        for bucket in chunk_index.take_dirty() {
+            txn.write(sb.dedup_index_start + bucket as u64, bincode::serialize(chunk_index.bucket(bucket))?);
+        }
+    }
+    commit(device, crypto, sb, &txn)?;
+    Ok(plan.movers.len())
+}
+
+/// Aplica un cambio protegido por el journal (ver `volume::commit`)
+fn commit(device: &BlockDevice, crypto: &CryptoEngine, sb: &mut SuperBlock, txn: &Transaction) -> anyhow::Result<()> {
+    if let Some((parts, blocks)) = volume::commit(device, crypto, sb, txn)? {
+        println!("    {} El cambio ocupó {} bloques y el journal tiene {}: se escribió sin journal", "[WARN]".yellow(), parts, blocks);
+    }
+    Ok(())
+}
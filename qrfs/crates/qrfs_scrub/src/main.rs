@@ -0,0 +1,134 @@
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+use std::io::Write;
+use std::process::ExitCode;
+use rpassword::read_password;
+use zeroize::Zeroizing;
+use colored::*;
+
+use qrfs_lib::device::BlockDevice;
+use qrfs_lib::scrub::{self, ScrubStatus};
+use qrfs_lib::volume;
+
+// Códigos de salida (al estilo de fsck)
+const EXIT_CLEAN: u8 = 0;
+const EXIT_PROBLEMS: u8 = 4;
+const EXIT_FAILED: u8 = 8;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    /// Carpeta donde están los QRs
+    #[arg(value_name = "QR_FOLDER")]
+    path: PathBuf,
+
+    /// Cuántas imágenes se decodifican a la vez (por defecto, una por núcleo)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+
+    /// Formato del reporte
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            if args.format == Format::Json {
+                println!("{}", serde_json::json!({ "error": e.to_string() }));
+            } else {
+                eprintln!("{} {}", "[ERROR]".red(), e);
+            }
+            ExitCode::from(EXIT_FAILED)
+        }
+    }
+}
+
+fn run(args: &Args) -> anyhow::Result<u8> {
+    let text = args.format == Format::Text;
+    if text {
+        println!("{}", "=== QRFS Scrub ===".bold().blue());
+    }
+
+    // 1. Abrir el volumen
+    if !args.path.exists() {
+        anyhow::bail!("La carpeta no existe");
+    }
+    let device = BlockDevice::new(&args.path)?;
+    // Con --format json la salida estándar es solo el reporte: el pedido va a stderr
+    let (crypto, sb) = volume::open(&device, || {
+        if text {
+            print!("Passphrase: ");
+            std::io::stdout().flush()?;
+        } else {
+            eprint!("Passphrase: ");
+        }
+        Ok(Zeroizing::new(read_password()?))
+    })?;
+    if sb.journal_needs_recovery && text {
+        println!("{} El volumen está montado o no se desmontó limpio: puede haber bloques a medio escribir", "[WARN]".yellow());
+    }
+
+    // 2. De quién es cada bloque y cuáles revisar. Si el bitmap no se puede leer,
+    // se revisan todas las imágenes y todos los bloques con dueño conocido.
+    let owners = scrub::block_owners(&device, &crypto, &sb);
+    let blocks = match volume::load_bitmap(&device, &crypto, &sb) {
+        Ok(bitmap) => scrub::allocated_blocks(&bitmap, sb.total_blocks),
+        Err(e) => {
+            if text {
+                println!("{} No se pudo leer el bitmap ({}): se revisan todas las imágenes", "[WARN]".yellow(), e);
+            }
+            (0..sb.total_blocks).filter(|&block| owners.contains_key(&block) || device.has_block(block)).collect()
+        }
+    };
+    let jobs = args.jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+        .max(1);
+    if text {
+        println!("Revisando {} bloques con {} hilos...", blocks.len(), jobs);
+    }
+
+    // 3. Leer, decodificar y descifrar todo
+    let findings = scrub::scrub(&device, &crypto, &sb, &blocks, &owners, jobs);
+    let counts = scrub::summarize(&findings);
+
+    // 4. Reporte
+    if text {
+        for finding in &findings {
+            println!(
+                "  {} bloque {} ({}): {}",
+                format!("[{}]", finding.status.label()).red(), finding.block, finding.owner, finding.detail
+            );
+        }
+        println!("\n{}", "--- Resumen ---".bold());
+        println!("Bloques revisados: {}", blocks.len());
+        for status in ScrubStatus::ALL {
+            println!("  {:<10} {}", status.label(), counts[&status]);
+        }
+        if findings.is_empty() {
+            println!("{}", "\n[OK] Todas las imágenes se leen y autentican".green().bold());
+        } else {
+            println!("{}", format!("\n[PRECAUCIÓN] {} imágenes con problemas", findings.len()).red().bold());
+        }
+    } else {
+        let summary = serde_json::json!({
+            "blocks_checked": blocks.len(),
+            "missing": counts[&ScrubStatus::Missing],
+            "unreadable": counts[&ScrubStatus::Unreadable],
+            "corrupt": counts[&ScrubStatus::Corrupt],
+            "foreign": counts[&ScrubStatus::Foreign],
+            "findings": findings,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    }
+
+    Ok(if findings.is_empty() { EXIT_CLEAN } else { EXIT_PROBLEMS })
+}
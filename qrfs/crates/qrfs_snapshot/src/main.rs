@@ -0,0 +1,192 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use rpassword::read_password;
+use zeroize::Zeroizing;
+use colored::*;
+
+use qrfs_lib::device::BlockDevice;
+use qrfs_lib::crypto::CryptoEngine;
+use qrfs_lib::types::{SuperBlock, Inode};
+use qrfs_lib::journal::Transaction;
+use qrfs_lib::snapshot::{self, Snapshot, SnapshotError, MAX_SNAPSHOTS};
+use qrfs_lib::volume;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Args {
+    /// Carpeta donde están los QRs
+    #[arg(value_name = "QR_FOLDER")]
+    path: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Foto del volumen entero tal como está ahora (con el volumen desmontado)
+    Create { name: String },
+    /// Lista los snapshots del volumen
+    List,
+    /// Borra un snapshot y libera los bloques que solo él usaba
+    Delete { name: String },
+    /// Monta un snapshot en solo lectura (con qrfs_mount --snapshot)
+    Mount {
+        name: String,
+        #[arg(value_name = "MOUNT_POINT")]
+        mountpoint: PathBuf,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    // Montar es cosa de qrfs_mount (que pide la passphrase por su cuenta)
+    if let Command::Mount { name, mountpoint } = &args.command {
+        let err = std::process::Command::new(qrfs_mount_path())
+            .arg("--snapshot").arg(name)
+            .arg(&args.path).arg(mountpoint)
+            .exec();
+        anyhow::bail!("No se pudo ejecutar qrfs_mount: {}", err);
+    }
+
+    println!("{}", "=== QRFS Snapshots ===".bold().blue());
+
+    // 1. Abrir el volumen
+    if !args.path.exists() {
+        anyhow::bail!("La carpeta no existe");
+    }
+    let device = BlockDevice::new(&args.path)?;
+    let (crypto, mut sb) = volume::open(&device, || {
+        print!("Passphrase: ");
+        std::io::stdout().flush()?;
+        Ok(Zeroizing::new(read_password()?))
+    })?;
+    let mut snapshots = snapshot::read_catalog(&device, &crypto, &sb)?;
+
+    // 2. Crear o borrar cambia los contadores de referencias: no puede haber
+    // un qrfs_mount escribiendo a la vez, ni una transacción sin rehacer
+    if !matches!(args.command, Command::List) && sb.journal_needs_recovery {
+        anyhow::bail!("El volumen está montado o no se desmontó limpio: desmóntelo (o ejecute qrfs_fsck) antes de cambiar sus snapshots");
+    }
+
+    match args.command {
+        Command::List => {
+            if snapshots.is_empty() {
+                println!("El volumen no tiene snapshots");
+            }
+            for snap in &snapshots {
+                let created: chrono::DateTime<chrono::Local> = snap.created_at.into();
+                println!(
+                    "  {:<24} {}  ({} bloques de metadatos copiados)",
+                    snap.name, created.format("%Y-%m-%d %H:%M:%S"), snap.moved.len()
+                );
+            }
+            println!("{} de {} snapshots", snapshots.len(), MAX_SNAPSHOTS);
+        }
+
+        Command::Create { name } => {
+            if snapshots.iter().any(|s| s.name == name) {
+                return Err(SnapshotError::Exists(name).into());
+            }
+            if snapshots.len() >= MAX_SNAPSHOTS {
+                return Err(SnapshotError::TooMany.into());
+            }
+            let snap = Snapshot::new(&name, &sb)?;
+
+            // A. El snapshot es un dueño más de cada bloque que hoy usa el volumen
+            let mut refs = volume::load_refcounts(&device, &crypto, &sb)?;
+            let inodes = volume::load_inode_table(&device, &crypto, &sb, |block| block)?;
+            let mut shared = 0;
+            for block in inodes.iter().flat_map(owned_blocks) {
+                if !refs.add_share(block) {
+                    anyhow::bail!("El bloque {} no admite más referencias", block);
+                }
+                shared += 1;
+            }
+            snapshots.push(snap);
+
+            // B. Contadores y catálogo, en una sola transacción
+            let mut txn = Transaction::default();
+            for page in refs.take_dirty() {
+                txn.write(sb.refcount_start + page as u64, bincode::serialize(refs.page(page))?);
+            }
+            for (block, bytes) in snapshot::catalog_pages(&sb, &snapshots)? {
+                txn.write(block, bytes);
+            }
+            commit(&device, &crypto, &mut sb, &txn)?;
+            println!("{}", format!("[OK] Snapshot '{}' creado ({} bloques compartidos)", name, shared).green());
+        }
+
+        Command::Delete { name } => {
+            let pos = snapshots.iter().position(|s| s.name == name)
+                .ok_or_else(|| SnapshotError::NotFound(name.clone()))?;
+            let snap = snapshots.remove(pos);
+
+            // A. Soltar cada referencia del snapshot: a sus bloques de datos y a las
+            // copias de metadatos que se le hicieron. Lo que nadie más usa queda libre.
+            let mut bitmap = volume::load_bitmap(&device, &crypto, &sb)?;
+            let mut refs = volume::load_refcounts(&device, &crypto, &sb)?;
+            let mut chunk_index = volume::load_chunk_index(&device, &crypto, &sb)?;
+            let inodes = volume::load_inode_table(&device, &crypto, &sb, |block| snap.locate(block))?;
+            let mut freed = 0;
+            for block in inodes.iter().flat_map(owned_blocks).chain(snap.moved.values().copied()) {
+                if refs.release(block, &mut bitmap) {
+                    chunk_index.remove_block(block);
+                    freed += 1;
+                }
+            }
+
+            // B. Bitmap, contadores, índice de dedup y catálogo, en una sola transacción
+            let mut txn = Transaction::default();
+            for page in bitmap.take_dirty() {
+                txn.write(sb.bitmap_start + page as u64, bincode::serialize(bitmap.page(page))?);
+            }
+            for page in refs.take_dirty() {
+                txn.write(sb.refcount_start + page as u64, bincode::serialize(refs.page(page))?);
+            }
+            if sb.dedup_index_blocks > 0 {
+                for bucket in chunk_index.take_dirty() {
+                    txn.write(sb.dedup_index_start + bucket as u64, bincode::serialize(chunk_index.bucket(bucket))?);
+                }
+            }
+            for (block, bytes) in snapshot::catalog_pages(&sb, &snapshots)? {
+                txn.write(block, bytes);
+            }
+            sb.free_blocks_count = bitmap.free_count();
+            commit(&device, &crypto, &mut sb, &txn)?;
+            println!("{}", format!("[OK] Snapshot '{}' borrado ({} bloques liberados)", name, freed).green());
+        }
+
+        Command::Mount { .. } => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// qrfs_mount de la misma instalación (al lado de este ejecutable), o el del PATH
+fn qrfs_mount_path() -> PathBuf {
+    std::env::current_exe().ok()
+        .map(|exe| exe.with_file_name("qrfs_mount"))
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from("qrfs_mount"))
+}
+
+/// Bloques de los que un inodo es dueño (datos y xattrs desbordados)
+fn owned_blocks(inode: &Inode) -> Vec<u64> {
+    if inode.mode == 0 { return Vec::new(); }
+    inode.direct_blocks.iter().copied()
+        .chain(std::iter::once(inode.xattr_block))
+        .filter(|&block| block != 0)
+        .collect()
+}
+
+/// Aplica un cambio protegido por el journal (ver `volume::commit`)
+fn commit(device: &BlockDevice, crypto: &CryptoEngine, sb: &mut SuperBlock, txn: &Transaction) -> anyhow::Result<()> {
+    if let Some((parts, blocks)) = volume::commit(device, crypto, sb, txn)? {
+        println!("    {} El cambio ocupó {} bloques y el journal tiene {}: se escribió sin journal", "[WARN]".yellow(), parts, blocks);
+    }
+    Ok(())
+}